@@ -80,6 +80,9 @@ export const EventKind = {
   FOCUS: "FOCUS",
   BLUR: "BLUR",
   EXAM_EXIT: "EXAM_EXIT",
+  COPY_PASTE: "COPY_PASTE",
+  FULLSCREEN_EXIT: "FULLSCREEN_EXIT",
+  NETWORK_LOSS: "NETWORK_LOSS",
 } as const;
 
 type Meta = Record<string, unknown>;
@@ -0,0 +1,568 @@
+//! Item analysis of exam questions, computed over many attempts.
+use std::collections::HashMap;
+
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+
+use crate::{config::Attempt, database::prisma, scoring};
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemAnalysis {
+    pub exam_id: ObjectId,
+    pub number_of_attempts: usize,
+    /// In exam order
+    pub questions: Vec<QuestionAnalysis>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuestionAnalysis {
+    pub question_id: ObjectId,
+    pub question_set_id: ObjectId,
+    pub text: String,
+    pub tags: Vec<String>,
+    pub deprecated: bool,
+    /// Number of attempts the question was generated for
+    pub presented: usize,
+    pub answered: usize,
+    pub correct: usize,
+    /// Fraction of attempts presented the question which answered it correctly (p-value).
+    ///
+    /// `None` if never presented.
+    pub difficulty: Option<f64>,
+    /// Point-biserial correlation between answering the question correctly, and the number of
+    /// other questions answered correctly.
+    ///
+    /// `None` if there is no variance in either.
+    pub discrimination: Option<f64>,
+    /// Mean time between the previous submission (or the start of the attempt), and the question's submission
+    pub mean_time_to_answer_ms: Option<f64>,
+    pub distractors: Vec<DistractorAnalysis>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DistractorAnalysis {
+    pub answer_id: ObjectId,
+    pub text: String,
+    /// Number of attempts the incorrect answer was generated for
+    pub shown: usize,
+    pub selected: usize,
+    /// `selected / shown`, or `None` if never shown
+    pub selection_rate: Option<f64>,
+}
+
+#[derive(Default)]
+struct QuestionTally {
+    presented: usize,
+    answered: usize,
+    correct: usize,
+    time_to_answer_ms_total: f64,
+    timed: usize,
+    /// Answer id to (shown, selected)
+    distractors: HashMap<ObjectId, (usize, usize)>,
+    /// Correctness, and rest score, of each attempt presented the question
+    discrimination: Correlation,
+}
+
+/// Running sums of `(x, y)` pairs, for the Pearson correlation coefficient
+#[derive(Clone, Copy, Debug, Default)]
+struct Correlation {
+    n: f64,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_yy: f64,
+    sum_xy: f64,
+}
+
+impl Correlation {
+    fn add(&mut self, x: f64, y: f64) {
+        self.n += 1.0;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_yy += y * y;
+        self.sum_xy += x * y;
+    }
+
+    /// Pearson correlation coefficient, or `None` if there are fewer than 2 pairs, or either
+    /// variable has no variance
+    fn coefficient(&self) -> Option<f64> {
+        if self.n < 2.0 {
+            return None;
+        }
+
+        let covariance = self.sum_xy - self.sum_x * self.sum_y / self.n;
+        let variance_x = self.sum_xx - self.sum_x * self.sum_x / self.n;
+        let variance_y = self.sum_yy - self.sum_y * self.sum_y / self.n;
+        if variance_x <= 0.0 || variance_y <= 0.0 {
+            return None;
+        }
+
+        Some(covariance / (variance_x * variance_y).sqrt())
+    }
+}
+
+/// Accumulates attempts, so attempts can be streamed instead of held in memory. Only running
+/// totals are kept for each question.
+pub struct ItemAnalysisBuilder<'a> {
+    exam: &'a prisma::ExamEnvironmentExam,
+    /// Question id to index in `tallies`
+    question_indices: HashMap<ObjectId, usize>,
+    tallies: Vec<QuestionTally>,
+    number_of_attempts: usize,
+}
+
+impl<'a> ItemAnalysisBuilder<'a> {
+    pub fn new(exam: &'a prisma::ExamEnvironmentExam) -> Self {
+        let question_indices: HashMap<ObjectId, usize> = exam
+            .question_sets
+            .iter()
+            .flat_map(|qs| qs.questions.iter())
+            .enumerate()
+            .map(|(i, q)| (q.id, i))
+            .collect();
+        let tallies = (0..question_indices.len())
+            .map(|_| QuestionTally::default())
+            .collect();
+
+        Self {
+            exam,
+            question_indices,
+            tallies,
+            number_of_attempts: 0,
+        }
+    }
+
+    /// Adds an attempt of the exam
+    pub fn add(&mut self, attempt: &Attempt) {
+        let score = scoring::score_attempt(attempt);
+        let correct_by_id: HashMap<ObjectId, bool> = score
+            .questions
+            .iter()
+            .map(|q| (q.question_id, q.correct))
+            .collect();
+
+        let mut submission_times: Vec<i64> = attempt
+            .question_sets
+            .iter()
+            .flat_map(|qs| qs.questions.iter())
+            .filter_map(|q| q.submission_time)
+            .map(|t| t.timestamp_millis())
+            .collect();
+        submission_times.sort_unstable();
+
+        let mut results = vec![];
+        for question in attempt
+            .question_sets
+            .iter()
+            .flat_map(|qs| qs.questions.iter())
+        {
+            let (Some(index), Some(correct)) = (
+                self.question_indices.get(&question.id),
+                correct_by_id.get(&question.id),
+            ) else {
+                continue;
+            };
+            let tally = &mut self.tallies[*index];
+
+            tally.presented += 1;
+            if *correct {
+                tally.correct += 1;
+            }
+            if let Some(submission_time) = question.submission_time {
+                tally.answered += 1;
+                let submission_time = submission_time.timestamp_millis();
+                let previous = submission_times
+                    .iter()
+                    .rev()
+                    .find(|t| **t < submission_time)
+                    .copied()
+                    .unwrap_or(attempt.start_time.timestamp_millis());
+                tally.time_to_answer_ms_total += (submission_time - previous) as f64;
+                tally.timed += 1;
+            }
+            for answer in question.answers.iter().filter(|a| !a.is_correct) {
+                if !question.generated.contains(&answer.id) {
+                    continue;
+                }
+                let (shown, selected) = tally.distractors.entry(answer.id).or_default();
+                *shown += 1;
+                if question.selected.contains(&answer.id) {
+                    *selected += 1;
+                }
+            }
+
+            results.push((*index, *correct));
+        }
+
+        // Correlate each question with the number of other questions answered correctly
+        let total = results.iter().filter(|(_, correct)| *correct).count() as f64;
+        for (index, correct) in results {
+            let x = if correct { 1.0 } else { 0.0 };
+            self.tallies[index].discrimination.add(x, total - x);
+        }
+        self.number_of_attempts += 1;
+    }
+
+    pub fn build(self) -> ItemAnalysis {
+        let questions = self
+            .exam
+            .question_sets
+            .iter()
+            .flat_map(|qs| qs.questions.iter().map(move |q| (qs, q)))
+            .zip(self.tallies)
+            .map(|((question_set, question), tally)| {
+                let distractors = question
+                    .answers
+                    .iter()
+                    .filter(|a| !a.is_correct)
+                    .map(|answer| {
+                        let (shown, selected) =
+                            tally.distractors.get(&answer.id).copied().unwrap_or((0, 0));
+                        DistractorAnalysis {
+                            answer_id: answer.id,
+                            text: answer.text.clone(),
+                            shown,
+                            selected,
+                            selection_rate: ratio(selected, shown),
+                        }
+                    })
+                    .collect();
+
+                QuestionAnalysis {
+                    question_id: question.id,
+                    question_set_id: question_set.id,
+                    text: question.text.clone(),
+                    tags: question.tags.clone(),
+                    deprecated: question.deprecated,
+                    presented: tally.presented,
+                    answered: tally.answered,
+                    correct: tally.correct,
+                    difficulty: ratio(tally.correct, tally.presented),
+                    discrimination: tally.discrimination.coefficient(),
+                    mean_time_to_answer_ms: (tally.timed > 0)
+                        .then(|| tally.time_to_answer_ms_total / tally.timed as f64),
+                    distractors,
+                }
+            })
+            .collect();
+
+        ItemAnalysis {
+            exam_id: self.exam.id,
+            number_of_attempts: self.number_of_attempts,
+            questions,
+        }
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    (denominator > 0).then(|| numerator as f64 / denominator as f64)
+}
+
+/// Width of each score histogram bin, in percent
+const HISTOGRAM_BIN_WIDTH: f64 = 10.0;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamPsychometrics {
+    pub exam_id: ObjectId,
+    pub passing_percent: f64,
+    /// Over all attempts.
+    ///
+    /// Generations present different questions, so `reliability` is the mean of the generations'
+    /// reliabilities, weighted by their number of attempts.
+    pub overall: ScoreStatistics,
+    pub generations: Vec<GenerationPsychometrics>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationPsychometrics {
+    pub generation_id: ObjectId,
+    #[serde(flatten)]
+    pub statistics: ScoreStatistics,
+}
+
+/// Statistics of attempt percents
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreStatistics {
+    pub number_of_attempts: usize,
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    /// Population standard deviation
+    pub standard_deviation: Option<f64>,
+    /// Fraction of attempts which passed
+    pub pass_rate: Option<f64>,
+    pub histogram: Vec<HistogramBin>,
+    /// Kuder-Richardson Formula 20 (Cronbach's alpha for dichotomous items)
+    ///
+    /// `None` if there are fewer than 2 attempts or questions, or no variance in scores.
+    pub reliability: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramBin {
+    /// Inclusive
+    pub from_percent: f64,
+    /// Exclusive, except for the last bin
+    pub to_percent: f64,
+    pub count: usize,
+}
+
+struct ScoredResult {
+    percent: f64,
+    passed: bool,
+    /// Question id and correctness of each presented question
+    questions: Vec<(ObjectId, bool)>,
+}
+
+/// Accumulates attempts, so attempts can be streamed. The percent, and question correctness, of
+/// each attempt are kept, for the median and KR-20.
+pub struct PsychometricsBuilder<'a> {
+    exam: &'a prisma::ExamEnvironmentExam,
+    /// Generation id to results, in order of first attempt
+    generations: Vec<(ObjectId, Vec<ScoredResult>)>,
+}
+
+impl<'a> PsychometricsBuilder<'a> {
+    pub fn new(exam: &'a prisma::ExamEnvironmentExam) -> Self {
+        Self {
+            exam,
+            generations: vec![],
+        }
+    }
+
+    /// Adds an attempt of the exam, generated from `generation_id`
+    pub fn add(&mut self, generation_id: ObjectId, attempt: &Attempt) {
+        let score = scoring::score_attempt(attempt);
+        let result = ScoredResult {
+            percent: score.percent,
+            passed: score.passed,
+            questions: score
+                .questions
+                .iter()
+                .map(|q| (q.question_id, q.correct))
+                .collect(),
+        };
+
+        match self
+            .generations
+            .iter_mut()
+            .find(|(id, _)| *id == generation_id)
+        {
+            Some((_, results)) => results.push(result),
+            None => self.generations.push((generation_id, vec![result])),
+        }
+    }
+
+    pub fn build(self) -> ExamPsychometrics {
+        let generations: Vec<GenerationPsychometrics> = self
+            .generations
+            .iter()
+            .map(|(generation_id, results)| GenerationPsychometrics {
+                generation_id: *generation_id,
+                statistics: statistics(results.iter(), kr20(results)),
+            })
+            .collect();
+
+        let weighted: Vec<(f64, usize)> = generations
+            .iter()
+            .filter_map(|g| {
+                g.statistics
+                    .reliability
+                    .map(|r| (r, g.statistics.number_of_attempts))
+            })
+            .collect();
+        let weight: usize = weighted.iter().map(|(_, n)| n).sum();
+        let reliability = (weight > 0)
+            .then(|| weighted.iter().map(|(r, n)| r * *n as f64).sum::<f64>() / weight as f64);
+
+        let overall = statistics(
+            self.generations.iter().flat_map(|(_, results)| results),
+            reliability,
+        );
+
+        ExamPsychometrics {
+            exam_id: self.exam.id,
+            passing_percent: self.exam.config.passing_percent,
+            overall,
+            generations,
+        }
+    }
+}
+
+fn statistics<'r>(
+    results: impl Iterator<Item = &'r ScoredResult>,
+    reliability: Option<f64>,
+) -> ScoreStatistics {
+    let mut percents = vec![];
+    let mut passed = 0;
+    for result in results {
+        percents.push(result.percent);
+        if result.passed {
+            passed += 1;
+        }
+    }
+    percents.sort_by(|a, b| a.total_cmp(b));
+
+    let n = percents.len();
+    let mean = (n > 0).then(|| percents.iter().sum::<f64>() / n as f64);
+    let median = (n > 0).then(|| {
+        if n % 2 == 0 {
+            (percents[n / 2 - 1] + percents[n / 2]) / 2.0
+        } else {
+            percents[n / 2]
+        }
+    });
+    let standard_deviation = mean
+        .map(|mean| (percents.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / n as f64).sqrt());
+
+    let number_of_bins = (100.0 / HISTOGRAM_BIN_WIDTH) as usize;
+    let mut histogram: Vec<HistogramBin> = (0..number_of_bins)
+        .map(|i| HistogramBin {
+            from_percent: i as f64 * HISTOGRAM_BIN_WIDTH,
+            to_percent: (i + 1) as f64 * HISTOGRAM_BIN_WIDTH,
+            count: 0,
+        })
+        .collect();
+    for percent in &percents {
+        let bin = ((percent / HISTOGRAM_BIN_WIDTH) as usize).min(number_of_bins - 1);
+        histogram[bin].count += 1;
+    }
+
+    ScoreStatistics {
+        number_of_attempts: n,
+        mean,
+        median,
+        standard_deviation,
+        pass_rate: ratio(passed, n),
+        histogram,
+        reliability,
+    }
+}
+
+/// KR-20 over attempts of one generation, which all present the same questions
+fn kr20(results: &[ScoredResult]) -> Option<f64> {
+    let n = results.len();
+    if n < 2 {
+        return None;
+    }
+
+    let mut correct_by_question: HashMap<ObjectId, usize> = HashMap::new();
+    for result in results {
+        for (question_id, correct) in &result.questions {
+            let count = correct_by_question.entry(*question_id).or_default();
+            if *correct {
+                *count += 1;
+            }
+        }
+    }
+    let k = correct_by_question.len();
+    if k < 2 {
+        return None;
+    }
+
+    let sum_pq: f64 = correct_by_question
+        .values()
+        .map(|correct| {
+            let p = *correct as f64 / n as f64;
+            p * (1.0 - p)
+        })
+        .sum();
+
+    let totals: Vec<f64> = results
+        .iter()
+        .map(|r| r.questions.iter().filter(|(_, correct)| *correct).count() as f64)
+        .collect();
+    let mean = totals.iter().sum::<f64>() / n as f64;
+    let variance = totals.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / n as f64;
+    if variance == 0.0 {
+        return None;
+    }
+
+    Some(k as f64 / (k as f64 - 1.0) * (1.0 - sum_pq / variance))
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+
+    use super::{Correlation, ScoredResult, kr20};
+
+    fn correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+        let mut correlation = Correlation::default();
+        for (x, y) in pairs {
+            correlation.add(*x, *y);
+        }
+        correlation.coefficient()
+    }
+
+    #[test]
+    fn perfect_correlation() {
+        let r = correlation(&[(1.0, 2.0), (2.0, 4.0), (3.0, 6.0)]).unwrap();
+        assert!((r - 1.0).abs() < 1e-12);
+
+        let r = correlation(&[(1.0, 3.0), (2.0, 2.0), (3.0, 1.0)]).unwrap();
+        assert!((r + 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn partial_correlation() {
+        let r = correlation(&[(1.0, 2.0), (2.0, 4.0), (3.0, 5.0), (4.0, 4.0), (5.0, 5.0)]).unwrap();
+        // 6 / sqrt(10 * 6)
+        assert!((r - 0.774_596_669_241_483_4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn no_variance() {
+        assert_eq!(correlation(&[(1.0, 2.0), (1.0, 3.0), (1.0, 4.0)]), None);
+        assert_eq!(correlation(&[(0.0, 2.0), (1.0, 2.0)]), None);
+        assert_eq!(correlation(&[(1.0, 2.0)]), None);
+    }
+
+    /// Results of attempts answering the same questions, by correctness of each question
+    fn results(attempts: &[&[bool]]) -> Vec<ScoredResult> {
+        let question_ids: Vec<ObjectId> = attempts[0].iter().map(|_| ObjectId::new()).collect();
+        attempts
+            .iter()
+            .map(|questions| ScoredResult {
+                percent: 0.0,
+                passed: false,
+                questions: question_ids
+                    .iter()
+                    .copied()
+                    .zip(questions.iter().copied())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kr20_of_graded_attempts() {
+        // p = 3/4, 1/2, 1/4, so sum(pq) = 0.625. Totals 3, 2, 1, 0 have variance 1.25.
+        // KR-20 = 3/2 * (1 - 0.625 / 1.25) = 0.75
+        let results = results(&[
+            &[true, true, true],
+            &[true, true, false],
+            &[true, false, false],
+            &[false, false, false],
+        ]);
+        let reliability = kr20(&results).unwrap();
+        assert!((reliability - 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn kr20_without_enough_data() {
+        // One attempt
+        assert_eq!(kr20(&results(&[&[true, false]])), None);
+        // One question
+        assert_eq!(kr20(&results(&[&[true], &[false]])), None);
+        // No variance in totals
+        assert_eq!(kr20(&results(&[&[true, false], &[false, true]])), None);
+    }
+}
@@ -18,7 +18,6 @@ use oauth2::{AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl, basic::Basi
 use reqwest::Method;
 use sentry::integrations::tower::{NewSentryLayer, SentryHttpLayer};
 use std::sync::{Arc, Mutex};
-use supabase_rs::SupabaseClient;
 use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
@@ -31,10 +30,12 @@ use tracing::info;
 use tracing::warn;
 
 use crate::errors::Error;
-use crate::state::Cache;
+use crate::extractor::authorization::{ADMIN, AUTHOR, MODERATOR, READ, require_roles};
 use crate::{
-    database, extractor, routes,
+    cache::{self, MetricsCache},
+    database, deletion, event_store, extractor, moderation, routes,
     state::{self, ClientSync, ServerState},
+    suspicion,
 };
 
 use crate::config::EnvVars;
@@ -75,6 +76,21 @@ pub async fn app(env_vars: EnvVars) -> Result<Router, Error> {
         generated_exam: production_database.collection("ExamEnvironmentGeneratedExam"),
         exam_creator_user: production_database.collection("ExamCreatorUser"),
         exam_creator_session: production_database.collection("ExamCreatorSession"),
+        exam_creator_user_access: production_database.collection("ExamCreatorUser"),
+        exam_creator_audit_log: production_database.collection("ExamCreatorAuditLog"),
+        exam_creator_moderation_note: production_database.collection("ExamCreatorModerationNote"),
+        exam_creator_moderation_claim: production_database.collection("ExamCreatorModerationClaim"),
+        exam_creator_attempt_suspicion: production_database
+            .collection("ExamCreatorAttemptSuspicion"),
+        exam_creator_review_session: production_database.collection("ExamCreatorReviewSession"),
+        exam_creator_attempt_deletion: production_database.collection("ExamCreatorAttemptDeletion"),
+        exam_creator_archived_attempt: production_database.collection("ExamCreatorArchivedAttempt"),
+        exam_creator_denial_reason: production_database.collection("ExamCreatorDenialReason"),
+        exam_creator_appeal: production_database.collection("ExamCreatorAppeal"),
+        exam_creator_challenge_revocation: production_database
+            .collection("ExamCreatorChallengeRevocation"),
+        exam_creator_moderation_decision: production_database
+            .collection("ExamCreatorModerationDecision"),
         exam_environment_exam_moderation: production_database
             .collection("ExamEnvironmentExamModeration"),
     };
@@ -90,35 +106,47 @@ pub async fn app(env_vars: EnvVars) -> Result<Router, Error> {
         exam_creator_user: staging_database.collection("ExamCreatorUser"),
         // Should not be used
         exam_creator_session: staging_database.collection("ExamCreatorSession"),
+        // Should not be used
+        exam_creator_user_access: staging_database.collection("ExamCreatorUser"),
+        // Should not be used
+        exam_creator_audit_log: staging_database.collection("ExamCreatorAuditLog"),
+        exam_creator_moderation_note: staging_database.collection("ExamCreatorModerationNote"),
+        exam_creator_moderation_claim: staging_database.collection("ExamCreatorModerationClaim"),
+        exam_creator_attempt_suspicion: staging_database.collection("ExamCreatorAttemptSuspicion"),
+        exam_creator_review_session: staging_database.collection("ExamCreatorReviewSession"),
+        exam_creator_attempt_deletion: staging_database.collection("ExamCreatorAttemptDeletion"),
+        exam_creator_archived_attempt: staging_database.collection("ExamCreatorArchivedAttempt"),
+        exam_creator_denial_reason: staging_database.collection("ExamCreatorDenialReason"),
+        exam_creator_appeal: staging_database.collection("ExamCreatorAppeal"),
+        exam_creator_challenge_revocation: staging_database
+            .collection("ExamCreatorChallengeRevocation"),
+        exam_creator_moderation_decision: staging_database
+            .collection("ExamCreatorModerationDecision"),
         exam_environment_exam_moderation: staging_database
             .collection("ExamEnvironmentExamModeration"),
     };
 
+    production_database.create_indexes().await;
+    staging_database.create_indexes().await;
+    moderation::seed_denial_reasons(&production_database).await;
+
     let client_sync = Arc::new(Mutex::new(ClientSync {
         users: Vec::new(),
         exams: Vec::new(),
     }));
 
-    let exam_metrics_by_id_cache = Arc::new(Mutex::new(vec![]));
-    let attempt_metrics_cache = Arc::new(Mutex::new(Cache::new()));
-    let pending_deletes = Arc::new(Mutex::new(std::collections::HashMap::new()));
-    let attempt_page_views = Arc::new(Mutex::new(std::collections::HashMap::new()));
+    let metrics_cache = Arc::new(MetricsCache::default());
 
-    let supabase_url = &env_vars.supabase_url;
-    let supabase_key = &env_vars.supabase_key;
-    let supabase = SupabaseClient::new(supabase_url, supabase_key)?;
+    let event_store = event_store::new(&env_vars.event_store)?;
 
     let server_state = ServerState {
         production_database,
         staging_database,
-        supabase,
+        event_store,
         client_sync,
         key: Key::from(env_vars.cookie_key.as_bytes()),
         env_vars: env_vars.clone(),
-        exam_metrics_by_id_cache,
-        attempt_metrics_cache,
-        pending_deletes,
-        attempt_page_views,
+        metrics_cache,
     };
 
     tokio::spawn(state::cleanup_online_users(
@@ -126,6 +154,26 @@ pub async fn app(env_vars: EnvVars) -> Result<Router, Error> {
         std::time::Duration::from_secs(5 * 60),
     ));
 
+    tokio::spawn(suspicion::score_pending_attempts(
+        server_state.clone(),
+        std::time::Duration::from_secs(10 * 60),
+    ));
+
+    tokio::spawn(deletion::execute_due_deletions(
+        server_state.clone(),
+        std::time::Duration::from_secs(2),
+    ));
+
+    tokio::spawn(deletion::purge_archived_attempts(
+        server_state.clone(),
+        std::time::Duration::from_secs(60 * 60),
+    ));
+
+    tokio::spawn(cache::evict_expired(
+        Arc::clone(&server_state.metrics_cache),
+        std::time::Duration::from_secs(10 * 60),
+    ));
+
     let cors = CorsLayer::new()
         .allow_methods([
             Method::GET,
@@ -172,78 +220,374 @@ pub async fn app(env_vars: EnvVars) -> Result<Router, Error> {
     };
 
     let app = app
-        .route("/api/exams", get(routes::exams::get_exams))
-        .route("/api/exams", post(routes::exams::post_exam))
-        .route("/api/exams/{exam_id}", get(routes::exams::get_exam_by_id))
-        .route("/api/exams/{exam_id}", put(routes::exams::put_exam))
+        .route(
+            "/api/exams",
+            require_roles(&server_state, READ, get(routes::exams::get_exams)),
+        )
+        .route(
+            "/api/exams",
+            require_roles(&server_state, AUTHOR, post(routes::exams::post_exam)),
+        )
+        .route(
+            "/api/exams/{exam_id}",
+            require_roles(&server_state, READ, get(routes::exams::get_exam_by_id)),
+        )
+        .route(
+            "/api/exams/{exam_id}",
+            require_roles(&server_state, AUTHOR, put(routes::exams::put_exam)),
+        )
         .route(
             "/api/exams/{exam_id}/seed/staging",
-            put(routes::exams::put_exam_by_id_to_staging),
+            require_roles(
+                &server_state,
+                AUTHOR,
+                put(routes::exams::put_exam_by_id_to_staging),
+            ),
         )
         .route(
             "/api/exams/{exam_id}/seed/production",
-            put(routes::exams::put_exam_by_id_to_production),
+            require_roles(
+                &server_state,
+                ADMIN,
+                put(routes::exams::put_exam_by_id_to_production),
+            ),
         )
         .route(
             "/api/exams/{exam_id}/generations/{database_environment}",
-            get(routes::exams::get_generations_by_exam_id_with_database_environment)
-                .put(routes::exams::put_generations_by_exam_id_with_database_environment),
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::exams::get_generations_by_exam_id_with_database_environment),
+            ),
+        )
+        .route(
+            "/api/exams/{exam_id}/generations/{database_environment}",
+            require_roles(
+                &server_state,
+                AUTHOR,
+                put(routes::exams::put_generations_by_exam_id_with_database_environment),
+            ),
         )
         .route(
             "/api/exams/{exam_id}/config/validate",
-            post(routes::exams::post_validate_config_by_exam_id),
+            require_roles(
+                &server_state,
+                AUTHOR,
+                post(routes::exams::post_validate_config_by_exam_id),
+            ),
         )
         // .route("/api/attempts", get(routes::attempts::get_attempts))
+        .route(
+            "/api/exports/attempts",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::exports::get_attempts_export),
+            ),
+        )
+        .route(
+            "/api/exports/moderations",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::exports::get_moderations_export),
+            ),
+        )
+        .route(
+            "/api/exports/items",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::exports::get_item_statistics_export),
+            ),
+        )
+        .route(
+            "/api/exports/events",
+            require_roles(&server_state, READ, get(routes::exports::get_events_export)),
+        )
         .route(
             "/api/metrics/exams",
-            get(routes::metrics::get_exams_metrics),
+            require_roles(&server_state, READ, get(routes::metrics::get_exams_metrics)),
+        )
+        .route(
+            "/api/metrics/attempts/timeseries",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::metrics::get_attempts_time_series),
+            ),
         )
         .route(
             "/api/metrics/attempts",
-            get(routes::metrics::get_attempts_metrics),
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::metrics::get_attempts_metrics),
+            ),
+        )
+        .route(
+            "/api/metrics/exams/{exam_id}/psychometrics",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::metrics::get_psychometrics_by_exam_id),
+            ),
+        )
+        .route(
+            "/api/metrics/exams/{exam_id}/items",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::metrics::get_item_analysis_by_exam_id),
+            ),
         )
         .route(
             "/api/metrics/exams/{exam_id}",
-            get(routes::metrics::get_exam_metrics_by_exam_id),
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::metrics::get_exam_metrics_by_exam_id),
+            ),
         )
         .route(
             "/api/attempts/{attempt_id}",
-            get(routes::attempts::get_attempt_by_id),
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::attempts::get_attempt_by_id),
+            ),
+        )
+        .route(
+            "/api/attempts/pending-deletions",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                get(routes::attempts::get_pending_deletions),
+            ),
+        )
+        .route(
+            "/api/attempts/archived",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                get(routes::attempts::get_archived_attempts),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/restore",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                post(routes::attempts::post_restore_attempt),
+            ),
         )
         .route(
             "/api/attempts/{attempt_id}/pending-deletion",
-            put(routes::attempts::put_pending_deletion)
-                .delete(routes::attempts::delete_pending_deletion),
+            require_roles(
+                &server_state,
+                MODERATOR,
+                put(routes::attempts::put_pending_deletion)
+                    .delete(routes::attempts::delete_pending_deletion),
+            ),
         )
         .route(
             "/api/attempts/{attempt_id}/moderation",
-            patch(routes::attempts::patch_moderation_status_by_attempt_id)
-                .get(routes::moderations::get_moderation_by_attempt_id),
+            require_roles(
+                &server_state,
+                MODERATOR,
+                patch(routes::attempts::patch_moderation_status_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/moderation",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::moderations::get_moderation_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/suspicion",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::attempts::get_suspicion_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/timeline",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::attempts::get_timeline_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/moderation/claim",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::moderations::get_moderation_claim_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/moderation/claim",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                put(routes::moderations::put_moderation_claim_by_attempt_id)
+                    .delete(routes::moderations::delete_moderation_claim_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/moderation/notes",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::moderations::get_moderation_notes_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/moderation/notes",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                post(routes::moderations::post_moderation_note_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/moderation/decisions",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::moderations::get_moderation_decisions_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/appeals",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::appeals::get_appeals_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/attempts/{attempt_id}/appeals",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                post(routes::appeals::post_appeal_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/appeals",
+            require_roles(&server_state, READ, get(routes::appeals::get_appeals)),
+        )
+        .route(
+            "/api/appeals/{appeal_id}/assignee",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                put(routes::appeals::put_appeal_assignee),
+            ),
+        )
+        .route(
+            "/api/appeals/{appeal_id}/resolution",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                post(routes::appeals::post_appeal_resolution),
+            ),
+        )
+        .route(
+            "/api/denial-reasons",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::moderations::get_denial_reasons),
+            ),
         )
         .route(
             "/api/attempts/{attempt_id}/moderation/view",
-            put(routes::attempts::put_moderation_view_start),
+            require_roles(
+                &server_state,
+                MODERATOR,
+                put(routes::attempts::put_moderation_view_start),
+            ),
+        )
+        .route(
+            "/api/attempts",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::moderations::get_moderations),
+            ),
         )
-        .route("/api/attempts", get(routes::moderations::get_moderations))
         .route(
             "/api/attempts/moderations/count",
-            get(routes::moderations::get_moderations_count),
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::moderations::get_moderations_count),
+            ),
+        )
+        .route(
+            "/api/attempts/moderations/queue",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::moderations::get_moderation_queue),
+            ),
+        )
+        .route(
+            "/api/attempts/moderations/bulk",
+            require_roles(
+                &server_state,
+                MODERATOR,
+                post(routes::attempts::post_bulk_moderation),
+            ),
         )
         .route(
             "/api/attempts/user/{user_id}",
-            get(routes::attempts::get_attempts_by_user_id),
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::attempts::get_attempts_by_user_id),
+            ),
         )
         .route(
             "/api/attempts/user/{user_id}/count",
-            get(routes::attempts::get_number_of_attempts_by_user_id),
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::attempts::get_number_of_attempts_by_user_id),
+            ),
+        )
+        .route(
+            "/api/exam-challenges/{exam_id}",
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::exam_challenge::get_exam_challenges),
+            ),
         )
         .route(
             "/api/exam-challenges/{exam_id}",
-            get(routes::exam_challenge::get_exam_challenges)
-                .put(routes::exam_challenge::put_exam_challenges), // .delete(routes::exam_challenge::delete_exam_challenge),
+            require_roles(
+                &server_state,
+                AUTHOR,
+                put(routes::exam_challenge::put_exam_challenges), // .delete(routes::exam_challenge::delete_exam_challenge),
+            ),
         )
-        .route("/api/users", get(routes::users::get_users))
-        .route("/api/users/search", get(routes::users::get_user_search))
+        .route(
+            "/api/users",
+            require_roles(&server_state, READ, get(routes::users::get_users)),
+        )
+        .route(
+            "/api/users/search",
+            require_roles(&server_state, READ, get(routes::users::get_user_search)),
+        )
+        // Any authenticated user may view their session, and change their own settings
         .route("/api/users/session", get(routes::users::get_session_user))
         .route(
             "/api/users/session/settings",
@@ -251,11 +595,95 @@ pub async fn app(env_vars: EnvVars) -> Result<Router, Error> {
         )
         .route(
             "/api/state/exams/{exam_id}",
-            put(routes::discard_exam_state_by_id),
+            require_roles(&server_state, AUTHOR, put(routes::discard_exam_state_by_id)),
         )
         .route(
             "/api/events/attempts/{attempt_id}",
-            get(routes::events::get_events_by_attempt_id),
+            require_roles(
+                &server_state,
+                READ,
+                get(routes::events::get_events_by_attempt_id),
+            ),
+        )
+        .route(
+            "/api/events",
+            require_roles(&server_state, READ, get(routes::events::get_events)),
+        )
+        .route(
+            "/api/admin/users",
+            require_roles(
+                &server_state,
+                ADMIN,
+                get(routes::admin::users::get_users).post(routes::admin::users::post_user),
+            ),
+        )
+        .route(
+            "/api/admin/users/{user_id}",
+            require_roles(
+                &server_state,
+                ADMIN,
+                patch(routes::admin::users::patch_user).delete(routes::admin::users::delete_user),
+            ),
+        )
+        .route(
+            "/api/admin/cache",
+            require_roles(
+                &server_state,
+                ADMIN,
+                get(routes::admin::cache::get_cache).delete(routes::admin::cache::delete_cache),
+            ),
+        )
+        .route(
+            "/api/admin/denial-reasons",
+            require_roles(
+                &server_state,
+                ADMIN,
+                post(routes::admin::denial_reasons::post_denial_reason),
+            ),
+        )
+        .route(
+            "/api/admin/denial-reasons/{code}",
+            require_roles(
+                &server_state,
+                ADMIN,
+                patch(routes::admin::denial_reasons::patch_denial_reason),
+            ),
+        )
+        .route(
+            "/api/admin/revocations",
+            require_roles(
+                &server_state,
+                ADMIN,
+                get(routes::admin::revocations::get_revocations),
+            ),
+        )
+        .route(
+            "/api/admin/revocations/{revocation_id}/complete",
+            require_roles(
+                &server_state,
+                ADMIN,
+                post(routes::admin::revocations::post_revocation_complete),
+            ),
+        )
+        .route(
+            "/api/reports/moderators",
+            require_roles(
+                &server_state,
+                ADMIN,
+                get(routes::reports::get_moderator_report),
+            ),
+        )
+        .route(
+            "/api/reports/denial-reasons",
+            require_roles(
+                &server_state,
+                ADMIN,
+                get(routes::reports::get_denial_reason_report),
+            ),
+        )
+        .route(
+            "/api/audit",
+            require_roles(&server_state, ADMIN, get(routes::audit_log::get_audit_log)),
         )
         .route(
             "/auth/login/github",
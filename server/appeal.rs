@@ -0,0 +1,390 @@
+//! Appeals of denied attempts, each resolved by a moderator other than the one who denied it.
+//!
+//! Upholding an appeal approves the attempt through `moderation::moderate_attempt`, so the change
+//! is recorded in the decision history and audit log like any other decision.
+use std::collections::HashMap;
+
+use futures_util::TryStreamExt;
+use http::StatusCode;
+use mongodb::{
+    bson::{DateTime, doc, oid::ObjectId},
+    options::ReturnDocument,
+};
+
+use crate::{
+    audit::{self, AuditEntry},
+    database::{
+        Database, database_environment,
+        exam_creator::{
+            ExamCreatorAppeal, ExamCreatorAppealStatus, ExamCreatorAuditAction, ExamCreatorRole,
+        },
+        prisma, user_access, user_roles,
+    },
+    errors::Error,
+    moderation::{self, is_duplicate_key},
+    routes::metrics::count,
+    state::ServerState,
+};
+
+/// Opens an appeal of an attempt's denial, assigned to `assignee_id`, or otherwise to the
+/// moderator with the fewest open appeals.
+///
+/// Rejects with 409 if the attempt already has an open appeal.
+pub async fn open(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+    reason: String,
+    assignee_id: Option<ObjectId>,
+) -> Result<ExamCreatorAppeal, Error> {
+    let reason = reason.trim().to_string();
+    if reason.is_empty() {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            "appeal reason must not be empty".to_string(),
+        ));
+    }
+
+    let database = database_environment(server_state, exam_creator_user);
+    let moderation = database
+        .exam_environment_exam_moderation
+        .find_one(doc! {"examAttemptId": attempt_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("moderation non-existent for attempt id: {attempt_id}"),
+        ))?;
+    if moderation.status != prisma::ExamEnvironmentExamModerationStatus::Denied {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "only denied attempts can be appealed, not: {}",
+                moderation.status
+            ),
+        ));
+    }
+
+    let assignee = match assignee_id {
+        Some(assignee_id) => {
+            Some(eligible_assignee(server_state, assignee_id, moderation.moderator_id).await?)
+        }
+        None => least_loaded_assignee(server_state, database, moderation.moderator_id).await?,
+    };
+
+    let appeal = ExamCreatorAppeal {
+        id: ObjectId::new(),
+        exam_attempt_id: attempt_id,
+        moderation_id: moderation.id,
+        database_environment: exam_creator_user.settings.database_environment.clone(),
+        reason,
+        opened_by_id: exam_creator_user.id,
+        original_moderator_id: moderation.moderator_id,
+        assignee_id: assignee.as_ref().map(|(id, _)| *id),
+        assignee_name: assignee.map(|(_, name)| name),
+        status: ExamCreatorAppealStatus::Open,
+        outcome_feedback: None,
+        resolved_by_id: None,
+        resolved_at: None,
+        created_at: DateTime::now(),
+        version: 1,
+    };
+
+    match database.exam_creator_appeal.insert_one(&appeal).await {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key(&e) => {
+            return Err(Error::Server(
+                StatusCode::CONFLICT,
+                format!("attempt already has an open appeal: {attempt_id}"),
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    appeal_metric(&appeal.database_environment, "opened");
+    audit::record(
+        server_state,
+        exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::AppealOpen,
+            vec![attempt_id, moderation.id, appeal.id],
+        )
+        .database_environment(appeal.database_environment.clone())
+        .after(doc! {
+            "reason": appeal.reason.clone(),
+            "assigneeId": appeal.assignee_id,
+        }),
+    )
+    .await;
+
+    Ok(appeal)
+}
+
+/// Assigns an open appeal to another moderator
+pub async fn assign(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    appeal_id: ObjectId,
+    assignee_id: ObjectId,
+) -> Result<ExamCreatorAppeal, Error> {
+    let database = database_environment(server_state, exam_creator_user);
+    let appeal = open_appeal(database, appeal_id).await?;
+
+    let (assignee_id, assignee_name) =
+        eligible_assignee(server_state, assignee_id, appeal.original_moderator_id).await?;
+
+    let updated_appeal = database
+        .exam_creator_appeal
+        .find_one_and_update(
+            doc! {"_id": appeal_id, "status": "Open"},
+            doc! {"$set": {"assigneeId": assignee_id, "assigneeName": &assignee_name}},
+        )
+        .return_document(ReturnDocument::After)
+        .await?
+        .ok_or_else(|| appeal_resolved(appeal_id))?;
+
+    audit::record(
+        server_state,
+        exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::AppealAssign,
+            vec![appeal.exam_attempt_id, appeal.id],
+        )
+        .database_environment(appeal.database_environment.clone())
+        .before(doc! {"assigneeId": appeal.assignee_id})
+        .after(doc! {"assigneeId": assignee_id}),
+    )
+    .await;
+
+    Ok(updated_appeal)
+}
+
+/// Resolves an open appeal. Only the assignee, or an admin, can resolve an appeal, and never the
+/// moderator of the appealed decision.
+///
+/// Upholding an appeal approves the attempt, with the outcome feedback as the moderation feedback.
+pub async fn resolve(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    appeal_id: ObjectId,
+    outcome: ExamCreatorAppealStatus,
+    outcome_feedback: Option<String>,
+) -> Result<ExamCreatorAppeal, Error> {
+    if outcome == ExamCreatorAppealStatus::Open {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            "an appeal can only be resolved as Upheld or Rejected".to_string(),
+        ));
+    }
+
+    let database = database_environment(server_state, exam_creator_user);
+    let appeal = open_appeal(database, appeal_id).await?;
+
+    if appeal.original_moderator_id == Some(exam_creator_user.id) {
+        return Err(Error::Server(
+            StatusCode::FORBIDDEN,
+            "moderators cannot resolve appeals of their own decisions".to_string(),
+        ));
+    }
+    if appeal.assignee_id != Some(exam_creator_user.id) {
+        let is_admin = user_roles(server_state, exam_creator_user.id)
+            .await?
+            .contains(&ExamCreatorRole::Admin);
+        if !is_admin {
+            return Err(Error::Server(
+                StatusCode::FORBIDDEN,
+                "only the assignee can resolve an appeal".to_string(),
+            ));
+        }
+    }
+
+    let outcome_feedback = outcome_feedback
+        .map(|feedback| feedback.trim().to_string())
+        .filter(|feedback| !feedback.is_empty());
+
+    // Resolved before approving, so concurrent resolutions cannot both approve the attempt
+    let resolved_appeal = database
+        .exam_creator_appeal
+        .find_one_and_update(
+            doc! {"_id": appeal_id, "status": "Open"},
+            doc! {"$set": {
+                "status": bson::serialize_to_bson(&outcome)?,
+                "outcomeFeedback": outcome_feedback.clone(),
+                "resolvedById": exam_creator_user.id,
+                "resolvedAt": DateTime::now(),
+            }},
+        )
+        .return_document(ReturnDocument::After)
+        .await?
+        .ok_or_else(|| appeal_resolved(appeal_id))?;
+
+    if outcome == ExamCreatorAppealStatus::Upheld {
+        if let Err(e) = moderation::moderate_attempt(
+            server_state,
+            exam_creator_user,
+            appeal.exam_attempt_id,
+            &prisma::ExamEnvironmentExamModerationStatus::Approved,
+            outcome_feedback.clone(),
+            &[],
+            None,
+        )
+        .await
+        {
+            // Reopen, so the appeal can be resolved again
+            database
+                .exam_creator_appeal
+                .update_one(
+                    doc! {"_id": appeal_id},
+                    doc! {
+                        "$set": {"status": "Open"},
+                        "$unset": {"outcomeFeedback": "", "resolvedById": "", "resolvedAt": ""},
+                    },
+                )
+                .await?;
+            return Err(e);
+        }
+    }
+
+    appeal_metric(
+        &appeal.database_environment,
+        match outcome {
+            ExamCreatorAppealStatus::Upheld => "upheld",
+            _ => "rejected",
+        },
+    );
+    audit::record(
+        server_state,
+        exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::AppealResolve,
+            vec![appeal.exam_attempt_id, appeal.id],
+        )
+        .database_environment(appeal.database_environment.clone())
+        .before(doc! {"status": bson::serialize_to_bson(&appeal.status)?})
+        .after(doc! {
+            "status": bson::serialize_to_bson(&outcome)?,
+            "outcomeFeedback": outcome_feedback,
+        }),
+    )
+    .await;
+
+    Ok(resolved_appeal)
+}
+
+async fn open_appeal(database: &Database, appeal_id: ObjectId) -> Result<ExamCreatorAppeal, Error> {
+    let appeal = database
+        .exam_creator_appeal
+        .find_one(doc! {"_id": appeal_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::NOT_FOUND,
+            format!("appeal non-existent: {appeal_id}"),
+        ))?;
+    if appeal.status != ExamCreatorAppealStatus::Open {
+        return Err(appeal_resolved(appeal_id));
+    }
+
+    Ok(appeal)
+}
+
+fn appeal_resolved(appeal_id: ObjectId) -> Error {
+    Error::Server(
+        StatusCode::CONFLICT,
+        format!("appeal is already resolved: {appeal_id}"),
+    )
+}
+
+/// Get the id and name of a user who can be assigned an appeal: an enabled moderator, or admin,
+/// other than the moderator of the appealed decision.
+async fn eligible_assignee(
+    server_state: &ServerState,
+    assignee_id: ObjectId,
+    original_moderator_id: Option<ObjectId>,
+) -> Result<(ObjectId, String), Error> {
+    if original_moderator_id == Some(assignee_id) {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            "an appeal cannot be assigned to the moderator of the appealed decision".to_string(),
+        ));
+    }
+
+    let can_moderate = user_access(server_state, assignee_id)
+        .await?
+        .is_some_and(|access| {
+            !access.disabled
+                && access
+                    .roles
+                    .iter()
+                    .any(|role| matches!(role, ExamCreatorRole::Moderator | ExamCreatorRole::Admin))
+        });
+    if !can_moderate {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("user cannot moderate: {assignee_id}"),
+        ));
+    }
+
+    let assignee = server_state
+        .production_database
+        .exam_creator_user
+        .find_one(doc! {"_id": assignee_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("user non-existent: {assignee_id}"),
+        ))?;
+
+    Ok((assignee.id, assignee.name))
+}
+
+/// Get the enabled moderator with the fewest open appeals, other than the moderator of the
+/// appealed decision. Returns `None` if there is no such moderator.
+async fn least_loaded_assignee(
+    server_state: &ServerState,
+    database: &Database,
+    original_moderator_id: Option<ObjectId>,
+) -> Result<Option<(ObjectId, String)>, Error> {
+    let mut filter = doc! {
+        "roles": bson::serialize_to_bson(&ExamCreatorRole::Moderator)?,
+        "disabled": {"$ne": true},
+    };
+    if let Some(original_moderator_id) = original_moderator_id {
+        filter.insert("_id", doc! {"$ne": original_moderator_id});
+    }
+    let moderators: Vec<prisma::ExamCreatorUser> = server_state
+        .production_database
+        .exam_creator_user
+        .find(filter)
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut open_appeals: HashMap<ObjectId, u64> = HashMap::new();
+    let mut loads = database
+        .exam_creator_appeal
+        .aggregate(vec![
+            doc! {"$match": {"status": "Open", "assigneeId": {"$ne": null}}},
+            doc! {"$group": {"_id": "$assigneeId", "open": {"$sum": 1}}},
+        ])
+        .await?;
+    while let Some(load) = loads.try_next().await? {
+        open_appeals.insert(load.get_object_id("_id")?, count(&load, "open"));
+    }
+
+    // Ties go to the longest-standing moderator, so assignment is deterministic
+    let assignee = moderators
+        .into_iter()
+        .min_by_key(|m| (open_appeals.get(&m.id).copied().unwrap_or(0), m.id))
+        .map(|m| (m.id, m.name));
+
+    Ok(assignee)
+}
+
+fn appeal_metric(
+    database_environment: &prisma::ExamCreatorDatabaseEnvironment,
+    outcome: &'static str,
+) {
+    sentry::metrics::counter("exam.moderation.appeal", 1)
+        .attribute("outcome", outcome)
+        .attribute("database_environment", database_environment.to_string())
+        .capture();
+}
@@ -0,0 +1,111 @@
+//! Audit log of mutating actions taken by Exam Creator users.
+use mongodb::{
+    Collection,
+    bson::{DateTime, Document, doc, oid::ObjectId},
+};
+use tracing::error;
+
+use crate::{
+    database::{
+        exam_creator::{ExamCreatorAuditAction, ExamCreatorAuditLog},
+        prisma,
+    },
+    state::ServerState,
+};
+
+/// An action to record in the audit log
+pub struct AuditEntry {
+    action: ExamCreatorAuditAction,
+    target_ids: Vec<ObjectId>,
+    database_environment: Option<prisma::ExamCreatorDatabaseEnvironment>,
+    before: Option<Document>,
+    after: Option<Document>,
+}
+
+impl AuditEntry {
+    pub fn new(action: ExamCreatorAuditAction, target_ids: Vec<ObjectId>) -> Self {
+        Self {
+            action,
+            target_ids,
+            database_environment: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn database_environment(
+        mut self,
+        database_environment: prisma::ExamCreatorDatabaseEnvironment,
+    ) -> Self {
+        self.database_environment = Some(database_environment);
+        self
+    }
+
+    pub fn before(mut self, before: Document) -> Self {
+        self.before = Some(before);
+        self
+    }
+
+    pub fn after(mut self, after: Document) -> Self {
+        self.after = Some(after);
+        self
+    }
+}
+
+/// Records an action taken by `actor` in the audit log.
+pub async fn record(state: &ServerState, actor: &prisma::ExamCreatorUser, entry: AuditEntry) {
+    insert(
+        &state.production_database.exam_creator_audit_log,
+        Some(actor.id),
+        &actor.email,
+        entry,
+    )
+    .await;
+}
+
+/// Inserts an entry into the audit log.
+///
+/// A failed insert is logged, but not returned, because the audited action has already been taken.
+pub async fn insert(
+    audit_log: &Collection<ExamCreatorAuditLog>,
+    actor_id: Option<ObjectId>,
+    actor_email: &str,
+    entry: AuditEntry,
+) {
+    let AuditEntry {
+        action,
+        target_ids,
+        database_environment,
+        before,
+        after,
+    } = entry;
+
+    let audit_log_entry = ExamCreatorAuditLog {
+        id: ObjectId::new(),
+        actor_id,
+        actor_email: actor_email.to_string(),
+        action,
+        target_ids,
+        database_environment,
+        before,
+        after,
+        created_at: DateTime::now(),
+        version: 1,
+    };
+
+    if let Err(e) = audit_log.insert_one(&audit_log_entry).await {
+        error!(error = ?e, ?action, "unable to record audit log entry");
+    }
+}
+
+/// Summary of an exam, small enough to store in the audit log
+pub fn exam_summary(exam: &prisma::ExamCreatorExam) -> Document {
+    let number_of_questions: usize = exam.question_sets.iter().map(|qs| qs.questions.len()).sum();
+    doc! {
+        "name": exam.config.name.clone(),
+        "deprecated": exam.deprecated,
+        "version": exam.version,
+        "numberOfQuestionSets": exam.question_sets.len() as i64,
+        "numberOfQuestions": number_of_questions as i64,
+    }
+}
@@ -0,0 +1,538 @@
+//! Cache of expensive metrics queries, keyed by database environment and query.
+//!
+//! Entries expire after their TTL, are refreshed in the background once stale, and are
+//! invalidated when the attempts or moderations they are computed from change.
+//!
+//! Invalidating bumps the generation of the invalidated keys, so a value computed before the
+//! invalidation, but finishing after it, is not cached.
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use futures_util::{
+    FutureExt,
+    future::{BoxFuture, Shared},
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::{database::prisma, errors::Error};
+
+/// Maximum number of entries, after which the least recently accessed entry is evicted
+const MAX_ENTRIES: usize = 256;
+/// Fraction of the TTL after which an entry is stale, and refreshed in the background on access
+const STALE_AFTER_TTL_FRACTION: f64 = 0.8;
+
+/// Query an entry was computed from, used to invalidate entries when their data changes
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheKind {
+    ExamMetrics,
+    AttemptMetrics,
+    ItemAnalysis,
+    Psychometrics,
+    AttemptTimeSeries,
+}
+
+impl CacheKind {
+    fn depends_on_moderations(&self) -> bool {
+        match self {
+            CacheKind::ExamMetrics
+            | CacheKind::ItemAnalysis
+            | CacheKind::Psychometrics
+            | CacheKind::AttemptTimeSeries => true,
+            CacheKind::AttemptMetrics => false,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            CacheKind::ExamMetrics => "exam_metrics",
+            CacheKind::AttemptMetrics => "attempt_metrics",
+            CacheKind::ItemAnalysis => "item_analysis",
+            CacheKind::Psychometrics => "psychometrics",
+            CacheKind::AttemptTimeSeries => "attempt_time_series",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// `prisma::ExamCreatorDatabaseEnvironment` is not `Hash`, so its string form is used
+    database_environment: String,
+    kind: CacheKind,
+    /// Parameters of the query, e.g. the exam id
+    query: String,
+}
+
+impl CacheKey {
+    pub fn new(
+        database_environment: &prisma::ExamCreatorDatabaseEnvironment,
+        kind: CacheKind,
+        query: impl Into<String>,
+    ) -> Self {
+        Self {
+            database_environment: database_environment.to_string(),
+            kind,
+            query: query.into(),
+        }
+    }
+}
+
+struct CacheEntry {
+    value: Arc<dyn Any + Send + Sync>,
+    created_at: SystemTime,
+    stale_at: SystemTime,
+    expires_at: SystemTime,
+    last_accessed_at: SystemTime,
+    hits: u64,
+    refreshing: bool,
+}
+
+/// Summary of a cache entry, for the admin API
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheEntryInfo {
+    pub database_environment: String,
+    pub kind: CacheKind,
+    pub query: String,
+    #[serde_as(as = "serde_with::TimestampMilliSeconds<i64>")]
+    pub created_at: SystemTime,
+    #[serde_as(as = "serde_with::TimestampMilliSeconds<i64>")]
+    pub expires_at: SystemTime,
+    #[serde_as(as = "serde_with::TimestampMilliSeconds<i64>")]
+    pub last_accessed_at: SystemTime,
+    pub hits: u64,
+    pub refreshing: bool,
+}
+
+/// Database environment, and kind, of the keys sharing a generation
+type GenerationKey = (String, CacheKind);
+
+/// A value being computed, shared by every miss waiting for it. An error is shared as its status,
+/// and message, as `Error` is not `Clone`.
+type Computation =
+    Shared<BoxFuture<'static, Result<Arc<dyn Any + Send + Sync>, (StatusCode, String)>>>;
+
+#[derive(Default)]
+pub struct MetricsCache {
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    /// Values being computed, so concurrent misses of a key wait on one computation. Always locked
+    /// after `entries`.
+    in_flight: Mutex<HashMap<CacheKey, Computation>>,
+    /// Bumped on invalidation. Always locked after `entries`, and `in_flight`.
+    ///
+    /// Keys are added when a value starts being computed, so only keys with a computation in
+    /// flight need to be bumped.
+    generations: Mutex<HashMap<GenerationKey, u64>>,
+}
+
+impl MetricsCache {
+    /// Get the cached value of `key`, or compute, and cache it for `ttl`.
+    ///
+    /// A stale value is returned immediately, while `compute` refreshes it in the background. A
+    /// missing value is computed in the background too, so it is cached even if the caller
+    /// gives up waiting for it, and concurrent misses wait on the same computation.
+    pub async fn get_or_compute<T, F, Fut>(
+        self: &Arc<Self>,
+        key: CacheKey,
+        ttl: Duration,
+        compute: F,
+    ) -> Result<T, Error>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + Send + 'static,
+    {
+        let now = SystemTime::now();
+        let mut compute = Some(compute);
+        let (cached, refresh, computation, generation) = {
+            let mut entries = self.entries.lock().unwrap();
+            let generation = *self
+                .generations
+                .lock()
+                .unwrap()
+                .entry(generation_key(&key))
+                .or_default();
+            let (cached, refresh) = match entries.get_mut(&key) {
+                Some(entry) if entry.expires_at > now => {
+                    entry.hits += 1;
+                    entry.last_accessed_at = now;
+                    let refresh = entry.stale_at <= now && !entry.refreshing;
+                    if refresh {
+                        entry.refreshing = true;
+                    }
+                    (entry.value.clone().downcast::<T>().ok(), refresh)
+                }
+                Some(_) => {
+                    entries.remove(&key);
+                    (None, false)
+                }
+                None => (None, false),
+            };
+            let computation = match &cached {
+                Some(_) => None,
+                None => Some(
+                    self.in_flight
+                        .lock()
+                        .unwrap()
+                        .entry(key.clone())
+                        .or_insert_with(|| {
+                            self.spawn_computation(
+                                key.clone(),
+                                ttl,
+                                generation,
+                                compute.take().unwrap(),
+                            )
+                        })
+                        .clone(),
+                ),
+            };
+            (cached, refresh, computation, generation)
+        };
+
+        if let Some(cached) = cached {
+            access_metric(key.kind, "hit");
+            if refresh {
+                let compute = compute.take().unwrap();
+                let cache = Arc::clone(self);
+                tokio::spawn(async move {
+                    match compute().await {
+                        Ok(value) => cache.insert(key, ttl, Arc::new(value), generation),
+                        Err(e) => {
+                            error!(error = ?e, ?key, "unable to refresh cache entry");
+                            if let Some(entry) = cache.entries.lock().unwrap().get_mut(&key) {
+                                entry.refreshing = false;
+                            }
+                        }
+                    }
+                });
+            }
+            return Ok((*cached).clone());
+        }
+
+        access_metric(key.kind, "miss");
+        let value = computation
+            .expect("a miss has a computation")
+            .await
+            .map_err(|(status, message)| Error::Server(status, message))?;
+        value
+            .downcast::<T>()
+            .map(|value| (*value).clone())
+            .map_err(|_| {
+                Error::Server(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("cache entry of {key:?} is not of the requested type"),
+                )
+            })
+    }
+
+    /// Computes the value of `key` in its own task, so it is still cached if every caller waiting
+    /// for it is dropped meanwhile, e.g. on timeout, and the next request hits it.
+    fn spawn_computation<T, F, Fut>(
+        self: &Arc<Self>,
+        key: CacheKey,
+        ttl: Duration,
+        generation: u64,
+        compute: F,
+    ) -> Computation
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + Send + 'static,
+    {
+        let cache = Arc::clone(self);
+        let task_key = key.clone();
+        let task = tokio::spawn(async move {
+            match compute().await {
+                Ok(value) => {
+                    let value: Arc<dyn Any + Send + Sync> = Arc::new(value);
+                    cache.insert(task_key, ttl, Arc::clone(&value), generation);
+                    Ok(value)
+                }
+                Err(e) => {
+                    cache.end_computation(&task_key, generation);
+                    let message = e.to_string();
+                    Err((StatusCode::from(e), message))
+                }
+            }
+        });
+
+        // Not kept alive by its own computation
+        let cache = Arc::downgrade(self);
+        async move {
+            task.await.unwrap_or_else(|e| {
+                if let Some(cache) = cache.upgrade() {
+                    cache.end_computation(&key, generation);
+                }
+                Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("unable to compute cache entry: {e}"),
+                ))
+            })
+        }
+        .boxed()
+        .shared()
+    }
+
+    /// Caches `value`, ending the computation of `key`, unless `key` has been invalidated since
+    /// `generation`.
+    fn insert(
+        &self,
+        key: CacheKey,
+        ttl: Duration,
+        value: Arc<dyn Any + Send + Sync>,
+        generation: u64,
+    ) {
+        let now = SystemTime::now();
+        let entry = CacheEntry {
+            value,
+            created_at: now,
+            stale_at: now + ttl.mul_f64(STALE_AFTER_TTL_FRACTION),
+            expires_at: now + ttl,
+            last_accessed_at: now,
+            hits: 0,
+            refreshing: false,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        if generation != self.generation(&key) {
+            debug!(?key, "cache entry invalidated while computed, not caching");
+            return;
+        }
+        self.in_flight.lock().unwrap().remove(&key);
+
+        if !entries.contains_key(&key) && entries.len() >= MAX_ENTRIES {
+            let least_recently_accessed = entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_accessed_at)
+                .map(|(k, _)| k.clone());
+            if let Some(evicted) = least_recently_accessed {
+                debug!(?evicted, "evicting least recently accessed cache entry");
+                entries.remove(&evicted);
+            }
+        }
+        entries.insert(key, entry);
+    }
+
+    /// Ends the failed computation of `key`, so the next miss starts another, unless `key` has been
+    /// invalidated since `generation`, which already ended it.
+    fn end_computation(&self, key: &CacheKey, generation: u64) {
+        let _entries = self.entries.lock().unwrap();
+        if generation == self.generation(key) {
+            self.in_flight.lock().unwrap().remove(key);
+        }
+    }
+
+    fn generation(&self, key: &CacheKey) -> u64 {
+        self.generations
+            .lock()
+            .unwrap()
+            .get(&generation_key(key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Removes entries matching the filters, returning the number removed.
+    pub fn clear(
+        &self,
+        database_environment: Option<&prisma::ExamCreatorDatabaseEnvironment>,
+        kind: Option<CacheKind>,
+    ) -> usize {
+        let database_environment = database_environment.map(|e| e.to_string());
+        self.remove_where(|(key_environment, key_kind)| {
+            database_environment
+                .as_ref()
+                .is_none_or(|e| e == key_environment)
+                && kind.is_none_or(|k| k == *key_kind)
+        })
+    }
+
+    /// Removes all entries computed from attempts of the database environment
+    pub fn invalidate_attempts(
+        &self,
+        database_environment: &prisma::ExamCreatorDatabaseEnvironment,
+    ) {
+        let removed = self.clear(Some(database_environment), None);
+        debug!(removed, "attempts changed, invalidated cache entries");
+    }
+
+    /// Removes all entries computed from moderations of the database environment
+    pub fn invalidate_moderations(
+        &self,
+        database_environment: &prisma::ExamCreatorDatabaseEnvironment,
+    ) {
+        let database_environment = database_environment.to_string();
+        let removed = self.remove_where(|(key_environment, key_kind)| {
+            *key_environment == database_environment && key_kind.depends_on_moderations()
+        });
+        debug!(removed, "moderations changed, invalidated cache entries");
+    }
+
+    /// Removes entries, and computations in flight, and bumps the generation of keys, matching
+    /// `invalidated`, returning the number of entries removed.
+    fn remove_where(&self, invalidated: impl Fn(&GenerationKey) -> bool) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|key, _| !invalidated(&generation_key(key)));
+        // Callers already waiting still get the computed value, which is not cached
+        self.in_flight
+            .lock()
+            .unwrap()
+            .retain(|key, _| !invalidated(&generation_key(key)));
+
+        let mut generations = self.generations.lock().unwrap();
+        for (_, generation) in generations.iter_mut().filter(|(key, _)| invalidated(key)) {
+            *generation += 1;
+        }
+
+        before - entries.len()
+    }
+
+    pub fn entries(&self) -> Vec<CacheEntryInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .map(|(key, entry)| CacheEntryInfo {
+                database_environment: key.database_environment.clone(),
+                kind: key.kind,
+                query: key.query.clone(),
+                created_at: entry.created_at,
+                expires_at: entry.expires_at,
+                last_accessed_at: entry.last_accessed_at,
+                hits: entry.hits,
+                refreshing: entry.refreshing,
+            })
+            .collect()
+    }
+
+    fn remove_expired(&self) -> usize {
+        let now = SystemTime::now();
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.expires_at > now);
+        before - entries.len()
+    }
+}
+
+fn generation_key(key: &CacheKey) -> GenerationKey {
+    (key.database_environment.clone(), key.kind)
+}
+
+fn access_metric(kind: CacheKind, outcome: &'static str) {
+    sentry::metrics::counter("cache.access", 1)
+        .attribute("cache", kind.as_str())
+        .attribute("outcome", outcome)
+        .capture();
+}
+
+/// Periodically removes expired entries, so entries which are no longer accessed do not linger.
+pub async fn evict_expired(cache: Arc<MetricsCache>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let removed = cache.remove_expired();
+        if removed > 0 {
+            debug!(removed, "evicted expired cache entries");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn key() -> CacheKey {
+        CacheKey::new(
+            &prisma::ExamCreatorDatabaseEnvironment::Staging,
+            CacheKind::ItemAnalysis,
+            "exam",
+        )
+    }
+
+    #[tokio::test]
+    async fn caches_a_miss_computed_after_the_caller_is_dropped() {
+        let cache = Arc::new(MetricsCache::default());
+        let (computed, compute) = tokio::sync::oneshot::channel::<()>();
+
+        // Dropped before the value is computed, as on a request timeout
+        let request = cache.get_or_compute(key(), Duration::from_secs(60), move || async move {
+            compute.await.ok();
+            Ok(1)
+        });
+        let timed_out = tokio::time::timeout(Duration::from_millis(10), request).await;
+        assert!(timed_out.is_err());
+
+        computed.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while cache.entries().is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let value = cache
+            .get_or_compute(key(), Duration::from_secs(60), || async {
+                Err::<i32, _>(Error::Server(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "not cached".to_string(),
+                ))
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_one_computation() {
+        let cache = Arc::new(MetricsCache::default());
+        let computations = Arc::new(AtomicUsize::new(0));
+        let compute = || {
+            let computations = Arc::clone(&computations);
+            move || async move {
+                computations.fetch_add(1, Ordering::SeqCst);
+                tokio::task::yield_now().await;
+                Ok(1)
+            }
+        };
+
+        let (first, second) = tokio::join!(
+            cache.get_or_compute(key(), Duration::from_secs(60), compute()),
+            cache.get_or_compute(key(), Duration::from_secs(60), compute()),
+        );
+
+        assert_eq!(first.unwrap(), 1);
+        assert_eq!(second.unwrap(), 1);
+        assert_eq!(computations.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn a_failed_computation_is_retried_by_the_next_miss() {
+        let cache = Arc::new(MetricsCache::default());
+
+        let failed = cache
+            .get_or_compute(key(), Duration::from_secs(60), || async {
+                Err::<i32, _>(Error::Server(StatusCode::BAD_REQUEST, "failed".to_string()))
+            })
+            .await;
+        assert!(matches!(
+            failed,
+            Err(Error::Server(StatusCode::BAD_REQUEST, _))
+        ));
+
+        let value = cache
+            .get_or_compute(key(), Duration::from_secs(60), || async { Ok(1) })
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+    }
+}
@@ -1,4 +1,4 @@
-use std::env::var;
+use std::{env::var, path::PathBuf};
 
 use http::HeaderValue;
 use mongodb::bson::oid::ObjectId;
@@ -14,10 +14,14 @@ pub struct EnvVars {
     ///
     /// ALLOWED_ORIGINS=http://localhost:3000,https://myapp.com
     pub allowed_origins: Vec<HeaderValue>,
+    /// Days a deleted attempt is kept in the archive, and can be restored, before being purged
+    pub attempt_archive_retention_in_days: u64,
     /// Cookie key for signing cookies
     ///
     /// Must be 64 bytes
     pub cookie_key: String,
+    /// Where attempt events are read from
+    pub event_store: EventStoreConfig,
     /// GitHub OAuth Client ID
     pub github_client_id: String,
     /// GitHub OAuth Client Secret
@@ -40,10 +44,18 @@ pub struct EnvVars {
     pub sentry_dsn: Option<String>,
     /// Session TTL in seconds
     pub session_ttl_in_s: u64,
-    /// Supabase Project URL
-    pub supabase_url: String,
-    /// Supabase Private Key
-    pub supabase_key: String,
+}
+
+#[derive(Clone, Debug)]
+pub enum EventStoreConfig {
+    Supabase {
+        /// Supabase Project URL
+        url: String,
+        /// Supabase Private Key
+        key: String,
+    },
+    /// Directory of `<attempt_id>.json` files, each an array of `events` rows
+    File { dir: PathBuf },
 }
 
 impl EnvVars {
@@ -77,6 +89,19 @@ impl EnvVars {
             }
         };
 
+        let attempt_archive_retention_in_days = match var("ATTEMPT_ARCHIVE_RETENTION_IN_DAYS") {
+            Ok(s) => s
+                .parse()
+                .expect("ATTEMPT_ARCHIVE_RETENTION_IN_DAYS to be valid unsigned integer"),
+            Err(_e) => {
+                let default_attempt_archive_retention_in_days = 90;
+                warn!(
+                    "ATTEMPT_ARCHIVE_RETENTION_IN_DAYS not set. Defaulting to {default_attempt_archive_retention_in_days}"
+                );
+                default_attempt_archive_retention_in_days
+            }
+        };
+
         let Ok(cookie_key) = var("COOKIE_KEY") else {
             error!("COOKIE_KEY not set");
             panic!("COOKIE_KEY required");
@@ -209,20 +234,40 @@ impl EnvVars {
             }
         };
 
-        let Ok(supabase_url) = var("SUPABASE_URL") else {
-            error!("SUPABASE_URL not set");
-            panic!("SUPABASE_URL required");
-        };
-        assert!(!supabase_url.is_empty(), "SUPABASE_URL must not be empty");
-        let Ok(supabase_key) = var("SUPABASE_KEY") else {
-            error!("SUPABASE_KEY not set");
-            panic!("SUPABASE_KEY required");
+        let event_store = match var("EVENT_STORE").as_deref() {
+            Ok("supabase") | Err(_) => {
+                let Ok(url) = var("SUPABASE_URL") else {
+                    error!("SUPABASE_URL not set");
+                    panic!("SUPABASE_URL required, unless EVENT_STORE=file");
+                };
+                assert!(!url.is_empty(), "SUPABASE_URL must not be empty");
+                let Ok(key) = var("SUPABASE_KEY") else {
+                    error!("SUPABASE_KEY not set");
+                    panic!("SUPABASE_KEY required, unless EVENT_STORE=file");
+                };
+                assert!(!key.is_empty(), "SUPABASE_KEY must not be empty");
+                EventStoreConfig::Supabase { url, key }
+            }
+            Ok("file") => {
+                let dir = match var("EVENT_STORE_DIR") {
+                    Ok(dir) => PathBuf::from(dir),
+                    Err(_e) => {
+                        let default_dir = PathBuf::from("public/mocks/api/events/attempts");
+                        warn!("EVENT_STORE_DIR not set. Defaulting to {default_dir:?}");
+                        default_dir
+                    }
+                };
+                warn!("EVENT_STORE set to file. Reading events from {dir:?}");
+                EventStoreConfig::File { dir }
+            }
+            Ok(other) => panic!("EVENT_STORE must be one of supabase, file. Got: {other}"),
         };
-        assert!(!supabase_key.is_empty(), "SUPABASE_KEY must not be empty");
 
         let env_vars = Self {
             allowed_origins,
+            attempt_archive_retention_in_days,
             cookie_key,
+            event_store,
             github_client_id,
             github_client_secret,
             github_redirect_url,
@@ -234,8 +279,6 @@ impl EnvVars {
             request_timeout_in_ms,
             sentry_dsn,
             session_ttl_in_s,
-            supabase_url,
-            supabase_key,
         };
 
         env_vars
@@ -245,22 +288,26 @@ impl EnvVars {
 #[serde_with::serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Attempt {
-    id: ObjectId,
+    pub id: ObjectId,
     #[serde(rename = "examId")]
-    exam_id: ObjectId,
+    pub exam_id: ObjectId,
     #[serde(rename = "userId")]
-    user_id: ObjectId,
-    prerequisites: Vec<ObjectId>,
-    deprecated: bool,
+    pub user_id: ObjectId,
+    pub prerequisites: Vec<ObjectId>,
+    pub deprecated: bool,
     #[serde(rename = "questionSets")]
-    question_sets: Vec<AttemptQuestionSet>,
-    config: prisma::ExamEnvironmentConfig,
+    pub question_sets: Vec<AttemptQuestionSet>,
+    pub config: prisma::ExamEnvironmentConfig,
     #[serde(rename = "startTime")]
     #[serde_as(as = "bson::serde_helpers::datetime::AsRfc3339String")]
-    start_time: mongodb::bson::DateTime,
+    pub start_time: mongodb::bson::DateTime,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Kind of an exam environment event.
+///
+/// New kinds are added here, with their `EventMeta`. Until then, events of the kind are reported
+/// as unknown, rather than failing the whole response.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum EventKind {
     CaptionsOpened,
@@ -268,42 +315,221 @@ pub enum EventKind {
     Focus,
     Blur,
     ExamExit,
+    CopyPaste,
+    FullscreenExit,
+    NetworkLoss,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl EventKind {
+    pub const ALL: [EventKind; 8] = [
+        EventKind::CaptionsOpened,
+        EventKind::QuestionVisit,
+        EventKind::Focus,
+        EventKind::Blur,
+        EventKind::ExamExit,
+        EventKind::CopyPaste,
+        EventKind::FullscreenExit,
+        EventKind::NetworkLoss,
+    ];
+
+    /// Parses the `SCREAMING_SNAKE_CASE` name, returning `None` for unknown kinds
+    pub fn from_name(name: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+    }
+
+    /// `SCREAMING_SNAKE_CASE` name, as stored in Supabase
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::CaptionsOpened => "CAPTIONS_OPENED",
+            EventKind::QuestionVisit => "QUESTION_VISIT",
+            EventKind::Focus => "FOCUS",
+            EventKind::Blur => "BLUR",
+            EventKind::ExamExit => "EXAM_EXIT",
+            EventKind::CopyPaste => "COPY_PASTE",
+            EventKind::FullscreenExit => "FULLSCREEN_EXIT",
+            EventKind::NetworkLoss => "NETWORK_LOSS",
+        }
+    }
+}
+
+/// Event validated against the payload of its kind
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize)]
 pub struct Event {
     pub id: String,
-    pub timestamp: String,
+    /// UTC, parsed from the RFC 3339 timestamp stored in Supabase, with any offset
+    #[serde_as(as = "bson::serde_helpers::datetime::AsRfc3339String")]
+    pub timestamp: mongodb::bson::DateTime,
     pub kind: EventKind,
-    pub meta: serde_json::Value,
+    pub meta: EventMeta,
+    pub attempt_id: ObjectId,
+}
+
+impl Event {
+    /// Question the event happened on, if recorded
+    pub fn question_id(&self) -> Option<ObjectId> {
+        match &self.meta {
+            EventMeta::Question(meta) => meta.question,
+            EventMeta::CopyPaste(meta) => meta.question,
+            EventMeta::NetworkLoss(meta) => meta.question,
+        }
+    }
+}
+
+/// Payload of an event, depending on its kind. Unknown fields are kept in `extra`, and passed
+/// through, so fields can be added without breaking validation.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum EventMeta {
+    /// `CAPTIONS_OPENED`, `QUESTION_VISIT`, `FOCUS`, `BLUR`, `EXAM_EXIT`, and `FULLSCREEN_EXIT`
+    Question(QuestionEventMeta),
+    CopyPaste(CopyPasteEventMeta),
+    NetworkLoss(NetworkLossEventMeta),
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct QuestionEventMeta {
+    /// Question being viewed. Hex string, like the client records it.
+    #[serde_as(as = "Option<bson::serde_helpers::object_id::AsHexString>")]
+    pub question: Option<ObjectId>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ClipboardAction {
+    Copy,
+    Cut,
+    Paste,
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CopyPasteEventMeta {
+    #[serde_as(as = "Option<bson::serde_helpers::object_id::AsHexString>")]
+    pub question: Option<ObjectId>,
+    pub action: ClipboardAction,
+    /// Number of characters copied, cut, or pasted
+    pub length: Option<u64>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkLossEventMeta {
+    #[serde_as(as = "Option<bson::serde_helpers::object_id::AsHexString>")]
+    pub question: Option<ObjectId>,
+    /// `None` if the connection was not restored during the attempt
+    pub duration_ms: Option<u64>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Row of the Supabase `events` table, before validation
+#[derive(Clone, Debug, Deserialize)]
+pub struct RawEvent {
+    pub id: String,
+    pub timestamp: String,
+    pub kind: String,
+    #[serde(default)]
+    pub meta: Option<serde_json::Value>,
     pub attempt_id: ObjectId,
 }
 
+/// Reason an event row was left out of an attempt's events
+#[derive(Debug, thiserror::Error)]
+pub enum EventError {
+    #[error("unknown event kind: {0}")]
+    UnknownKind(String),
+    #[error("invalid timestamp {0}: {1}")]
+    Timestamp(String, chrono::ParseError),
+    #[error("invalid {kind:?} meta: {error}")]
+    Meta {
+        kind: EventKind,
+        error: serde_json::Error,
+    },
+    #[error("{0:?} event is missing meta.question")]
+    MissingQuestion(EventKind),
+}
+
+impl TryFrom<RawEvent> for Event {
+    type Error = EventError;
+
+    fn try_from(raw: RawEvent) -> Result<Self, Self::Error> {
+        let kind =
+            EventKind::from_name(&raw.kind).ok_or_else(|| EventError::UnknownKind(raw.kind))?;
+        let timestamp = chrono::DateTime::parse_from_rfc3339(&raw.timestamp)
+            .map_err(|e| EventError::Timestamp(raw.timestamp.clone(), e))?;
+
+        // Older events store `null` meta
+        let meta = raw
+            .meta
+            .filter(|meta| !meta.is_null())
+            .unwrap_or_else(|| serde_json::json!({}));
+        let meta_error = |error| EventError::Meta { kind, error };
+        let meta = match kind {
+            EventKind::CopyPaste => {
+                EventMeta::CopyPaste(serde_json::from_value(meta).map_err(meta_error)?)
+            }
+            EventKind::NetworkLoss => {
+                EventMeta::NetworkLoss(serde_json::from_value(meta).map_err(meta_error)?)
+            }
+            EventKind::CaptionsOpened
+            | EventKind::QuestionVisit
+            | EventKind::Focus
+            | EventKind::Blur
+            | EventKind::ExamExit
+            | EventKind::FullscreenExit => {
+                let meta: QuestionEventMeta = serde_json::from_value(meta).map_err(meta_error)?;
+                let needs_question =
+                    matches!(kind, EventKind::CaptionsOpened | EventKind::QuestionVisit);
+                if needs_question && meta.question.is_none() {
+                    return Err(EventError::MissingQuestion(kind));
+                }
+                EventMeta::Question(meta)
+            }
+        };
+
+        Ok(Event {
+            id: raw.id,
+            timestamp: mongodb::bson::DateTime::from_millis(timestamp.timestamp_millis()),
+            kind,
+            meta,
+            attempt_id: raw.attempt_id,
+        })
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AttemptQuestionSet {
-    id: ObjectId,
+    pub id: ObjectId,
     #[serde(rename = "type")]
-    _type: prisma::ExamEnvironmentQuestionType,
-    context: Option<String>,
-    questions: Vec<AttemptQuestionSetQuestion>,
+    pub _type: prisma::ExamEnvironmentQuestionType,
+    pub context: Option<String>,
+    pub questions: Vec<AttemptQuestionSetQuestion>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AttemptQuestionSetQuestion {
-    id: ObjectId,
-    text: String,
-    tags: Vec<String>,
-    deprecated: bool,
-    audio: Option<prisma::ExamEnvironmentAudio>,
+    pub id: ObjectId,
+    pub text: String,
+    pub tags: Vec<String>,
+    pub deprecated: bool,
+    pub audio: Option<prisma::ExamEnvironmentAudio>,
     /// Includes all answers available in the exam
-    answers: Vec<prisma::ExamEnvironmentAnswer>,
+    pub answers: Vec<prisma::ExamEnvironmentAnswer>,
     /// Includes only answers submitted in the attempt
-    selected: Vec<ObjectId>,
+    pub selected: Vec<ObjectId>,
     /// Includes only answers shown from the generation
-    generated: Vec<ObjectId>,
+    pub generated: Vec<ObjectId>,
     /// If question was submitted, time it was submitted
     #[serde(rename = "submissionTime")]
-    submission_time: Option<mongodb::bson::DateTime>,
+    pub submission_time: Option<mongodb::bson::DateTime>,
 }
 
 /// Constructs an `Attempt`:
@@ -567,3 +793,66 @@ pub fn validate_config(exam: &prisma::ExamCreatorExam) -> Result<(), String> {
 fn valid_sentry_dsn(url: &str) -> bool {
     url.parse::<Dsn>().is_ok()
 }
+
+/// Fixtures shared by the tests of modules reading attempts
+#[cfg(test)]
+pub mod fixtures {
+    use mongodb::bson::oid::ObjectId;
+    use serde_json::json;
+
+    use super::Attempt;
+
+    /// Attempt, started at 2026-01-01T00:00:00Z, of one multiple choice question set, with a
+    /// question for each of `questions`, the correctness of its answers. Nothing is generated,
+    /// selected, or submitted.
+    pub fn attempt(questions: &[Vec<bool>]) -> Attempt {
+        let questions: Vec<_> = questions
+            .iter()
+            .map(|answers| {
+                json!({
+                    "id": ObjectId::new().to_hex(),
+                    "text": "",
+                    "tags": ["tag"],
+                    "deprecated": false,
+                    "audio": null,
+                    "answers": answers
+                        .iter()
+                        .map(|is_correct| json!({
+                            "id": ObjectId::new().to_hex(),
+                            "isCorrect": is_correct,
+                            "text": "",
+                        }))
+                        .collect::<Vec<_>>(),
+                    "selected": [],
+                    "generated": [],
+                    "submissionTime": null,
+                })
+            })
+            .collect();
+
+        serde_json::from_value(json!({
+            "id": ObjectId::new().to_hex(),
+            "examId": ObjectId::new().to_hex(),
+            "userId": ObjectId::new().to_hex(),
+            "prerequisites": [],
+            "deprecated": false,
+            "questionSets": [{
+                "id": ObjectId::new().to_hex(),
+                "type": "MultipleChoice",
+                "context": null,
+                "questions": questions,
+            }],
+            "config": {
+                "name": "Exam",
+                "note": "",
+                "tags": [],
+                "totalTimeInS": 3600,
+                "questionSets": [],
+                "retakeTimeInS": 0,
+                "passingPercent": 80.0,
+            },
+            "startTime": "2026-01-01T00:00:00Z",
+        }))
+        .expect("attempt to deserialize")
+    }
+}
@@ -0,0 +1,358 @@
+//! Exam Creator specific fields and collections which are not part of the upstream Prisma schema.
+use mongodb::bson::{DateTime, Document, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+use crate::database::prisma;
+
+/// Role granted to an `ExamCreatorUser`, used to authorize requests.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ExamCreatorRole {
+    /// Manages users, and is allowed every action
+    Admin,
+    /// Creates, edits, seeds, and generates exams
+    Author,
+    /// Moderates and deletes exam attempts
+    Moderator,
+    /// Views exams, attempts, and metrics
+    ReadOnly,
+}
+
+impl std::fmt::Display for ExamCreatorRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let role = match self {
+            ExamCreatorRole::Admin => "admin",
+            ExamCreatorRole::Author => "author",
+            ExamCreatorRole::Moderator => "moderator",
+            ExamCreatorRole::ReadOnly => "read-only",
+        };
+        f.write_str(role)
+    }
+}
+
+/// Access control fields stored on `ExamCreatorUser` documents.
+///
+/// Users without a `roles` field are not granted any role.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExamCreatorUserAccess {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(default)]
+    pub roles: Vec<ExamCreatorRole>,
+    /// Disabled users are not allowed to log in, nor make requests
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+/// Action recorded in the audit log
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ExamCreatorAuditAction {
+    Login,
+    Logout,
+    ExamCreate,
+    ExamUpdate,
+    ExamStateDiscard,
+    ExamSeed,
+    ExamGenerate,
+    ExamChallengesUpdate,
+    ModerationDecision,
+    ModerationNoteCreate,
+    ModerationClaim,
+    ModerationRelease,
+    AttemptDeletionSchedule,
+    AttemptDeletionCancel,
+    AttemptDelete,
+    AttemptRestore,
+    UserSettingsUpdate,
+    UserCreate,
+    UserUpdate,
+    UserDelete,
+    CacheClear,
+    DenialReasonCreate,
+    DenialReasonUpdate,
+    AppealOpen,
+    AppealAssign,
+    AppealResolve,
+    ChallengeRevocationComplete,
+}
+
+/// Record of a mutating action taken by an Exam Creator user.
+///
+/// Only stored in the production database. `databaseEnvironment` records which
+/// database the action was taken against, if any.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorAuditLog {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// `None` if the actor is not an Exam Creator user (e.g. a failed login)
+    pub actor_id: Option<ObjectId>,
+    pub actor_email: String,
+    pub action: ExamCreatorAuditAction,
+    /// Ids of the documents the action was taken on
+    pub target_ids: Vec<ObjectId>,
+    pub database_environment: Option<prisma::ExamCreatorDatabaseEnvironment>,
+    /// Summary of the target(s) before the action
+    pub before: Option<Document>,
+    /// Summary of the target(s) after the action
+    pub after: Option<Document>,
+    pub created_at: DateTime,
+    pub version: i64,
+}
+
+/// Note left by a moderator on an attempt's moderation, so moderators can discuss it before deciding.
+///
+/// Stored in the same database environment as the moderation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorModerationNote {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub exam_attempt_id: ObjectId,
+    pub author_id: ObjectId,
+    pub author_name: String,
+    /// Markdown
+    pub text: String,
+    pub created_at: DateTime,
+    pub version: i64,
+}
+
+/// Time-boxed lease of an attempt's moderation by one moderator, so moderators do not review the same attempt.
+///
+/// `_id` is the attempt id, so an attempt has at most one claim. Claims are removed by a TTL index
+/// once `expiresAt` passes, but the TTL monitor is not immediate, so readers must also filter on `expiresAt`.
+///
+/// Stored in the same database environment as the moderation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorModerationClaim {
+    #[serde(rename = "_id")]
+    pub exam_attempt_id: ObjectId,
+    pub moderator_id: ObjectId,
+    pub moderator_name: String,
+    pub claimed_at: DateTime,
+    pub expires_at: DateTime,
+    pub version: i64,
+}
+
+/// Risk signal contributing to an attempt's suspicion score
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ExamCreatorSuspicionSignalKind {
+    /// Many questions answered faster than they can be read
+    FastAnswers,
+    /// Attempt submitted faster than the exam can reasonably be completed
+    FastSubmission,
+    /// Time between answers is nearly constant, as if automated
+    UniformAnswerTimes,
+    /// Exam window lost focus
+    Blur,
+    /// Exam was exited, and re-entered
+    ExamExit,
+    /// Questions were visited again after being answered
+    Revisits,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorSuspicionSignal {
+    pub kind: ExamCreatorSuspicionSignalKind,
+    /// Points added to the score, out of 100
+    pub points: f64,
+    /// Human readable reason the signal was raised
+    pub explanation: String,
+}
+
+/// Suspicion score of an attempt, computed from its answers and events.
+///
+/// `_id` is the attempt id. Stored in the same database environment as the attempt.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorAttemptSuspicion {
+    #[serde(rename = "_id")]
+    pub exam_attempt_id: ObjectId,
+    /// 0 (no signals) to 100 (most suspicious)
+    pub score: f64,
+    pub signals: Vec<ExamCreatorSuspicionSignal>,
+    pub computed_at: DateTime,
+    pub version: i64,
+}
+
+/// Attempt deletion scheduled by a moderator, executed once `graceUntil` passes, unless cancelled.
+///
+/// `_id` is the attempt id, so an attempt has at most one scheduled deletion. Stored in the same
+/// database environment as the attempt.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorAttemptDeletion {
+    #[serde(rename = "_id")]
+    pub exam_attempt_id: ObjectId,
+    pub database_environment: prisma::ExamCreatorDatabaseEnvironment,
+    pub requested_by_id: ObjectId,
+    pub requested_by_email: String,
+    /// Why the attempt is deleted, kept in the archive
+    #[serde(default)]
+    pub reason: Option<String>,
+    pub scheduled_at: DateTime,
+    pub grace_until: DateTime,
+    /// Set while an executor deletes the attempt. Until it passes, the deletion cannot be
+    /// cancelled, and other executors skip it.
+    pub locked_until: Option<DateTime>,
+    pub version: i64,
+}
+
+/// Deleted attempt, and its moderation, kept so the deletion can be undone.
+///
+/// `_id` is the attempt id. Purged once older than `ATTEMPT_ARCHIVE_RETENTION_IN_DAYS`. Stored in
+/// the same database environment as the attempt.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorArchivedAttempt {
+    #[serde(rename = "_id")]
+    pub exam_attempt_id: ObjectId,
+    pub attempt: prisma::ExamEnvironmentExamAttempt,
+    /// `None` if the attempt was never submitted
+    pub moderation: Option<prisma::ExamEnvironmentExamModeration>,
+    pub database_environment: prisma::ExamCreatorDatabaseEnvironment,
+    pub deleted_by_id: ObjectId,
+    pub deleted_by_email: String,
+    pub reason: Option<String>,
+    pub deleted_at: DateTime,
+    pub version: i64,
+}
+
+/// Time a moderator spent reviewing an attempt, from opening its moderation page to deciding.
+///
+/// A session is open until the moderator decides, and is removed by a TTL index once `expiresAt`
+/// passes, so sessions without a decision do not linger.
+///
+/// Stored in the same database environment as the moderation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorReviewSession {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub moderator_id: ObjectId,
+    pub exam_attempt_id: ObjectId,
+    pub database_environment: prisma::ExamCreatorDatabaseEnvironment,
+    pub started_at: DateTime,
+    /// `None` until the moderator decides
+    pub ended_at: Option<DateTime>,
+    pub expires_at: DateTime,
+    pub version: i64,
+}
+
+/// Reason an attempt was denied, picked by moderators from a catalogue managed by admins.
+///
+/// `_id` is the reason's code. Reasons are disabled, not deleted, so past decisions keep their
+/// meaning. Only stored in the production database, and shared by both database environments.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorDenialReason {
+    #[serde(rename = "_id")]
+    pub code: String,
+    pub label: String,
+    /// Guidance for moderators on when the reason applies
+    pub description: Option<String>,
+    /// Message shown to the student. `{exam}` is replaced with the exam's name.
+    pub student_message: String,
+    /// Disabled reasons can no longer be picked
+    pub disabled: bool,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+    pub version: i64,
+}
+
+/// A moderation decision, with the decision it replaced, kept after the moderation is decided
+/// again. Re-opening a moderation is recorded as a decision with a `Pending` status.
+///
+/// Stored in the same database environment as the moderation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorModerationDecision {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub exam_attempt_id: ObjectId,
+    pub moderation_id: ObjectId,
+    pub moderator_id: ObjectId,
+    pub status: prisma::ExamEnvironmentExamModerationStatus,
+    pub previous_status: prisma::ExamEnvironmentExamModerationStatus,
+    pub previous_moderator_id: Option<ObjectId>,
+    pub previous_feedback: Option<String>,
+    pub feedback: Option<String>,
+    /// Codes of `ExamCreatorDenialReason`s. Only set for denials.
+    pub denial_reason_codes: Vec<String>,
+    /// Rendered from the denial reasons, at the time of the decision
+    pub student_message: Option<String>,
+    /// Admin's reason for changing the status after challenges were awarded
+    pub override_reason: Option<String>,
+    pub decided_at: DateTime,
+    pub version: i64,
+}
+
+/// State of an `ExamCreatorAppeal`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ExamCreatorAppealStatus {
+    /// Waiting for the assignee to resolve it
+    Open,
+    /// The denial was overturned, and the attempt approved
+    Upheld,
+    /// The denial stands
+    Rejected,
+}
+
+/// A student's contest of a denied attempt, opened on their behalf by a moderator.
+///
+/// Assigned to a moderator who did not make the original decision. An attempt has at most one
+/// open appeal. Stored in the same database environment as the moderation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorAppeal {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub exam_attempt_id: ObjectId,
+    pub moderation_id: ObjectId,
+    pub database_environment: prisma::ExamCreatorDatabaseEnvironment,
+    /// The student's reason for appealing
+    pub reason: String,
+    pub opened_by_id: ObjectId,
+    /// Moderator of the appealed decision, who cannot be assigned
+    pub original_moderator_id: Option<ObjectId>,
+    /// `None` if no moderator could be assigned
+    pub assignee_id: Option<ObjectId>,
+    pub assignee_name: Option<String>,
+    pub status: ExamCreatorAppealStatus,
+    /// Explanation of the outcome
+    pub outcome_feedback: Option<String>,
+    pub resolved_by_id: Option<ObjectId>,
+    pub resolved_at: Option<DateTime>,
+    pub created_at: DateTime,
+    pub version: i64,
+}
+
+/// Challenges awarded for an attempt, which have to be revoked downstream, because an admin
+/// changed the attempt's moderation after they were awarded.
+///
+/// Completing the revocation unsets `challengesAwarded` on the moderation. Stored in the same
+/// database environment as the moderation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamCreatorChallengeRevocation {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub exam_attempt_id: ObjectId,
+    pub moderation_id: ObjectId,
+    /// Student the challenges were awarded to
+    pub user_id: ObjectId,
+    pub exam_id: ObjectId,
+    /// Challenges mapped to the exam when the revocation was recorded
+    pub challenge_ids: Vec<ObjectId>,
+    pub previous_status: prisma::ExamEnvironmentExamModerationStatus,
+    pub status: prisma::ExamEnvironmentExamModerationStatus,
+    /// The admin's override reason
+    pub reason: String,
+    pub requested_by_id: ObjectId,
+    /// `None` until the challenges are revoked
+    pub completed_by_id: Option<ObjectId>,
+    pub completed_at: Option<DateTime>,
+    pub created_at: DateTime,
+    pub version: i64,
+}
@@ -1,8 +1,13 @@
-use bson::Document;
-use mongodb::Collection;
+use bson::{Document, doc, oid::ObjectId};
+use mongodb::{Collection, IndexModel, options::IndexOptions};
+use tracing::{info, warn};
 
-use crate::state::{Activity, ServerState, User};
+use crate::{
+    errors::Error,
+    state::{Activity, ServerState, User},
+};
 
+pub mod exam_creator;
 pub mod prisma;
 
 #[derive(Clone, Debug)]
@@ -15,9 +20,156 @@ pub struct Database {
     pub generated_exam: Collection<prisma::ExamEnvironmentGeneratedExam>,
     pub exam_creator_user: Collection<prisma::ExamCreatorUser>,
     pub exam_creator_session: Collection<prisma::ExamCreatorSession>,
+    /// Same collection as `exam_creator_user`, typed to only the access control fields
+    pub exam_creator_user_access: Collection<exam_creator::ExamCreatorUserAccess>,
+    pub exam_creator_audit_log: Collection<exam_creator::ExamCreatorAuditLog>,
+    pub exam_creator_moderation_note: Collection<exam_creator::ExamCreatorModerationNote>,
+    pub exam_creator_moderation_claim: Collection<exam_creator::ExamCreatorModerationClaim>,
+    pub exam_creator_attempt_suspicion: Collection<exam_creator::ExamCreatorAttemptSuspicion>,
+    pub exam_creator_review_session: Collection<exam_creator::ExamCreatorReviewSession>,
+    pub exam_creator_attempt_deletion: Collection<exam_creator::ExamCreatorAttemptDeletion>,
+    pub exam_creator_archived_attempt: Collection<exam_creator::ExamCreatorArchivedAttempt>,
+    pub exam_creator_denial_reason: Collection<exam_creator::ExamCreatorDenialReason>,
+    pub exam_creator_moderation_decision: Collection<exam_creator::ExamCreatorModerationDecision>,
+    pub exam_creator_appeal: Collection<exam_creator::ExamCreatorAppeal>,
+    pub exam_creator_challenge_revocation: Collection<exam_creator::ExamCreatorChallengeRevocation>,
     pub exam_environment_exam_moderation: Collection<prisma::ExamEnvironmentExamModeration>,
 }
 
+impl Database {
+    /// Creates the indexes Exam Creator relies on, if they do not exist.
+    ///
+    /// Failures are logged, and not returned, because the server can still run without them.
+    pub async fn create_indexes(&self) {
+        match self
+            .exam_creator_moderation_claim
+            .create_index(expiry_index())
+            .await
+        {
+            Ok(index) => info!(index = %index.index_name, "moderation claim index created"),
+            Err(e) => warn!(error = ?e, "unable to create moderation claim index"),
+        }
+
+        match self
+            .exam_creator_review_session
+            .create_index(expiry_index())
+            .await
+        {
+            Ok(index) => info!(index = %index.index_name, "review session expiry index created"),
+            Err(e) => warn!(error = ?e, "unable to create review session expiry index"),
+        }
+
+        // Open sessions are looked up by moderator and attempt on every decision
+        let open_session = IndexModel::builder()
+            .keys(doc! {"moderatorId": 1, "examAttemptId": 1, "endedAt": 1})
+            .build();
+        match self
+            .exam_creator_review_session
+            .create_index(open_session)
+            .await
+        {
+            Ok(index) => info!(index = %index.index_name, "review session index created"),
+            Err(e) => warn!(error = ?e, "unable to create review session index"),
+        }
+
+        // Due deletions are polled by every executor
+        let due_deletion = IndexModel::builder().keys(doc! {"graceUntil": 1}).build();
+        match self
+            .exam_creator_attempt_deletion
+            .create_index(due_deletion)
+            .await
+        {
+            Ok(index) => info!(index = %index.index_name, "attempt deletion index created"),
+            Err(e) => warn!(error = ?e, "unable to create attempt deletion index"),
+        }
+
+        // Archived attempts are listed, and purged, by deletion date
+        let archived_at = IndexModel::builder().keys(doc! {"deletedAt": 1}).build();
+        match self
+            .exam_creator_archived_attempt
+            .create_index(archived_at)
+            .await
+        {
+            Ok(index) => info!(index = %index.index_name, "archived attempt index created"),
+            Err(e) => warn!(error = ?e, "unable to create archived attempt index"),
+        }
+
+        // Decisions are listed per attempt
+        let attempt_decisions = IndexModel::builder()
+            .keys(doc! {"examAttemptId": 1, "decidedAt": -1})
+            .build();
+        match self
+            .exam_creator_moderation_decision
+            .create_index(attempt_decisions)
+            .await
+        {
+            Ok(index) => info!(index = %index.index_name, "moderation decision index created"),
+            Err(e) => warn!(error = ?e, "unable to create moderation decision index"),
+        }
+
+        // Decisions are reported on by decision date
+        let decided_at = IndexModel::builder().keys(doc! {"decidedAt": 1}).build();
+        match self
+            .exam_creator_moderation_decision
+            .create_index(decided_at)
+            .await
+        {
+            Ok(index) => info!(index = %index.index_name, "moderation decision date index created"),
+            Err(e) => warn!(error = ?e, "unable to create moderation decision date index"),
+        }
+
+        // An attempt has at most one open appeal
+        let open_appeal = IndexModel::builder()
+            .keys(doc! {"examAttemptId": 1})
+            .options(
+                IndexOptions::builder()
+                    .unique(true)
+                    .partial_filter_expression(doc! {"status": "Open"})
+                    .name("examAttemptId_open".to_string())
+                    .build(),
+            )
+            .build();
+        match self.exam_creator_appeal.create_index(open_appeal).await {
+            Ok(index) => info!(index = %index.index_name, "open appeal index created"),
+            Err(e) => warn!(error = ?e, "unable to create open appeal index"),
+        }
+
+        // The appeals queue is filtered by status and assignee, oldest first
+        let appeal_queue = IndexModel::builder()
+            .keys(doc! {"status": 1, "assigneeId": 1, "createdAt": 1})
+            .build();
+        match self.exam_creator_appeal.create_index(appeal_queue).await {
+            Ok(index) => info!(index = %index.index_name, "appeal queue index created"),
+            Err(e) => warn!(error = ?e, "unable to create appeal queue index"),
+        }
+
+        // Pending revocations are listed oldest first
+        let pending_revocation = IndexModel::builder()
+            .keys(doc! {"completedAt": 1, "createdAt": 1})
+            .build();
+        match self
+            .exam_creator_challenge_revocation
+            .create_index(pending_revocation)
+            .await
+        {
+            Ok(index) => info!(index = %index.index_name, "challenge revocation index created"),
+            Err(e) => warn!(error = ?e, "unable to create challenge revocation index"),
+        }
+    }
+}
+
+/// Removes documents once their `expiresAt` passes
+fn expiry_index() -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! {"expiresAt": 1})
+        .options(
+            IndexOptions::builder()
+                .expire_after(std::time::Duration::from_secs(0))
+                .build(),
+        )
+        .build()
+}
+
 impl prisma::ExamCreatorUser {
     pub fn to_session(&self, users: &Vec<User>) -> User {
         if let Some(user) = users.iter().find(|u| u.email == self.email) {
@@ -46,3 +198,51 @@ pub fn database_environment<'a>(
         prisma::ExamCreatorDatabaseEnvironment::Production => &state.production_database,
     }
 }
+
+/// Get the access control fields of a user.
+///
+/// Users are only stored in the production database.
+pub async fn user_access(
+    state: &ServerState,
+    user_id: ObjectId,
+) -> Result<Option<exam_creator::ExamCreatorUserAccess>, Error> {
+    let access = state
+        .production_database
+        .exam_creator_user_access
+        .find_one(doc! {"_id": user_id})
+        .projection(doc! {"_id": true, "roles": true, "disabled": true})
+        .await?;
+
+    Ok(access)
+}
+
+/// Insert a new, enabled, user, granted `roles`.
+///
+/// The roles are part of the inserted document, so the user is never stored without them.
+pub async fn insert_user_with_roles(
+    state: &ServerState,
+    user: &prisma::ExamCreatorUser,
+    roles: &[exam_creator::ExamCreatorRole],
+) -> Result<(), Error> {
+    let mut document = bson::serialize_to_document(user)?;
+    document.insert("roles", bson::serialize_to_bson(roles)?);
+    document.insert("disabled", false);
+    state
+        .production_database
+        .exam_creator_user
+        .clone_with_type::<Document>()
+        .insert_one(document)
+        .await?;
+
+    Ok(())
+}
+
+/// Get the roles granted to a user.
+pub async fn user_roles(
+    state: &ServerState,
+    user_id: ObjectId,
+) -> Result<Vec<exam_creator::ExamCreatorRole>, Error> {
+    let access = user_access(state, user_id).await?;
+
+    Ok(access.map(|a| a.roles).unwrap_or_default())
+}
@@ -0,0 +1,428 @@
+//! Durable queue of attempt deletions, executed once their grace period passes.
+//!
+//! Scheduled deletions are stored, so they survive restarts, and can be cancelled from any
+//! instance. Every instance runs an executor, which locks a deletion before executing it.
+//!
+//! Executed deletions move the attempt, and its moderation, into an archive, from which they can
+//! be restored until purged after `ATTEMPT_ARCHIVE_RETENTION_IN_DAYS`.
+use std::time::Duration;
+
+use http::StatusCode;
+use mongodb::bson::{DateTime, doc, oid::ObjectId};
+use tracing::{error, info, warn};
+
+use crate::{
+    audit::{self, AuditEntry},
+    database::{
+        Database, database_environment,
+        exam_creator::{
+            ExamCreatorArchivedAttempt, ExamCreatorAttemptDeletion, ExamCreatorAuditAction,
+        },
+        prisma,
+    },
+    errors::Error,
+    moderation::is_duplicate_key,
+    state::ServerState,
+};
+
+/// Grace period before a scheduled attempt deletion is executed, allowing an undo
+pub const DELETE_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// How long an executor holds a deletion. If the executor fails, or stops, within the lease, the
+/// deletion is retried once it passes.
+const EXECUTION_LEASE: Duration = Duration::from_secs(60);
+
+fn after(now: DateTime, duration: Duration) -> DateTime {
+    DateTime::from_millis(now.timestamp_millis() + duration.as_millis() as i64)
+}
+
+fn deletion_in_progress(attempt_id: ObjectId) -> Error {
+    Error::Server(
+        StatusCode::CONFLICT,
+        format!("deletion of attempt {attempt_id} is already in progress"),
+    )
+}
+
+/// Schedules deletion of an attempt (and its moderation) after `DELETE_GRACE_PERIOD`, replacing
+/// any existing schedule of the attempt.
+pub async fn schedule(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+    reason: Option<String>,
+) -> Result<ExamCreatorAttemptDeletion, Error> {
+    let database = database_environment(server_state, exam_creator_user);
+
+    let now = DateTime::now();
+    let deletion = ExamCreatorAttemptDeletion {
+        exam_attempt_id: attempt_id,
+        database_environment: exam_creator_user.settings.database_environment.clone(),
+        requested_by_id: exam_creator_user.id,
+        requested_by_email: exam_creator_user.email.clone(),
+        reason: reason
+            .map(|reason| reason.trim().to_string())
+            .filter(|reason| !reason.is_empty()),
+        scheduled_at: now,
+        grace_until: after(now, DELETE_GRACE_PERIOD),
+        locked_until: None,
+        version: 1,
+    };
+
+    // A locked deletion does not match, so the upsert conflicts on `_id`
+    match database
+        .exam_creator_attempt_deletion
+        .replace_one(
+            doc! {"_id": attempt_id, "lockedUntil": {"$not": {"$gt": now}}},
+            &deletion,
+        )
+        .upsert(true)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key(&e) => return Err(deletion_in_progress(attempt_id)),
+        Err(e) => return Err(e.into()),
+    }
+
+    deletion_metric(&deletion.database_environment, "scheduled");
+    audit::record(
+        server_state,
+        exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::AttemptDeletionSchedule,
+            vec![attempt_id],
+        )
+        .database_environment(deletion.database_environment.clone())
+        .after(doc! {
+            "graceSeconds": DELETE_GRACE_PERIOD.as_secs() as i64,
+            "graceUntil": deletion.grace_until,
+            "reason": deletion.reason.clone(),
+        }),
+    )
+    .await;
+
+    Ok(deletion)
+}
+
+/// Cancels a scheduled deletion of an attempt.
+///
+/// Idempotent: cancelling when nothing is scheduled (e.g. the grace period already elapsed, and
+/// the attempt was deleted) is a no-op, not an error. Returns whether a deletion was cancelled.
+pub async fn cancel(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+) -> Result<bool, Error> {
+    let database = database_environment(server_state, exam_creator_user);
+
+    let now = DateTime::now();
+    let result = database
+        .exam_creator_attempt_deletion
+        .delete_one(doc! {"_id": attempt_id, "lockedUntil": {"$not": {"$gt": now}}})
+        .await?;
+
+    if result.deleted_count == 0 {
+        let locked = database
+            .exam_creator_attempt_deletion
+            .count_documents(doc! {"_id": attempt_id})
+            .await?
+            > 0;
+        if locked {
+            return Err(deletion_in_progress(attempt_id));
+        }
+
+        info!(%attempt_id, "no pending deletion to cancel");
+        return Ok(false);
+    }
+
+    deletion_metric(
+        &exam_creator_user.settings.database_environment,
+        "cancelled",
+    );
+    audit::record(
+        server_state,
+        exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::AttemptDeletionCancel,
+            vec![attempt_id],
+        )
+        .database_environment(exam_creator_user.settings.database_environment.clone()),
+    )
+    .await;
+
+    Ok(true)
+}
+
+/// Periodically executes deletions whose grace period has passed, in both database environments.
+///
+/// Deletions scheduled before a restart are executed on the first run.
+pub async fn execute_due_deletions(state: ServerState, interval: Duration) {
+    loop {
+        for (database_environment, database) in [
+            (
+                prisma::ExamCreatorDatabaseEnvironment::Production,
+                &state.production_database,
+            ),
+            (
+                prisma::ExamCreatorDatabaseEnvironment::Staging,
+                &state.staging_database,
+            ),
+        ] {
+            if let Err(e) = execute_due(&state, &database_environment, database).await {
+                error!(
+                    error = ?e,
+                    database_environment = database_environment.to_string(),
+                    "unable to execute attempt deletions"
+                );
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn execute_due(
+    state: &ServerState,
+    database_environment: &prisma::ExamCreatorDatabaseEnvironment,
+    database: &Database,
+) -> Result<(), Error> {
+    loop {
+        let now = DateTime::now();
+        let Some(deletion) = database
+            .exam_creator_attempt_deletion
+            .find_one_and_update(
+                doc! {
+                    "graceUntil": {"$lte": now},
+                    "lockedUntil": {"$not": {"$gt": now}},
+                },
+                doc! {"$set": {"lockedUntil": after(now, EXECUTION_LEASE)}},
+            )
+            .sort(doc! {"graceUntil": 1})
+            .await?
+        else {
+            return Ok(());
+        };
+        let attempt_id = deletion.exam_attempt_id;
+
+        let outcome = match archive_attempt(database, &deletion).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                // Retried once the lease passes
+                error!(error = ?e, %attempt_id, "scheduled delete failed");
+                deletion_metric(database_environment, "failed");
+                continue;
+            }
+        };
+
+        database
+            .exam_creator_attempt_deletion
+            .delete_one(doc! {"_id": attempt_id})
+            .await?;
+
+        if outcome == ArchiveOutcome::AlreadyDeleted {
+            info!(%attempt_id, "attempt already deleted, nothing to archive");
+            deletion_metric(database_environment, "noop");
+            continue;
+        }
+
+        state
+            .metrics_cache
+            .invalidate_attempts(database_environment);
+        deletion_metric(database_environment, "executed");
+        audit::insert(
+            &state.production_database.exam_creator_audit_log,
+            Some(deletion.requested_by_id),
+            &deletion.requested_by_email,
+            AuditEntry::new(ExamCreatorAuditAction::AttemptDelete, vec![attempt_id])
+                .database_environment(database_environment.clone()),
+        )
+        .await;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ArchiveOutcome {
+    /// The attempt is in the archive, archived now, or by a previous, interrupted, execution
+    Archived,
+    /// The attempt was deleted without being archived, so there is nothing to archive
+    AlreadyDeleted,
+}
+
+/// Move an attempt and its moderation (0-1 records) into the archive. Already-archived records
+/// are not an error: the desired end state is reached either way.
+async fn archive_attempt(
+    database: &Database,
+    deletion: &ExamCreatorAttemptDeletion,
+) -> Result<ArchiveOutcome, Error> {
+    let attempt_id = deletion.exam_attempt_id;
+
+    let Some(attempt) = database
+        .exam_attempt
+        .find_one(doc! { "_id": attempt_id })
+        .await?
+    else {
+        let archived = database
+            .exam_creator_archived_attempt
+            .count_documents(doc! {"_id": attempt_id})
+            .await?
+            > 0;
+        return Ok(if archived {
+            ArchiveOutcome::Archived
+        } else {
+            ArchiveOutcome::AlreadyDeleted
+        });
+    };
+    let moderation = database
+        .exam_environment_exam_moderation
+        .find_one(doc! { "examAttemptId": attempt_id })
+        .await?;
+
+    let archived_attempt = ExamCreatorArchivedAttempt {
+        exam_attempt_id: attempt_id,
+        attempt,
+        moderation,
+        database_environment: deletion.database_environment.clone(),
+        deleted_by_id: deletion.requested_by_id,
+        deleted_by_email: deletion.requested_by_email.clone(),
+        reason: deletion.reason.clone(),
+        deleted_at: DateTime::now(),
+        version: 1,
+    };
+    // A retried deletion keeps the first archive, which has the moderation, even if the
+    // moderation was deleted before the retry
+    match database
+        .exam_creator_archived_attempt
+        .insert_one(&archived_attempt)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key(&e) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // Delete moderation first (0-1 records). Not-found is fine.
+    database
+        .exam_environment_exam_moderation
+        .delete_one(doc! { "examAttemptId": attempt_id })
+        .await?;
+
+    database
+        .exam_attempt
+        .delete_one(doc! { "_id": attempt_id })
+        .await?;
+
+    Ok(ArchiveOutcome::Archived)
+}
+
+/// Restores an archived attempt, and its moderation, removing it from the archive.
+///
+/// Retrying a partially failed restore completes it.
+pub async fn restore(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+) -> Result<ExamCreatorArchivedAttempt, Error> {
+    let database = database_environment(server_state, exam_creator_user);
+
+    let archived_attempt = database
+        .exam_creator_archived_attempt
+        .find_one(doc! {"_id": attempt_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::NOT_FOUND,
+            format!("archived attempt non-existent: {attempt_id}"),
+        ))?;
+
+    // A previous restore may have failed after inserting the attempt, or its moderation. Both keep
+    // their archived `_id`, so a duplicate key means it is already restored.
+    match database
+        .exam_attempt
+        .insert_one(&archived_attempt.attempt)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key(&e) => {
+            warn!(%attempt_id, "archived attempt already restored");
+        }
+        Err(e) => return Err(e.into()),
+    }
+    if let Some(moderation) = &archived_attempt.moderation {
+        match database
+            .exam_environment_exam_moderation
+            .insert_one(moderation)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) if is_duplicate_key(&e) => {
+                warn!(%attempt_id, "archived moderation already restored");
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    database
+        .exam_creator_archived_attempt
+        .delete_one(doc! {"_id": attempt_id})
+        .await?;
+
+    server_state
+        .metrics_cache
+        .invalidate_attempts(&exam_creator_user.settings.database_environment);
+    audit::record(
+        server_state,
+        exam_creator_user,
+        AuditEntry::new(ExamCreatorAuditAction::AttemptRestore, vec![attempt_id])
+            .database_environment(exam_creator_user.settings.database_environment.clone())
+            .before(doc! {
+                "deletedById": archived_attempt.deleted_by_id,
+                "deletedAt": archived_attempt.deleted_at,
+                "reason": archived_attempt.reason.clone(),
+            }),
+    )
+    .await;
+
+    Ok(archived_attempt)
+}
+
+/// Periodically purges archived attempts older than `ATTEMPT_ARCHIVE_RETENTION_IN_DAYS`, in both
+/// database environments.
+pub async fn purge_archived_attempts(state: ServerState, interval: Duration) {
+    let retention =
+        Duration::from_secs(state.env_vars.attempt_archive_retention_in_days * 24 * 60 * 60);
+
+    loop {
+        let purge_before = DateTime::from_millis(
+            DateTime::now().timestamp_millis() - retention.as_millis() as i64,
+        );
+
+        for (database_environment, database) in [
+            ("production", &state.production_database),
+            ("staging", &state.staging_database),
+        ] {
+            match database
+                .exam_creator_archived_attempt
+                .delete_many(doc! {"deletedAt": {"$lt": purge_before}})
+                .await
+            {
+                Ok(result) if result.deleted_count > 0 => info!(
+                    database_environment,
+                    purged = result.deleted_count,
+                    "purged archived attempts"
+                ),
+                Ok(_) => {}
+                Err(e) => {
+                    error!(error = ?e, database_environment, "unable to purge archived attempts")
+                }
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+fn deletion_metric(
+    database_environment: &prisma::ExamCreatorDatabaseEnvironment,
+    outcome: &'static str,
+) {
+    sentry::metrics::counter("exam.attempt.deletion", 1)
+        .attribute("outcome", outcome)
+        .attribute("database_environment", database_environment.to_string())
+        .capture();
+}
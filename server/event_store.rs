@@ -0,0 +1,692 @@
+//! Backends attempt events are read from, chosen by `EVENT_STORE`.
+use std::{collections::HashMap, fmt, io::ErrorKind, path::PathBuf, sync::Arc};
+
+use chrono::{SecondsFormat, Utc};
+use futures_util::future::BoxFuture;
+use http::StatusCode;
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::Deserialize;
+use supabase_rs::SupabaseClient;
+use tracing::warn;
+
+use crate::{
+    config::{EventKind, EventStoreConfig},
+    errors::Error,
+};
+
+/// Number of attempt ids sent to Supabase per request, to keep the query string short, and the
+/// counts of a request (one row per attempt, per kind) under PostgREST's default `max-rows` of
+/// 1000
+const SUPABASE_ATTEMPT_IDS_PER_REQUEST: usize = 100;
+
+/// Source of the rows of the `events` table.
+///
+/// Rows are returned unvalidated. See `routes::events::query_events`.
+pub trait EventStore: Send + Sync {
+    /// Rows matching `filter`, ordered by timestamp, then id
+    fn events<'a>(
+        &'a self,
+        filter: &'a EventFilter,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, Error>>;
+
+    /// Number of rows of each known kind, of each attempt, in the attempts and time window of
+    /// `filter`. `kinds`, `after`, and `limit` are ignored.
+    fn counts<'a>(
+        &'a self,
+        filter: &'a EventFilter,
+    ) -> BoxFuture<'a, Result<Vec<EventCount>, Error>>;
+}
+
+/// Rows of the `events` table to read
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    pub attempt_ids: Vec<ObjectId>,
+    /// Inclusive
+    pub from: Option<DateTime>,
+    /// Exclusive
+    pub to: Option<DateTime>,
+    /// All kinds if empty
+    pub kinds: Vec<EventKind>,
+    /// Only rows ordered after the cursor
+    pub after: Option<EventCursor>,
+    /// All rows if `None`
+    pub limit: Option<usize>,
+}
+
+impl EventFilter {
+    pub fn attempt(attempt_id: ObjectId) -> Self {
+        Self {
+            attempt_ids: vec![attempt_id],
+            ..Default::default()
+        }
+    }
+}
+
+/// Position of a row, in the order of `EventStore::events`.
+///
+/// Formatted as `<RFC 3339 timestamp>_<event id>`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EventCursor {
+    /// Full precision, as stored. `DateTime` only keeps milliseconds, which would skip, or repeat,
+    /// rows within the same millisecond.
+    pub timestamp: chrono::DateTime<Utc>,
+    pub id: String,
+}
+
+impl EventCursor {
+    /// Position of `row`, `None` if it does not have an id, or an RFC 3339 timestamp
+    pub fn of(row: &serde_json::Value) -> Option<Self> {
+        let timestamp = row.get("timestamp")?.as_str()?;
+        let id = row.get("id")?.as_str()?;
+        Some(Self {
+            timestamp: chrono::DateTime::parse_from_rfc3339(timestamp)
+                .ok()?
+                .with_timezone(&Utc),
+            id: id.to_string(),
+        })
+    }
+
+    pub fn parse(cursor: &str) -> Option<Self> {
+        let (timestamp, id) = cursor.split_once('_')?;
+        Some(Self {
+            timestamp: chrono::DateTime::parse_from_rfc3339(timestamp)
+                .ok()?
+                .with_timezone(&Utc),
+            id: id.to_string(),
+        })
+    }
+
+    fn timestamp_string(&self) -> String {
+        self.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    }
+}
+
+impl fmt::Display for EventCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.timestamp_string(), self.id)
+    }
+}
+
+/// Number of rows of a kind, of an attempt
+#[derive(Clone, Debug)]
+pub struct EventCount {
+    pub attempt_id: ObjectId,
+    pub kind: EventKind,
+    pub count: usize,
+}
+
+pub fn new(config: &EventStoreConfig) -> Result<Arc<dyn EventStore>, Error> {
+    let event_store: Arc<dyn EventStore> = match config {
+        EventStoreConfig::Supabase { url, key } => Arc::new(SupabaseEventStore {
+            client: SupabaseClient::new(url, key)?,
+            http_client: reqwest::Client::new(),
+            url: url.clone(),
+            key: key.clone(),
+        }),
+        EventStoreConfig::File { dir } => Arc::new(FileEventStore { dir: dir.clone() }),
+    };
+
+    Ok(event_store)
+}
+
+pub struct SupabaseEventStore {
+    pub client: SupabaseClient,
+    /// `client` cannot call database functions, so `event_counts` is called directly
+    pub http_client: reqwest::Client,
+    /// Supabase Project URL
+    pub url: String,
+    /// Supabase Private Key
+    pub key: String,
+}
+
+impl EventStore for SupabaseEventStore {
+    fn events<'a>(
+        &'a self,
+        filter: &'a EventFilter,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, Error>> {
+        Box::pin(async move {
+            let from = filter.from.map(|d| d.try_to_rfc3339_string()).transpose()?;
+            let to = filter.to.map(|d| d.try_to_rfc3339_string()).transpose()?;
+            let kinds: Vec<&str> = filter.kinds.iter().map(EventKind::name).collect();
+            let after = filter
+                .after
+                .as_ref()
+                .map(|after| (after.timestamp_string(), after));
+
+            let mut rows = vec![];
+            for attempt_ids in filter.attempt_ids.chunks(SUPABASE_ATTEMPT_IDS_PER_REQUEST) {
+                let attempt_ids: Vec<String> = attempt_ids.iter().map(|id| id.to_hex()).collect();
+                let attempt_ids: Vec<&str> = attempt_ids.iter().map(String::as_str).collect();
+
+                let query = || {
+                    let mut query = self.client.from("events").in_("attempt_id", &attempt_ids);
+                    if !kinds.is_empty() {
+                        query = query.in_("kind", &kinds);
+                    }
+                    if let Some(from) = &from {
+                        query = query.gte("timestamp", from);
+                    }
+                    if let Some(to) = &to {
+                        query = query.lt("timestamp", to);
+                    }
+                    query
+                };
+
+                // Rows after the cursor are at its timestamp with a greater id, or at a later
+                // timestamp. PostgREST filters cannot be or'ed here, so they are two queries.
+                let mut chunk = vec![];
+                if let Some((timestamp, after)) = &after {
+                    let mut ties = query()
+                        .eq("timestamp", timestamp)
+                        .gt("id", &after.id)
+                        .order("id", true);
+                    if let Some(limit) = filter.limit {
+                        ties = ties.limit(limit);
+                    }
+                    chunk.extend(ties.execute().await.map_err(supabase_error)?);
+                }
+
+                let remaining = filter.limit.map(|limit| limit.saturating_sub(chunk.len()));
+                if remaining != Some(0) {
+                    let mut later = query().order("timestamp", true);
+                    if let Some((timestamp, _)) = &after {
+                        later = later.gt("timestamp", timestamp);
+                    }
+                    if let Some(remaining) = remaining {
+                        later = later.limit(remaining);
+                    }
+                    let mut later = later.execute().await.map_err(supabase_error)?;
+
+                    // Rows are only ordered by timestamp, so the limit can cut the rows of the
+                    // last timestamp short of lower ids. The rows of the last timestamp are
+                    // re-read in full, and cut once ordered by id.
+                    let cut = remaining == Some(later.len());
+                    if let Some(last) = later.last().and_then(EventCursor::of).filter(|_| cut) {
+                        later.retain(|row| {
+                            EventCursor::of(row).is_none_or(|c| c.timestamp != last.timestamp)
+                        });
+                        let ties = query()
+                            .eq("timestamp", &last.timestamp_string())
+                            .order("id", true)
+                            .execute()
+                            .await
+                            .map_err(supabase_error)?;
+                        later.extend(ties);
+                    }
+                    chunk.extend(later);
+                }
+
+                rows.extend(chunk);
+            }
+
+            order_rows(&mut rows, filter.limit);
+            Ok(rows)
+        })
+    }
+
+    fn counts<'a>(
+        &'a self,
+        filter: &'a EventFilter,
+    ) -> BoxFuture<'a, Result<Vec<EventCount>, Error>> {
+        Box::pin(async move {
+            let from = filter.from.map(|d| d.try_to_rfc3339_string()).transpose()?;
+            let to = filter.to.map(|d| d.try_to_rfc3339_string()).transpose()?;
+
+            let kinds: Vec<&str> = EventKind::ALL.iter().map(EventKind::name).collect();
+            let url = format!(
+                "{}/rest/v1/rpc/event_counts",
+                self.url.trim_end_matches('/')
+            );
+
+            // Counted by the database, as reading the rows is capped at PostgREST's `max-rows`
+            let mut counts = vec![];
+            for attempt_ids in filter.attempt_ids.chunks(SUPABASE_ATTEMPT_IDS_PER_REQUEST) {
+                let attempt_ids: Vec<String> = attempt_ids.iter().map(|id| id.to_hex()).collect();
+
+                let rows: Vec<EventCountRow> = self
+                    .http_client
+                    .post(&url)
+                    .header("apikey", &self.key)
+                    .bearer_auth(&self.key)
+                    .json(&serde_json::json!({
+                        "attempt_ids": attempt_ids,
+                        "kinds": kinds,
+                        "window_from": from,
+                        "window_to": to,
+                    }))
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .map_err(supabase_error)?
+                    .json()
+                    .await
+                    .map_err(supabase_error)?;
+
+                counts.extend(rows.into_iter().filter_map(|row| {
+                    Some(EventCount {
+                        attempt_id: ObjectId::parse_str(&row.attempt_id).ok()?,
+                        kind: EventKind::from_name(&row.kind)?,
+                        count: row.count,
+                    })
+                }));
+            }
+
+            Ok(counts)
+        })
+    }
+}
+
+/// Row returned by the `event_counts` database function
+#[derive(Deserialize)]
+struct EventCountRow {
+    attempt_id: String,
+    kind: String,
+    count: usize,
+}
+
+fn supabase_error(e: impl fmt::Display) -> Error {
+    Error::Server(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("supabase http error: {e}"),
+    )
+}
+
+/// Reads events from `<dir>/<attempt_id>.json`, in the format of the client's mock events.
+///
+/// Attempts without a file have no events. Rows without an id, or an RFC 3339 timestamp, cannot be
+/// ordered, so are skipped.
+pub struct FileEventStore {
+    pub dir: PathBuf,
+}
+
+impl FileEventStore {
+    /// Rows of the attempts, and in the time window, of `filter`, with their position
+    async fn rows(
+        &self,
+        filter: &EventFilter,
+    ) -> Result<Vec<(EventCursor, serde_json::Value)>, Error> {
+        let mut rows = vec![];
+        for attempt_id in &filter.attempt_ids {
+            let path = self.dir.join(format!("{}.json", attempt_id.to_hex()));
+            let contents = match tokio::fs::read(&path).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(Error::Server(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("unable to read {path:?}: {e}"),
+                    ));
+                }
+            };
+
+            let attempt_rows: Vec<serde_json::Value> =
+                serde_json::from_slice(&contents).map_err(|e| {
+                    Error::Server(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("{path:?} is not an array of events: {e}"),
+                    )
+                })?;
+            for row in attempt_rows {
+                let Some(cursor) = EventCursor::of(&row) else {
+                    warn!(?path, id = ?row.get("id"), "skipping event without id, or timestamp");
+                    continue;
+                };
+                let in_window = filter
+                    .from
+                    .is_none_or(|from| cursor.timestamp >= from.to_chrono())
+                    && filter.to.is_none_or(|to| cursor.timestamp < to.to_chrono());
+                if in_window {
+                    rows.push((cursor, row));
+                }
+            }
+        }
+
+        Ok(rows)
+    }
+}
+
+impl EventStore for FileEventStore {
+    fn events<'a>(
+        &'a self,
+        filter: &'a EventFilter,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, Error>> {
+        Box::pin(async move {
+            let mut rows: Vec<serde_json::Value> = self
+                .rows(filter)
+                .await?
+                .into_iter()
+                .filter(|(cursor, row)| {
+                    let kind_matches = filter.kinds.is_empty()
+                        || row_kind(row).is_some_and(|kind| filter.kinds.contains(&kind));
+                    kind_matches && filter.after.as_ref().is_none_or(|after| cursor > after)
+                })
+                .map(|(_, row)| row)
+                .collect();
+
+            order_rows(&mut rows, filter.limit);
+            Ok(rows)
+        })
+    }
+
+    fn counts<'a>(
+        &'a self,
+        filter: &'a EventFilter,
+    ) -> BoxFuture<'a, Result<Vec<EventCount>, Error>> {
+        Box::pin(async move {
+            let rows: Vec<serde_json::Value> = self
+                .rows(filter)
+                .await?
+                .into_iter()
+                .map(|(_, row)| row)
+                .collect();
+
+            Ok(count_rows(&rows))
+        })
+    }
+}
+
+/// Orders `rows` by timestamp, then id, keeping the first `limit`
+fn order_rows(rows: &mut Vec<serde_json::Value>, limit: Option<usize>) {
+    rows.sort_by_cached_key(EventCursor::of);
+    if let Some(limit) = limit {
+        rows.truncate(limit);
+    }
+}
+
+fn row_kind(row: &serde_json::Value) -> Option<EventKind> {
+    row.get("kind")
+        .and_then(|kind| kind.as_str())
+        .and_then(EventKind::from_name)
+}
+
+/// Counts `rows` of each known kind, of each attempt. Other rows are not counted.
+fn count_rows(rows: &[serde_json::Value]) -> Vec<EventCount> {
+    let mut counts: HashMap<(ObjectId, EventKind), usize> = HashMap::new();
+    for row in rows {
+        let attempt_id = row
+            .get("attempt_id")
+            .and_then(|id| id.as_str())
+            .and_then(|id| ObjectId::parse_str(id).ok());
+        if let (Some(attempt_id), Some(kind)) = (attempt_id, row_kind(row)) {
+            *counts.entry((attempt_id, kind)).or_default() += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|((attempt_id, kind), count)| EventCount {
+            attempt_id,
+            kind,
+            count,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use axum::{Json, Router, routing::post};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    /// PostgREST's default `max-rows`
+    const MAX_ROWS: usize = 1000;
+
+    /// Serves `event_counts` over `events` as Supabase does, returning at most `MAX_ROWS` rows
+    async fn supabase(events: Vec<Value>) -> SupabaseEventStore {
+        let events = Arc::new(events);
+        let app = Router::new().route(
+            "/rest/v1/rpc/event_counts",
+            post(move |Json(body): Json<Value>| {
+                let events = Arc::clone(&events);
+                async move {
+                    let attempt_ids = body["attempt_ids"].as_array().unwrap();
+                    let kinds = body["kinds"].as_array().unwrap();
+                    let mut counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+                    for event in events.iter().filter(|event| {
+                        attempt_ids.contains(&event["attempt_id"]) && kinds.contains(&event["kind"])
+                    }) {
+                        let attempt_id = event["attempt_id"].as_str().unwrap().to_string();
+                        let kind = event["kind"].as_str().unwrap().to_string();
+                        *counts.entry((attempt_id, kind)).or_default() += 1;
+                    }
+
+                    let rows: Vec<Value> = counts
+                        .into_iter()
+                        .map(|((attempt_id, kind), count)| {
+                            json!({"attempt_id": attempt_id, "kind": kind, "count": count})
+                        })
+                        .take(MAX_ROWS)
+                        .collect();
+                    Json(rows)
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let key = "key".to_string();
+        SupabaseEventStore {
+            client: SupabaseClient::new(&url, &key).unwrap(),
+            http_client: reqwest::Client::new(),
+            url,
+            key,
+        }
+    }
+
+    fn event(attempt_id: ObjectId, kind: &str) -> Value {
+        json!({
+            "id": ObjectId::new().to_hex(),
+            "attempt_id": attempt_id.to_hex(),
+            "kind": kind,
+            "timestamp": "2026-01-01T00:00:00Z",
+        })
+    }
+
+    fn count(counts: &[EventCount], attempt_id: ObjectId, kind: EventKind) -> Option<usize> {
+        counts
+            .iter()
+            .find(|c| c.attempt_id == attempt_id && c.kind == kind)
+            .map(|c| c.count)
+    }
+
+    #[tokio::test]
+    async fn supabase_counts_past_the_row_limit() {
+        let attempt_id = ObjectId::new();
+        let other_attempt_id = ObjectId::new();
+        let mut events = vec![];
+        events.extend((0..1200).map(|_| event(attempt_id, "FOCUS")));
+        events.extend((0..300).map(|_| event(attempt_id, "BLUR")));
+        events.extend((0..5).map(|_| event(attempt_id, "NOT_A_KIND")));
+        events.extend((0..2).map(|_| event(other_attempt_id, "EXAM_EXIT")));
+        let store = supabase(events).await;
+
+        let counts = store
+            .counts(&EventFilter::attempt(attempt_id))
+            .await
+            .unwrap();
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(count(&counts, attempt_id, EventKind::Focus), Some(1200));
+        assert_eq!(count(&counts, attempt_id, EventKind::Blur), Some(300));
+    }
+
+    #[tokio::test]
+    async fn supabase_counts_more_attempts_and_kinds_than_the_row_limit() {
+        let attempt_ids: Vec<ObjectId> = (0..150).map(|_| ObjectId::new()).collect();
+        let events = attempt_ids
+            .iter()
+            .flat_map(|&attempt_id| {
+                EventKind::ALL
+                    .iter()
+                    .map(move |kind| event(attempt_id, kind.name()))
+            })
+            .collect();
+        let store = supabase(events).await;
+
+        let counts = store
+            .counts(&EventFilter {
+                attempt_ids: attempt_ids.clone(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(counts.len(), attempt_ids.len() * EventKind::ALL.len());
+        assert!(counts.iter().all(|c| c.count == 1));
+    }
+
+    /// Directory of event files, removed once dropped
+    struct EventDir(PathBuf);
+
+    impl EventDir {
+        fn new(attempts: &[(ObjectId, Vec<Value>)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("events-{}", ObjectId::new().to_hex()));
+            std::fs::create_dir_all(&dir).unwrap();
+            for (attempt_id, rows) in attempts {
+                let path = dir.join(format!("{}.json", attempt_id.to_hex()));
+                std::fs::write(path, serde_json::to_vec(rows).unwrap()).unwrap();
+            }
+            Self(dir)
+        }
+
+        fn store(&self) -> FileEventStore {
+            FileEventStore {
+                dir: self.0.clone(),
+            }
+        }
+    }
+
+    impl Drop for EventDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn row(attempt_id: ObjectId, id: &str, kind: &str, timestamp: &str) -> Value {
+        json!({"id": id, "attempt_id": attempt_id.to_hex(), "kind": kind, "timestamp": timestamp})
+    }
+
+    fn ids(rows: &[Value]) -> Vec<&str> {
+        rows.iter().map(|row| row["id"].as_str().unwrap()).collect()
+    }
+
+    /// Two attempts, of the rows, in order, `a`, `d`, `e`, `b`, `c`
+    fn event_dir() -> (EventDir, Vec<ObjectId>) {
+        let attempt_ids = vec![ObjectId::new(), ObjectId::new()];
+        let event_dir = EventDir::new(&[
+            (
+                attempt_ids[0],
+                vec![
+                    row(attempt_ids[0], "b", "FOCUS", "2026-01-01T00:00:02Z"),
+                    row(attempt_ids[0], "d", "FOCUS", "2026-01-01T00:00:01Z"),
+                    row(attempt_ids[0], "c", "EXAM_EXIT", "2026-01-01T00:00:03.5Z"),
+                    row(attempt_ids[0], "a", "BLUR", "2026-01-01T00:00:01Z"),
+                    json!({"id": "no-timestamp", "kind": "BLUR"}),
+                ],
+            ),
+            (
+                attempt_ids[1],
+                vec![row(
+                    attempt_ids[1],
+                    "e",
+                    "BLUR",
+                    "2026-01-01T00:00:01.5+00:00",
+                )],
+            ),
+        ]);
+        (event_dir, attempt_ids)
+    }
+
+    fn date(rfc3339: &str) -> DateTime {
+        DateTime::parse_rfc3339_str(rfc3339).unwrap()
+    }
+
+    #[tokio::test]
+    async fn file_events_are_ordered_by_timestamp_then_id() {
+        let (event_dir, attempt_ids) = event_dir();
+
+        let rows = event_dir
+            .store()
+            .events(&EventFilter {
+                attempt_ids,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&rows), ["a", "d", "e", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn file_events_are_in_the_window() {
+        let (event_dir, attempt_ids) = event_dir();
+        let filter = EventFilter {
+            attempt_ids,
+            from: Some(date("2026-01-01T00:00:01.5Z")),
+            to: Some(date("2026-01-01T00:00:03.5Z")),
+            ..Default::default()
+        };
+
+        let rows = event_dir.store().events(&filter).await.unwrap();
+        assert_eq!(ids(&rows), ["e", "b"]);
+
+        let counts = event_dir.store().counts(&filter).await.unwrap();
+        assert_eq!(counts.iter().map(|c| c.count).sum::<usize>(), 2);
+    }
+
+    #[tokio::test]
+    async fn file_events_are_paged_by_cursor() {
+        let (event_dir, attempt_ids) = event_dir();
+        let mut filter = EventFilter {
+            attempt_ids,
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        let mut pages = vec![];
+        loop {
+            let rows = event_dir.store().events(&filter).await.unwrap();
+            let Some(last) = rows.last() else {
+                break;
+            };
+            filter.after = EventCursor::of(last);
+            pages.push(ids(&rows).join(""));
+        }
+
+        assert_eq!(pages, ["ad", "eb", "c"]);
+    }
+
+    #[tokio::test]
+    async fn file_events_are_filtered_by_kind_but_counted_for_every_kind() {
+        let (event_dir, attempt_ids) = event_dir();
+        let filter = EventFilter {
+            attempt_ids: attempt_ids.clone(),
+            kinds: vec![EventKind::Focus],
+            ..Default::default()
+        };
+
+        let rows = event_dir.store().events(&filter).await.unwrap();
+        assert_eq!(ids(&rows), ["d", "b"]);
+
+        let counts = event_dir.store().counts(&filter).await.unwrap();
+        assert_eq!(counts.len(), 4);
+        assert_eq!(count(&counts, attempt_ids[0], EventKind::Focus), Some(2));
+        assert_eq!(count(&counts, attempt_ids[0], EventKind::Blur), Some(1));
+        assert_eq!(count(&counts, attempt_ids[0], EventKind::ExamExit), Some(1));
+        assert_eq!(count(&counts, attempt_ids[1], EventKind::Blur), Some(1));
+    }
+
+    #[tokio::test]
+    async fn file_events_of_an_attempt_without_a_file_are_empty() {
+        let (event_dir, _) = event_dir();
+        let filter = EventFilter::attempt(ObjectId::new());
+
+        assert!(event_dir.store().events(&filter).await.unwrap().is_empty());
+        assert!(event_dir.store().counts(&filter).await.unwrap().is_empty());
+    }
+}
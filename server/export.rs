@@ -0,0 +1,423 @@
+//! Streaming CSV and Parquet exports, for analysis outside of Exam Creator.
+//!
+//! Rows are written as they are read from the database, so exports of large exams are not held in
+//! memory, and the response starts before the export finishes.
+use std::{io, sync::Arc};
+
+use arrow::{
+    datatypes::{DataType, Field, Schema},
+    json::ReaderBuilder,
+};
+use axum::{
+    body::{Body, Bytes},
+    response::{IntoResponse, Response},
+};
+use futures_util::{Stream, StreamExt, TryStreamExt, future, stream};
+use http::{HeaderValue, StatusCode, header::CONTENT_DISPOSITION, header::CONTENT_TYPE};
+use mongodb::bson::oid::ObjectId;
+use parquet::{arrow::AsyncArrowWriter, basic::Compression, file::properties::WriterProperties};
+use serde::{Deserialize, Serialize};
+use tokio::{io::DuplexStream, sync::oneshot};
+use tokio_util::io::ReaderStream;
+use tracing::error;
+
+use crate::{
+    analysis::QuestionAnalysis,
+    config::{Attempt, Event},
+    database::prisma,
+    errors::Error,
+    scoring::score_attempt,
+};
+
+/// Number of rows converted to a record batch at once
+const PARQUET_BATCH_SIZE: usize = 1_024;
+/// Maximum number of rows buffered in memory, before being written as a row group
+const PARQUET_ROW_GROUP_SIZE: usize = 16 * 1_024;
+/// Size of the buffer between the Parquet writer, and the response body
+const PARQUET_BUFFER_BYTES: usize = 64 * 1_024;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// A flat row of an export.
+///
+/// `schema` must have a field for every serialized field of the row, in the same order.
+pub trait ExportRow: Serialize + Send + Sync + 'static {
+    fn schema() -> Schema;
+}
+
+/// Streams `rows` as a file download named `name`.
+///
+/// The response has already started by the time a row fails, so a failing row logs the error, and
+/// aborts the response body, so the download fails instead of looking complete.
+pub fn export_response<T, S>(format: ExportFormat, name: &str, rows: S) -> Response
+where
+    T: ExportRow,
+    S: Stream<Item = Result<T, Error>> + Send + 'static,
+{
+    let file_name = format!("{name}.{}", format.extension());
+    let content_disposition =
+        HeaderValue::from_str(&format!("attachment; filename=\"{file_name}\""))
+            .unwrap_or(HeaderValue::from_static("attachment"));
+
+    match format {
+        ExportFormat::Csv => (
+            StatusCode::OK,
+            [
+                (CONTENT_TYPE, HeaderValue::from_static("text/csv")),
+                (CONTENT_DISPOSITION, content_disposition),
+            ],
+            Body::from_stream(csv_stream(rows)),
+        )
+            .into_response(),
+        ExportFormat::Parquet => {
+            let (writer, reader) = tokio::io::duplex(PARQUET_BUFFER_BYTES);
+            let (result_sender, result_receiver) = oneshot::channel();
+            tokio::spawn(async move {
+                let result = write_parquet(writer, rows).await;
+                if let Err(e) = &result {
+                    error!(error = ?e, file_name, "unable to write parquet export");
+                }
+                let _ = result_sender.send(result.map_err(|e| e.to_string()));
+            });
+
+            // The writer is dropped before the result is sent, so the reader ends first
+            let result = stream::once(async move {
+                match result_receiver.await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(Err(io::Error::other(e))),
+                    Err(_) => Some(Err(io::Error::other("parquet export ended early"))),
+                }
+            })
+            .filter_map(future::ready);
+
+            (
+                StatusCode::OK,
+                [
+                    (
+                        CONTENT_TYPE,
+                        HeaderValue::from_static("application/vnd.apache.parquet"),
+                    ),
+                    (CONTENT_DISPOSITION, content_disposition),
+                ],
+                Body::from_stream(ReaderStream::new(reader).chain(result)),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Serializes `rows` as CSV, with a header before the first row.
+///
+/// Ends with an error at the first failing row, logging it.
+fn csv_stream<T, S>(rows: S) -> impl Stream<Item = Result<Bytes, io::Error>> + Send
+where
+    T: ExportRow,
+    S: Stream<Item = Result<T, Error>> + Send,
+{
+    let mut writer = csv::Writer::from_writer(vec![]);
+    rows.map(move |row| {
+        let row = row.and_then(|row| {
+            writer.serialize(row).map_err(csv_error)?;
+            writer.flush().map_err(|e| csv_error(e.into()))?;
+            Ok(Bytes::from(std::mem::take(writer.get_mut())))
+        });
+        row.map_err(|e| {
+            error!(error = ?e, "unable to export row, aborting export");
+            io::Error::other(e.to_string())
+        })
+    })
+    // Nothing is read after the first error
+    .scan(false, |failed, row| {
+        let item = (!*failed).then(|| {
+            *failed = row.is_err();
+            row
+        });
+        future::ready(item)
+    })
+}
+
+async fn write_parquet<T, S>(writer: DuplexStream, rows: S) -> Result<(), Error>
+where
+    T: ExportRow,
+    S: Stream<Item = Result<T, Error>> + Send,
+{
+    let schema = Arc::new(T::schema());
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_size(PARQUET_ROW_GROUP_SIZE)
+        .build();
+    let mut writer = AsyncArrowWriter::try_new(writer, schema.clone(), Some(properties))
+        .map_err(parquet_error)?;
+    let mut decoder = ReaderBuilder::new(schema)
+        .build_decoder()
+        .map_err(arrow_error)?;
+
+    let mut chunks = std::pin::pin!(rows.try_chunks(PARQUET_BATCH_SIZE).map_err(|e| e.1));
+    while let Some(chunk) = chunks.try_next().await? {
+        decoder.serialize(&chunk).map_err(arrow_error)?;
+        if let Some(batch) = decoder.flush().map_err(arrow_error)? {
+            writer.write(&batch).await.map_err(parquet_error)?;
+        }
+    }
+    writer.close().await.map_err(parquet_error)?;
+
+    Ok(())
+}
+
+fn csv_error(e: csv::Error) -> Error {
+    Error::Server(StatusCode::INTERNAL_SERVER_ERROR, format!("csv error: {e}"))
+}
+
+fn arrow_error(e: arrow::error::ArrowError) -> Error {
+    Error::Server(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("arrow error: {e}"),
+    )
+}
+
+fn parquet_error(e: parquet::errors::ParquetError) -> Error {
+    Error::Server(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("parquet error: {e}"),
+    )
+}
+
+fn rfc3339(date: mongodb::bson::DateTime) -> String {
+    date.to_chrono().to_rfc3339()
+}
+
+/// One row per question presented in an attempt
+#[derive(Serialize)]
+pub struct AttemptQuestionRow {
+    pub attempt_id: String,
+    pub exam_id: String,
+    pub user_id: String,
+    pub start_time: String,
+    pub attempt_percent: f64,
+    pub attempt_passed: bool,
+    pub question_set_id: String,
+    pub question_id: String,
+    /// Separated by `;`
+    pub tags: String,
+    pub submission_time: Option<String>,
+    /// Separated by `;`
+    pub selected_answer_ids: String,
+    pub correct: bool,
+}
+
+impl ExportRow for AttemptQuestionRow {
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("attempt_id", DataType::Utf8, false),
+            Field::new("exam_id", DataType::Utf8, false),
+            Field::new("user_id", DataType::Utf8, false),
+            Field::new("start_time", DataType::Utf8, false),
+            Field::new("attempt_percent", DataType::Float64, false),
+            Field::new("attempt_passed", DataType::Boolean, false),
+            Field::new("question_set_id", DataType::Utf8, false),
+            Field::new("question_id", DataType::Utf8, false),
+            Field::new("tags", DataType::Utf8, false),
+            Field::new("submission_time", DataType::Utf8, true),
+            Field::new("selected_answer_ids", DataType::Utf8, false),
+            Field::new("correct", DataType::Boolean, false),
+        ])
+    }
+}
+
+impl AttemptQuestionRow {
+    /// Flattens an attempt into a row per presented question
+    pub fn from_attempt(attempt: &Attempt) -> Vec<Self> {
+        let score = score_attempt(attempt);
+
+        attempt
+            .question_sets
+            .iter()
+            .flat_map(|qs| qs.questions.iter().map(move |q| (qs, q)))
+            .filter(|(_, q)| !q.generated.is_empty())
+            .map(|(question_set, question)| Self {
+                attempt_id: attempt.id.to_hex(),
+                exam_id: attempt.exam_id.to_hex(),
+                user_id: attempt.user_id.to_hex(),
+                start_time: rfc3339(attempt.start_time),
+                attempt_percent: score.percent,
+                attempt_passed: score.passed,
+                question_set_id: question_set.id.to_hex(),
+                question_id: question.id.to_hex(),
+                tags: question.tags.join(";"),
+                submission_time: question.submission_time.map(rfc3339),
+                selected_answer_ids: join_ids(&question.selected),
+                correct: score
+                    .questions
+                    .iter()
+                    .any(|s| s.question_id == question.id && s.correct),
+            })
+            .collect()
+    }
+}
+
+fn join_ids(ids: &[ObjectId]) -> String {
+    ids.iter()
+        .map(|id| id.to_hex())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+#[derive(Serialize)]
+pub struct ModerationRow {
+    pub moderation_id: String,
+    pub attempt_id: String,
+    pub exam_id: String,
+    pub user_id: String,
+    pub status: String,
+    pub submission_date: String,
+    pub moderation_date: Option<String>,
+    pub moderator_id: Option<String>,
+    pub challenges_awarded: bool,
+    pub feedback: Option<String>,
+}
+
+impl ExportRow for ModerationRow {
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("moderation_id", DataType::Utf8, false),
+            Field::new("attempt_id", DataType::Utf8, false),
+            Field::new("exam_id", DataType::Utf8, false),
+            Field::new("user_id", DataType::Utf8, false),
+            Field::new("status", DataType::Utf8, false),
+            Field::new("submission_date", DataType::Utf8, false),
+            Field::new("moderation_date", DataType::Utf8, true),
+            Field::new("moderator_id", DataType::Utf8, true),
+            Field::new("challenges_awarded", DataType::Boolean, false),
+            Field::new("feedback", DataType::Utf8, true),
+        ])
+    }
+}
+
+impl ModerationRow {
+    pub fn new(
+        moderation: prisma::ExamEnvironmentExamModeration,
+        exam_id: ObjectId,
+        user_id: ObjectId,
+    ) -> Self {
+        Self {
+            moderation_id: moderation.id.to_hex(),
+            attempt_id: moderation.exam_attempt_id.to_hex(),
+            exam_id: exam_id.to_hex(),
+            user_id: user_id.to_hex(),
+            status: moderation.status.to_string(),
+            submission_date: rfc3339(moderation.submission_date),
+            moderation_date: moderation.moderation_date.map(rfc3339),
+            moderator_id: moderation.moderator_id.map(|id| id.to_hex()),
+            challenges_awarded: moderation.challenges_awarded,
+            feedback: moderation.feedback,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ItemStatisticRow {
+    pub exam_id: String,
+    pub question_set_id: String,
+    pub question_id: String,
+    pub text: String,
+    /// Separated by `;`
+    pub tags: String,
+    pub deprecated: bool,
+    pub presented: u64,
+    pub answered: u64,
+    pub correct: u64,
+    pub difficulty: Option<f64>,
+    pub discrimination: Option<f64>,
+    pub mean_time_to_answer_ms: Option<f64>,
+}
+
+impl ExportRow for ItemStatisticRow {
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("exam_id", DataType::Utf8, false),
+            Field::new("question_set_id", DataType::Utf8, false),
+            Field::new("question_id", DataType::Utf8, false),
+            Field::new("text", DataType::Utf8, false),
+            Field::new("tags", DataType::Utf8, false),
+            Field::new("deprecated", DataType::Boolean, false),
+            Field::new("presented", DataType::UInt64, false),
+            Field::new("answered", DataType::UInt64, false),
+            Field::new("correct", DataType::UInt64, false),
+            Field::new("difficulty", DataType::Float64, true),
+            Field::new("discrimination", DataType::Float64, true),
+            Field::new("mean_time_to_answer_ms", DataType::Float64, true),
+        ])
+    }
+}
+
+impl ItemStatisticRow {
+    pub fn new(exam_id: ObjectId, question: QuestionAnalysis) -> Self {
+        Self {
+            exam_id: exam_id.to_hex(),
+            question_set_id: question.question_set_id.to_hex(),
+            question_id: question.question_id.to_hex(),
+            text: question.text,
+            tags: question.tags.join(";"),
+            deprecated: question.deprecated,
+            presented: question.presented as u64,
+            answered: question.answered as u64,
+            correct: question.correct as u64,
+            difficulty: question.difficulty,
+            discrimination: question.discrimination,
+            mean_time_to_answer_ms: question.mean_time_to_answer_ms,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct EventRow {
+    pub event_id: String,
+    pub attempt_id: String,
+    pub timestamp: String,
+    pub kind: String,
+    /// JSON
+    pub meta: String,
+}
+
+impl ExportRow for EventRow {
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("event_id", DataType::Utf8, false),
+            Field::new("attempt_id", DataType::Utf8, false),
+            Field::new("timestamp", DataType::Utf8, false),
+            Field::new("kind", DataType::Utf8, false),
+            Field::new("meta", DataType::Utf8, false),
+        ])
+    }
+}
+
+impl From<Event> for EventRow {
+    fn from(event: Event) -> Self {
+        Self {
+            event_id: event.id,
+            attempt_id: event.attempt_id.to_hex(),
+            timestamp: event.timestamp.try_to_rfc3339_string().unwrap_or_default(),
+            // Serialized as the `SCREAMING_SNAKE_CASE` name
+            kind: serde_json::to_value(&event.kind)
+                .ok()
+                .and_then(|k| k.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            meta: serde_json::to_string(&event.meta).unwrap_or_default(),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    middleware::{self, Next},
+    response::Response,
+    routing::MethodRouter,
+};
+use http::StatusCode;
+use tracing::warn;
+
+use crate::{
+    database::{
+        exam_creator::{ExamCreatorRole, ExamCreatorUserAccess},
+        prisma,
+    },
+    errors::Error,
+    state::ServerState,
+};
+
+/// Any role is allowed to read
+pub const READ: &[ExamCreatorRole] = &[
+    ExamCreatorRole::ReadOnly,
+    ExamCreatorRole::Author,
+    ExamCreatorRole::Moderator,
+];
+pub const AUTHOR: &[ExamCreatorRole] = &[ExamCreatorRole::Author];
+pub const MODERATOR: &[ExamCreatorRole] = &[ExamCreatorRole::Moderator];
+pub const ADMIN: &[ExamCreatorRole] = &[ExamCreatorRole::Admin];
+
+#[derive(Clone)]
+pub struct RequiredRoles {
+    state: ServerState,
+    roles: &'static [ExamCreatorRole],
+}
+
+/// Restricts all handlers of `method_router` to users with at least one of `roles`.
+///
+/// `Admin` users are allowed on every route.
+pub fn require_roles(
+    state: &ServerState,
+    roles: &'static [ExamCreatorRole],
+    method_router: MethodRouter<ServerState>,
+) -> MethodRouter<ServerState> {
+    method_router.route_layer(middleware::from_fn_with_state(
+        RequiredRoles {
+            state: state.clone(),
+            roles,
+        },
+        authorize,
+    ))
+}
+
+/// Authenticates the user, and rejects with 403 if the user is disabled, or has none of the
+/// required roles.
+///
+/// The authenticated user is stored in the request extensions, so the handler's
+/// `prisma::ExamCreatorUser` extractor does not query the database again.
+async fn authorize(
+    State(required): State<RequiredRoles>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let (mut parts, body) = request.into_parts();
+
+    let user = prisma::ExamCreatorUser::from_request_parts(&mut parts, &required.state)
+        .await
+        .map_err(|(status, msg)| Error::Server(status, msg.to_string()))?;
+
+    // Disabled users are rejected by the extractor
+    let roles = parts
+        .extensions
+        .remove::<ExamCreatorUserAccess>()
+        .map(|access| access.roles)
+        .unwrap_or_default();
+
+    let authorized = roles
+        .iter()
+        .any(|role| *role == ExamCreatorRole::Admin || required.roles.contains(role));
+
+    if !authorized {
+        let required_roles: Vec<String> = required.roles.iter().map(|r| r.to_string()).collect();
+        let user_roles: Vec<String> = roles.iter().map(|r| r.to_string()).collect();
+        warn!(
+            user = %user.email,
+            ?required_roles,
+            ?user_roles,
+            path = %parts.uri.path(),
+            "forbidden request"
+        );
+        sentry::metrics::counter("auth.forbidden", 1)
+            .attribute("method", parts.method.to_string())
+            .capture();
+        return Err(Error::Server(
+            StatusCode::FORBIDDEN,
+            format!(
+                "forbidden: requires one of the roles [{}], but {} has [{}]",
+                required_roles.join(", "),
+                user.email,
+                user_roles.join(", ")
+            ),
+        ));
+    }
+
+    parts.extensions.insert(user);
+
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
@@ -13,12 +13,14 @@ use tower_sessions::Session;
 use tracing::{error, info, warn};
 
 use crate::{
-    database::prisma,
+    database::{exam_creator::ExamCreatorUserAccess, prisma, user_access},
     errors::Error,
     routes::websocket::handle_users_ws,
     state::{Activity, ServerState, User},
 };
 
+pub mod authorization;
+
 impl<S> FromRequestParts<S> for prisma::ExamCreatorUser
 where
     S: Send + Sync,
@@ -27,6 +29,11 @@ where
     type Rejection = (StatusCode, &'static str);
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Already authenticated by `authorization::require_roles`
+        if let Some(user) = parts.extensions.get::<prisma::ExamCreatorUser>() {
+            return Ok(user.clone());
+        }
+
         let state = ServerState::from_ref(state);
 
         let cookiejar: PrivateCookieJar = PrivateCookieJar::from_request_parts(parts, &state)
@@ -69,6 +76,27 @@ where
             })?
             .ok_or((StatusCode::UNAUTHORIZED, "no user account"))?;
 
+        // Sessions outlive a user being disabled, so the check is made on every request
+        let access = user_access(&state, user.id).await.map_err(|e| {
+            error!("db user access find op failed: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "db user access find op failed",
+            )
+        })?;
+        if access.as_ref().is_some_and(|access| access.disabled) {
+            warn!(user = %user.email, "request from disabled user");
+            return Err((StatusCode::FORBIDDEN, "user disabled"));
+        }
+        // Reused by `authorization::require_roles`
+        parts
+            .extensions
+            .insert(access.unwrap_or(ExamCreatorUserAccess {
+                id: user.id,
+                roles: vec![],
+                disabled: false,
+            }));
+
         let client_sync = &mut state.client_sync.lock().unwrap();
         if let Some(user) = client_sync.users.iter_mut().find(|u| u.email == user.email) {
             user.activity.last_active = chrono::Utc::now().timestamp_millis() as usize;
@@ -149,6 +177,16 @@ pub async fn ws_handler_users(
             format!("user not found: {}", session.user_id),
         ))?;
 
+    if user_access(&state, user.id)
+        .await?
+        .is_some_and(|access| access.disabled)
+    {
+        return Err(Error::Server(
+            StatusCode::FORBIDDEN,
+            format!("user disabled: {}", user.email),
+        ));
+    }
+
     let upgrade_res = ws.on_upgrade(move |socket| handle_users_ws(socket, user, state));
     Ok(upgrade_res)
 }
@@ -1,11 +1,22 @@
+mod analysis;
 mod app;
+mod appeal;
+mod audit;
+mod cache;
 mod config;
 mod database;
+mod deletion;
 mod errors;
+mod event_store;
+mod export;
 mod extractor;
 mod generate;
+mod moderation;
 mod routes;
+mod scoring;
 mod state;
+mod suspicion;
+mod timeline;
 
 #[tokio::main]
 async fn main() {
@@ -0,0 +1,789 @@
+//! Moderation decisions shared by the single and bulk moderation routes, moderation claims, and
+//! denial reasons.
+use std::time::Duration;
+
+use futures_util::TryStreamExt;
+use http::StatusCode;
+use mongodb::{
+    bson::{DateTime, Document, doc, oid::ObjectId},
+    error::{ErrorKind, WriteFailure},
+};
+use tracing::{info, warn};
+
+use crate::{
+    audit::{self, AuditEntry},
+    database::{
+        Database, database_environment,
+        exam_creator::{
+            ExamCreatorAuditAction, ExamCreatorChallengeRevocation, ExamCreatorDenialReason,
+            ExamCreatorModerationClaim, ExamCreatorModerationDecision, ExamCreatorRole,
+        },
+        prisma, user_roles,
+    },
+    errors::Error,
+    state::ServerState,
+};
+
+/// How long a claim lasts, unless renewed by the moderator holding it
+pub const CLAIM_LEASE: Duration = Duration::from_secs(15 * 60);
+/// How long a review session stays open without a decision
+const REVIEW_SESSION_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long an ended review session is kept for reporting
+const REVIEW_SESSION_RETENTION: Duration = Duration::from_secs(180 * 24 * 60 * 60);
+
+/// Sets the status of an attempt's moderation, recording the moderator, optional feedback, and
+/// the reasons for a denial.
+///
+/// Changing the status after challenges were awarded requires an admin override, with a reason,
+/// and records the challenge revocation this needs downstream. See `check_moderation`.
+///
+/// Emits the decision metrics, and records the decision, with the previous decision, in the
+/// decision history and audit log.
+///
+/// Returns the moderation as it was before the decision.
+pub async fn moderate_attempt(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+    status: &prisma::ExamEnvironmentExamModerationStatus,
+    feedback: Option<String>,
+    denial_reason_codes: &[String],
+    override_reason: Option<String>,
+) -> Result<prisma::ExamEnvironmentExamModeration, Error> {
+    let database = database_environment(server_state, exam_creator_user);
+
+    let feedback = feedback
+        .map(|feedback| feedback.trim().to_string())
+        .filter(|feedback| !feedback.is_empty());
+
+    let ModerationCheck {
+        moderation,
+        student_message,
+        override_reason,
+    } = check_moderation(
+        server_state,
+        exam_creator_user,
+        attempt_id,
+        status,
+        denial_reason_codes,
+        override_reason,
+    )
+    .await?;
+    // Recorded before the override, so it cannot be lost
+    let revocation_id = match &override_reason {
+        Some(override_reason) => Some(
+            record_revocation(
+                database,
+                exam_creator_user,
+                &moderation,
+                status,
+                override_reason,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    let now = DateTime::now();
+    // Recorded before the update, so a decision is never applied without its history. The update
+    // is conditional on the moderation's status, so the previous status cannot change meanwhile.
+    let decision = ExamCreatorModerationDecision {
+        id: ObjectId::new(),
+        exam_attempt_id: attempt_id,
+        moderation_id: moderation.id,
+        moderator_id: exam_creator_user.id,
+        status: status.clone(),
+        previous_status: moderation.status.clone(),
+        previous_moderator_id: moderation.moderator_id,
+        previous_feedback: moderation.feedback.clone(),
+        feedback: feedback.clone(),
+        denial_reason_codes: denial_reason_codes.to_vec(),
+        student_message,
+        override_reason: override_reason.clone(),
+        decided_at: now,
+        version: 1,
+    };
+    if let Err(e) = database
+        .exam_creator_moderation_decision
+        .insert_one(&decision)
+        .await
+    {
+        if let Some(revocation_id) = revocation_id {
+            database
+                .exam_creator_challenge_revocation
+                .delete_one(doc! {"_id": revocation_id})
+                .await?;
+        }
+        return Err(e.into());
+    }
+
+    // Only updates the moderation as it was checked. Returns the pre-update document, providing
+    // the previous decision and submission date.
+    let update = database
+        .exam_environment_exam_moderation
+        .find_one_and_update(
+            doc! {
+                "_id": moderation.id,
+                "status": bson::serialize_to_bson(&moderation.status)?,
+                "challengesAwarded": moderation.challenges_awarded,
+            },
+            doc! { "$set": {
+                "status": bson::serialize_to_bson(status)?,
+                "moderationDate": now,
+                "moderatorId": exam_creator_user.id,
+                "feedback": feedback.clone(),
+            } },
+        )
+        .await;
+    let old_moderation = match update {
+        Ok(Some(old_moderation)) => old_moderation,
+        update => {
+            // The change did not happen, so neither did its decision, nor revocation
+            database
+                .exam_creator_moderation_decision
+                .delete_one(doc! {"_id": decision.id})
+                .await?;
+            if let Some(revocation_id) = revocation_id {
+                database
+                    .exam_creator_challenge_revocation
+                    .delete_one(doc! {"_id": revocation_id})
+                    .await?;
+            }
+            return Err(match update {
+                Err(e) => e.into(),
+                Ok(_) => Error::Server(
+                    StatusCode::CONFLICT,
+                    format!("moderation of attempt {attempt_id} changed concurrently, retry"),
+                ),
+            });
+        }
+    };
+
+    let database_environment = exam_creator_user.settings.database_environment.to_string();
+    sentry::metrics::counter("exam.moderation.decision", 1)
+        .attribute("status", status.to_string())
+        .attribute("previous_status", old_moderation.status.to_string())
+        .attribute("database_environment", database_environment.clone())
+        .capture();
+
+    // Re-opening is not a decision
+    if *status != prisma::ExamEnvironmentExamModerationStatus::Pending {
+        let time_to_decision_s =
+            (now.timestamp_millis() - old_moderation.submission_date.timestamp_millis()) as f64
+                / 1000.0;
+        sentry::metrics::distribution("exam.moderation.time_to_decision", time_to_decision_s)
+            .unit(sentry::protocol::Unit::Second)
+            .attribute("status", status.to_string())
+            .attribute("database_environment", database_environment.clone())
+            .capture();
+    }
+
+    // The decision is already stored, so failing to end the review session only loses its timing
+    let time_on_page_ms = end_review_session(database, exam_creator_user.id, attempt_id, now)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(error = ?e, %attempt_id, "unable to end review session");
+            None
+        });
+    if let Some(time_on_page_ms) = time_on_page_ms {
+        sentry::metrics::distribution("exam.moderation.time_on_page", time_on_page_ms as f64)
+            .unit(sentry::protocol::Unit::Millisecond)
+            .attribute("moderator", exam_creator_user.name.clone())
+            .attribute("database_environment", database_environment)
+            .capture();
+    }
+
+    // The attempt no longer needs reviewing. The decision is already applied, so a failure is only
+    // logged, and the claim left to expire.
+    if let Err(e) = database
+        .exam_creator_moderation_claim
+        .delete_one(doc! {"_id": attempt_id})
+        .await
+    {
+        warn!(error = ?e, %attempt_id, "unable to release moderation claim");
+    }
+
+    server_state
+        .metrics_cache
+        .invalidate_moderations(&exam_creator_user.settings.database_environment);
+
+    audit::record(
+        server_state,
+        exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::ModerationDecision,
+            vec![attempt_id, old_moderation.id],
+        )
+        .database_environment(exam_creator_user.settings.database_environment.clone())
+        .before(doc! {
+            "status": bson::serialize_to_bson(&old_moderation.status)?,
+            "feedback": old_moderation.feedback.clone(),
+            "moderatorId": old_moderation.moderator_id,
+            "challengesAwarded": old_moderation.challenges_awarded,
+        })
+        .after(doc! {
+            "status": bson::serialize_to_bson(status)?,
+            "feedback": feedback,
+            "moderatorId": exam_creator_user.id,
+            "denialReasonCodes": denial_reason_codes,
+            "overrideReason": override_reason,
+            // Recorded for the moderator report, `null` if the moderation page view was not recorded
+            "timeOnPageMs": time_on_page_ms,
+        }),
+    )
+    .await;
+
+    Ok(old_moderation)
+}
+
+/// An attempt's moderation, checked to accept a decision, with what the decision records.
+pub struct ModerationCheck {
+    pub moderation: prisma::ExamEnvironmentExamModeration,
+    /// The message shown to the student for the denial reasons, if any
+    pub student_message: Option<String>,
+    /// Only kept if the override is needed
+    pub override_reason: Option<String>,
+}
+
+/// Runs every check `moderate_attempt` makes before applying a decision, without writing
+/// anything: the attempt is not claimed by another moderator, the denial reasons are allowed and
+/// known, the status transition is allowed, and any override it needs is given by an admin.
+pub async fn check_moderation(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+    status: &prisma::ExamEnvironmentExamModerationStatus,
+    denial_reason_codes: &[String],
+    override_reason: Option<String>,
+) -> Result<ModerationCheck, Error> {
+    let database = database_environment(server_state, exam_creator_user);
+
+    if let Some(claim) = active_claim(database, attempt_id).await? {
+        if claim.moderator_id != exam_creator_user.id {
+            return Err(claimed_by_other(&claim));
+        }
+    }
+
+    let student_message = denial_student_message(
+        server_state,
+        database,
+        attempt_id,
+        status,
+        denial_reason_codes,
+    )
+    .await?;
+
+    let moderation = database
+        .exam_environment_exam_moderation
+        .find_one(doc! { "examAttemptId": attempt_id })
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("Moderation record non-existent for attempt: {}", attempt_id),
+        ))?;
+
+    let override_reason = if check_transition(&moderation, status)? {
+        Some(check_override(server_state, exam_creator_user, attempt_id, override_reason).await?)
+    } else {
+        None
+    };
+
+    Ok(ModerationCheck {
+        moderation,
+        student_message,
+        override_reason,
+    })
+}
+
+/// Checks a moderation can change to `status`:
+///
+/// - A pending moderation cannot be re-opened.
+/// - Once challenges are awarded, only the feedback can change without an admin override, because
+///   the awarded challenges have to be revoked downstream.
+///
+/// Returns whether the change needs an admin override.
+fn check_transition(
+    moderation: &prisma::ExamEnvironmentExamModeration,
+    status: &prisma::ExamEnvironmentExamModerationStatus,
+) -> Result<bool, Error> {
+    if moderation.status == prisma::ExamEnvironmentExamModerationStatus::Pending
+        && *status == prisma::ExamEnvironmentExamModerationStatus::Pending
+    {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "moderation of attempt {} is already pending",
+                moderation.exam_attempt_id
+            ),
+        ));
+    }
+
+    Ok(moderation.challenges_awarded && moderation.status != *status)
+}
+
+/// Checks the user is an admin, overriding with a reason. Returns the reason.
+async fn check_override(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+    override_reason: Option<String>,
+) -> Result<String, Error> {
+    let Some(override_reason) = override_reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty())
+    else {
+        return Err(Error::Server(
+            StatusCode::CONFLICT,
+            format!(
+                "challenges were awarded for attempt {attempt_id}, so changing its status requires an admin override with a reason"
+            ),
+        ));
+    };
+
+    let is_admin = user_roles(server_state, exam_creator_user.id)
+        .await?
+        .contains(&ExamCreatorRole::Admin);
+    if !is_admin {
+        return Err(Error::Server(
+            StatusCode::FORBIDDEN,
+            "only admins can override a moderation with awarded challenges".to_string(),
+        ));
+    }
+
+    Ok(override_reason)
+}
+
+/// Records the revocation of the challenges awarded for an attempt, needed to override its
+/// moderation. Returns the revocation's id.
+async fn record_revocation(
+    database: &Database,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    moderation: &prisma::ExamEnvironmentExamModeration,
+    status: &prisma::ExamEnvironmentExamModerationStatus,
+    reason: &str,
+) -> Result<ObjectId, Error> {
+    let attempt_id = moderation.exam_attempt_id;
+    let attempt = database
+        .exam_attempt
+        .clone_with_type::<Document>()
+        .find_one(doc! {"_id": attempt_id})
+        .projection(doc! {"userId": true, "examId": true})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("attempt non-existent: {attempt_id}"),
+        ))?;
+    let exam_id = attempt.get_object_id("examId")?;
+
+    let challenge_ids = database
+        .exam_environment_challenge
+        .find(doc! {"examId": exam_id})
+        .await?
+        .map_ok(|challenge| challenge.challenge_id)
+        .try_collect()
+        .await?;
+
+    let revocation = ExamCreatorChallengeRevocation {
+        id: ObjectId::new(),
+        exam_attempt_id: attempt_id,
+        moderation_id: moderation.id,
+        user_id: attempt.get_object_id("userId")?,
+        exam_id,
+        challenge_ids,
+        previous_status: moderation.status.clone(),
+        status: status.clone(),
+        reason: reason.to_string(),
+        requested_by_id: exam_creator_user.id,
+        completed_by_id: None,
+        completed_at: None,
+        created_at: DateTime::now(),
+        version: 1,
+    };
+    database
+        .exam_creator_challenge_revocation
+        .insert_one(&revocation)
+        .await?;
+
+    warn!(%attempt_id, "overriding moderation with awarded challenges, revocation pending");
+
+    Ok(revocation.id)
+}
+
+/// Validates the reasons for a decision, and renders their student messages for the attempt's
+/// exam.
+///
+/// Returns `None` without reasons. Reasons are only allowed for denials, and must be enabled.
+async fn denial_student_message(
+    server_state: &ServerState,
+    database: &Database,
+    attempt_id: ObjectId,
+    status: &prisma::ExamEnvironmentExamModerationStatus,
+    denial_reason_codes: &[String],
+) -> Result<Option<String>, Error> {
+    if denial_reason_codes.is_empty() {
+        return Ok(None);
+    }
+    if !matches!(status, prisma::ExamEnvironmentExamModerationStatus::Denied) {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("denial reasons are only allowed when denying, not: {status}"),
+        ));
+    }
+
+    let reasons: Vec<ExamCreatorDenialReason> = server_state
+        .production_database
+        .exam_creator_denial_reason
+        .find(doc! {"_id": {"$in": denial_reason_codes}, "disabled": false})
+        .await?
+        .try_collect()
+        .await?;
+    let unknown: Vec<&str> = denial_reason_codes
+        .iter()
+        .filter(|code| !reasons.iter().any(|r| &r.code == *code))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("unknown or disabled denial reasons: {}", unknown.join(", ")),
+        ));
+    }
+
+    let exam_name = exam_name(database, attempt_id).await?.unwrap_or_default();
+    // In the order the moderator picked the reasons
+    let student_message = denial_reason_codes
+        .iter()
+        .filter_map(|code| reasons.iter().find(|r| &r.code == code))
+        .map(|reason| reason.student_message.replace("{exam}", &exam_name))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    Ok(Some(student_message))
+}
+
+/// Get the name of an attempt's exam, if both exist
+async fn exam_name(database: &Database, attempt_id: ObjectId) -> Result<Option<String>, Error> {
+    let Some(attempt) = database
+        .exam_attempt
+        .clone_with_type::<Document>()
+        .find_one(doc! {"_id": attempt_id})
+        .projection(doc! {"examId": true})
+        .await?
+    else {
+        return Ok(None);
+    };
+    let exam = database
+        .exam
+        .clone_with_type::<Document>()
+        .find_one(doc! {"_id": attempt.get_object_id("examId")?})
+        .projection(doc! {"config.name": true})
+        .await?;
+
+    Ok(exam.and_then(|exam| {
+        exam.get_document("config")
+            .and_then(|config| config.get_str("name"))
+            .map(str::to_string)
+            .ok()
+    }))
+}
+
+/// Reasons available before any are created by admins, as `(code, label, description, student
+/// message)`
+const DEFAULT_DENIAL_REASONS: [(&str, &str, &str, &str); 3] = [
+    (
+        "tab-switching",
+        "Tab switching",
+        "The exam window lost focus repeatedly, or for long periods.",
+        "Your {exam} attempt was denied, because the exam window lost focus repeatedly. Exams must be completed without leaving the exam window.",
+    ),
+    (
+        "impossible-timing",
+        "Impossible timing",
+        "Questions were answered faster than they can be read.",
+        "Your {exam} attempt was denied, because questions were answered faster than they can be read.",
+    ),
+    (
+        "identity-mismatch",
+        "Identity mismatch",
+        "The attempt was not completed by the account holder.",
+        "Your {exam} attempt was denied, because we could not verify that it was completed by you.",
+    ),
+];
+
+/// Inserts the default denial reasons, leaving existing reasons (including edited defaults)
+/// unchanged.
+///
+/// Failures are logged, and not returned, because reasons can still be created by admins.
+pub async fn seed_denial_reasons(database: &Database) {
+    let now = DateTime::now();
+    for (code, label, description, student_message) in DEFAULT_DENIAL_REASONS {
+        match database
+            .exam_creator_denial_reason
+            .update_one(
+                doc! {"_id": code},
+                doc! {"$setOnInsert": {
+                    "label": label,
+                    "description": description,
+                    "studentMessage": student_message,
+                    "disabled": false,
+                    "createdAt": now,
+                    "updatedAt": now,
+                    "version": 1,
+                }},
+            )
+            .upsert(true)
+            .await
+        {
+            Ok(result) if result.upserted_id.is_some() => info!(code, "denial reason seeded"),
+            Ok(_) => {}
+            Err(e) => warn!(error = ?e, code, "unable to seed denial reason"),
+        }
+    }
+}
+
+/// Starts a review session of an attempt's moderation, restarting the moderator's open session of
+/// the attempt, if any.
+pub async fn start_review_session(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+) -> Result<(), Error> {
+    let database = database_environment(server_state, exam_creator_user);
+
+    let now = DateTime::now();
+    database
+        .exam_creator_review_session
+        .update_one(
+            doc! {
+                "moderatorId": exam_creator_user.id,
+                "examAttemptId": attempt_id,
+                "endedAt": null,
+            },
+            doc! {
+                "$set": {
+                    "startedAt": now,
+                    "expiresAt": DateTime::from_millis(
+                        now.timestamp_millis() + REVIEW_SESSION_TIMEOUT.as_millis() as i64,
+                    ),
+                },
+                "$setOnInsert": {
+                    "databaseEnvironment": bson::serialize_to_bson(
+                        &exam_creator_user.settings.database_environment,
+                    )?,
+                    "version": 1,
+                },
+            },
+        )
+        .upsert(true)
+        .await?;
+
+    Ok(())
+}
+
+/// Ends the moderator's open review session of an attempt, keeping it for reporting.
+///
+/// Returns the time spent reviewing, if a session was open.
+async fn end_review_session(
+    database: &Database,
+    moderator_id: ObjectId,
+    attempt_id: ObjectId,
+    now: DateTime,
+) -> Result<Option<i64>, Error> {
+    let session = database
+        .exam_creator_review_session
+        .find_one_and_update(
+            doc! {
+                "moderatorId": moderator_id,
+                "examAttemptId": attempt_id,
+                "endedAt": null,
+                "expiresAt": {"$gt": now},
+            },
+            doc! {
+                "$set": {
+                    "endedAt": now,
+                    "expiresAt": DateTime::from_millis(
+                        now.timestamp_millis() + REVIEW_SESSION_RETENTION.as_millis() as i64,
+                    ),
+                },
+            },
+        )
+        .await?;
+
+    Ok(session.map(|s| now.timestamp_millis() - s.started_at.timestamp_millis()))
+}
+
+/// Get the unexpired claim of an attempt's moderation, if any.
+pub async fn active_claim(
+    database: &Database,
+    attempt_id: ObjectId,
+) -> Result<Option<ExamCreatorModerationClaim>, Error> {
+    let claim = database
+        .exam_creator_moderation_claim
+        .find_one(doc! {"_id": attempt_id, "expiresAt": {"$gt": DateTime::now()}})
+        .await?;
+
+    Ok(claim)
+}
+
+/// Claims an attempt's moderation for `CLAIM_LEASE`, or renews the claim if already held by the user.
+///
+/// Rejects with 409 if another moderator holds an unexpired claim.
+pub async fn claim_attempt(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+) -> Result<ExamCreatorModerationClaim, Error> {
+    let database = database_environment(server_state, exam_creator_user);
+
+    let moderation = database
+        .exam_environment_exam_moderation
+        .find_one(doc! {"examAttemptId": attempt_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("Moderation record non-existent for attempt: {}", attempt_id),
+        ))?;
+
+    let now = DateTime::now();
+    let current_claim = active_claim(database, attempt_id).await?;
+    let renewal = match &current_claim {
+        Some(claim) if claim.moderator_id != exam_creator_user.id => {
+            return Err(claimed_by_other(claim));
+        }
+        Some(_) => true,
+        None => false,
+    };
+
+    let claim = ExamCreatorModerationClaim {
+        exam_attempt_id: attempt_id,
+        moderator_id: exam_creator_user.id,
+        moderator_name: exam_creator_user.name.clone(),
+        claimed_at: current_claim.map(|c| c.claimed_at).unwrap_or(now),
+        expires_at: DateTime::from_millis(now.timestamp_millis() + CLAIM_LEASE.as_millis() as i64),
+        version: 1,
+    };
+
+    // Only replaces the user's own claim, or an expired claim the TTL monitor has not removed yet.
+    // If another moderator claimed the attempt since it was read, the upsert conflicts on `_id`.
+    let replace_result = database
+        .exam_creator_moderation_claim
+        .replace_one(
+            doc! {
+                "_id": attempt_id,
+                "$or": [
+                    {"moderatorId": exam_creator_user.id},
+                    {"expiresAt": {"$lte": now}},
+                ],
+            },
+            &claim,
+        )
+        .upsert(true)
+        .await;
+
+    match replace_result {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key(&e) => {
+            let claim = active_claim(database, attempt_id).await?;
+            return Err(match claim {
+                Some(claim) => claimed_by_other(&claim),
+                None => Error::Server(
+                    StatusCode::CONFLICT,
+                    format!("attempt was claimed concurrently, retry: {attempt_id}"),
+                ),
+            });
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    if !renewal {
+        sentry::metrics::counter("exam.moderation.claim", 1)
+            .attribute(
+                "database_environment",
+                exam_creator_user.settings.database_environment.to_string(),
+            )
+            .capture();
+
+        audit::record(
+            server_state,
+            exam_creator_user,
+            AuditEntry::new(
+                ExamCreatorAuditAction::ModerationClaim,
+                vec![attempt_id, moderation.id],
+            )
+            .database_environment(exam_creator_user.settings.database_environment.clone())
+            .after(doc! {"expiresAt": claim.expires_at}),
+        )
+        .await;
+    }
+
+    Ok(claim)
+}
+
+/// Releases the user's claim of an attempt's moderation.
+///
+/// Admins can release any moderator's claim.
+pub async fn release_claim(
+    server_state: &ServerState,
+    exam_creator_user: &prisma::ExamCreatorUser,
+    attempt_id: ObjectId,
+) -> Result<(), Error> {
+    let database = database_environment(server_state, exam_creator_user);
+
+    let is_admin = user_roles(server_state, exam_creator_user.id)
+        .await?
+        .contains(&ExamCreatorRole::Admin);
+
+    let filter = if is_admin {
+        doc! {"_id": attempt_id}
+    } else {
+        doc! {"_id": attempt_id, "moderatorId": exam_creator_user.id}
+    };
+
+    let claim = database
+        .exam_creator_moderation_claim
+        .find_one_and_delete(filter)
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::NOT_FOUND,
+            format!("claim non-existent for attempt: {attempt_id}"),
+        ))?;
+
+    audit::record(
+        server_state,
+        exam_creator_user,
+        AuditEntry::new(ExamCreatorAuditAction::ModerationRelease, vec![attempt_id])
+            .database_environment(exam_creator_user.settings.database_environment.clone())
+            .before(doc! {
+                "moderatorId": claim.moderator_id,
+                "expiresAt": claim.expires_at,
+            }),
+    )
+    .await;
+
+    Ok(())
+}
+
+fn claimed_by_other(claim: &ExamCreatorModerationClaim) -> Error {
+    Error::Server(
+        StatusCode::CONFLICT,
+        format!(
+            "attempt is claimed by {} until {}",
+            claim.moderator_name,
+            claim
+                .expires_at
+                .try_to_rfc3339_string()
+                .unwrap_or_else(|_| claim.expires_at.to_string())
+        ),
+    )
+}
+
+pub fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    match e.kind.as_ref() {
+        ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY,
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => {
+            write_error.code == DUPLICATE_KEY
+        }
+        _ => false,
+    }
+}
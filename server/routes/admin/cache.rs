@@ -0,0 +1,58 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+    audit::{self, AuditEntry},
+    cache::{CacheEntryInfo, CacheKind},
+    database::{exam_creator::ExamCreatorAuditAction, prisma},
+    errors::Error,
+    state::ServerState,
+};
+
+/// Get all cached metrics entries
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_cache(
+    _: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+) -> Result<Json<Vec<CacheEntryInfo>>, Error> {
+    Ok(Json(state.metrics_cache.entries()))
+}
+
+#[derive(Deserialize)]
+pub struct DeleteCacheQuery {
+    pub database_environment: Option<prisma::ExamCreatorDatabaseEnvironment>,
+    pub kind: Option<CacheKind>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteCacheResponse {
+    pub removed: usize,
+}
+
+/// Clear cached metrics entries matching all given filters, so they are recomputed on next access
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn delete_cache(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<DeleteCacheQuery>,
+) -> Result<Json<DeleteCacheResponse>, Error> {
+    let removed = state
+        .metrics_cache
+        .clear(params.database_environment.as_ref(), params.kind);
+
+    let mut entry = AuditEntry::new(ExamCreatorAuditAction::CacheClear, vec![]).after(doc! {
+        "kind": params.kind.map(|k| bson::serialize_to_bson(&k)).transpose()?,
+        "removed": removed as i64,
+    });
+    if let Some(database_environment) = params.database_environment {
+        entry = entry.database_environment(database_environment);
+    }
+    audit::record(&state, &exam_creator_user, entry).await;
+
+    Ok(Json(DeleteCacheResponse { removed }))
+}
@@ -0,0 +1,196 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use http::StatusCode;
+use mongodb::bson::{Bson, DateTime, Document, doc};
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::{
+    audit::{self, AuditEntry},
+    database::{
+        exam_creator::{ExamCreatorAuditAction, ExamCreatorDenialReason},
+        prisma,
+    },
+    errors::Error,
+    moderation::is_duplicate_key,
+    state::ServerState,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostDenialReasonBody {
+    /// Kebab-case, e.g. `tab-switching`. Cannot be changed.
+    pub code: String,
+    pub label: String,
+    pub description: Option<String>,
+    /// `{exam}` is replaced with the exam's name
+    pub student_message: String,
+}
+
+/// Add a reason moderators can pick when denying an attempt
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn post_denial_reason(
+    admin: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Json(body): Json<PostDenialReasonBody>,
+) -> Result<Json<ExamCreatorDenialReason>, Error> {
+    let code = body.code.trim().to_string();
+    let is_kebab_case = !code.is_empty()
+        && !code.starts_with('-')
+        && !code.ends_with('-')
+        && code
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !is_kebab_case {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("code must be kebab-case: {code}"),
+        ));
+    }
+
+    let now = DateTime::now();
+    let reason = ExamCreatorDenialReason {
+        code,
+        label: non_empty("label", body.label)?,
+        description: body
+            .description
+            .map(|description| description.trim().to_string())
+            .filter(|description| !description.is_empty()),
+        student_message: non_empty("studentMessage", body.student_message)?,
+        disabled: false,
+        created_at: now,
+        updated_at: now,
+        version: 1,
+    };
+
+    match state
+        .production_database
+        .exam_creator_denial_reason
+        .insert_one(&reason)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key(&e) => {
+            return Err(Error::Server(
+                StatusCode::CONFLICT,
+                format!("denial reason already exists: {}", reason.code),
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    info!(code = %reason.code, "denial reason created");
+
+    audit::record(
+        &state,
+        &admin,
+        AuditEntry::new(ExamCreatorAuditAction::DenialReasonCreate, vec![]).after(doc! {
+            "code": reason.code.clone(),
+            "label": reason.label.clone(),
+            "studentMessage": reason.student_message.clone(),
+        }),
+    )
+    .await;
+
+    Ok(Json(reason))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchDenialReasonBody {
+    pub label: Option<String>,
+    pub description: Option<String>,
+    pub student_message: Option<String>,
+    pub disabled: Option<bool>,
+}
+
+/// Update a denial reason.
+///
+/// Past decisions keep the student message rendered when they were made.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn patch_denial_reason(
+    admin: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(code): Path<String>,
+    Json(body): Json<PatchDenialReasonBody>,
+) -> Result<Json<ExamCreatorDenialReason>, Error> {
+    let mut update = doc! {"updatedAt": DateTime::now()};
+    if let Some(label) = body.label {
+        update.insert("label", non_empty("label", label)?);
+    }
+    if let Some(description) = body.description {
+        let description = description.trim().to_string();
+        update.insert(
+            "description",
+            (!description.is_empty()).then_some(description),
+        );
+    }
+    if let Some(student_message) = body.student_message {
+        update.insert(
+            "studentMessage",
+            non_empty("studentMessage", student_message)?,
+        );
+    }
+    if let Some(disabled) = body.disabled {
+        update.insert("disabled", disabled);
+    }
+
+    // Returns the pre-update document
+    let old_reason = state
+        .production_database
+        .exam_creator_denial_reason
+        .find_one_and_update(doc! {"_id": &code}, doc! {"$set": update.clone()})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::NOT_FOUND,
+            format!("denial reason non-existent: {code}"),
+        ))?;
+
+    let reason = state
+        .production_database
+        .exam_creator_denial_reason
+        .find_one(doc! {"_id": &code})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not find denial reason after update: {code}"),
+        ))?;
+
+    // Only the changed fields
+    update.remove("updatedAt");
+    let old_reason = bson::serialize_to_document(&old_reason)?;
+    let before: Document = update
+        .keys()
+        .map(|key| {
+            (
+                key.clone(),
+                old_reason.get(key).cloned().unwrap_or(Bson::Null),
+            )
+        })
+        .collect();
+    update.insert("code", code);
+    audit::record(
+        &state,
+        &admin,
+        AuditEntry::new(ExamCreatorAuditAction::DenialReasonUpdate, vec![])
+            .before(before)
+            .after(update),
+    )
+    .await;
+
+    Ok(Json(reason))
+}
+
+fn non_empty(field: &str, value: String) -> Result<String, Error> {
+    let value = value.trim().to_string();
+    if value.is_empty() {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("{field} must not be empty"),
+        ));
+    }
+
+    Ok(value)
+}
@@ -0,0 +1,4 @@
+pub mod cache;
+pub mod denial_reasons;
+pub mod revocations;
+pub mod users;
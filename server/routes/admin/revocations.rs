@@ -0,0 +1,112 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use futures_util::TryStreamExt;
+use http::StatusCode;
+use mongodb::{
+    bson::{DateTime, doc, oid::ObjectId},
+    options::ReturnDocument,
+};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    audit::{self, AuditEntry},
+    database::{
+        database_environment,
+        exam_creator::{ExamCreatorAuditAction, ExamCreatorChallengeRevocation},
+        prisma,
+    },
+    errors::Error,
+    state::ServerState,
+};
+
+#[derive(Deserialize)]
+pub struct GetRevocationsQuery {
+    /// Include revocations which are already completed
+    #[serde(default)]
+    pub include_completed: bool,
+}
+
+/// Get the challenge revocations of the user's database environment, oldest first
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_revocations(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<GetRevocationsQuery>,
+) -> Result<Json<Vec<ExamCreatorChallengeRevocation>>, Error> {
+    let database = database_environment(&state, &exam_creator_user);
+
+    let filter = if params.include_completed {
+        doc! {}
+    } else {
+        doc! {"completedAt": null}
+    };
+    let revocations = database
+        .exam_creator_challenge_revocation
+        .find(filter)
+        .sort(doc! {"createdAt": 1})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(revocations))
+}
+
+/// Mark the challenges of a revocation as revoked downstream, unsetting `challengesAwarded` on the
+/// attempt's moderation, so its status can change again.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn post_revocation_complete(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(revocation_id): Path<ObjectId>,
+) -> Result<Json<ExamCreatorChallengeRevocation>, Error> {
+    let database = database_environment(&state, &exam_creator_user);
+
+    let revocation = database
+        .exam_creator_challenge_revocation
+        .find_one_and_update(
+            doc! {"_id": revocation_id, "completedAt": null},
+            doc! {"$set": {
+                "completedById": exam_creator_user.id,
+                "completedAt": DateTime::now(),
+            }},
+        )
+        .return_document(ReturnDocument::After)
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::NOT_FOUND,
+            format!("pending revocation non-existent: {revocation_id}"),
+        ))?;
+
+    database
+        .exam_environment_exam_moderation
+        .update_one(
+            doc! {"_id": revocation.moderation_id},
+            doc! {"$set": {"challengesAwarded": false}},
+        )
+        .await?;
+
+    state
+        .metrics_cache
+        .invalidate_moderations(&exam_creator_user.settings.database_environment);
+    audit::record(
+        &state,
+        &exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::ChallengeRevocationComplete,
+            vec![
+                revocation.exam_attempt_id,
+                revocation.moderation_id,
+                revocation.id,
+            ],
+        )
+        .database_environment(exam_creator_user.settings.database_environment.clone())
+        .before(doc! {"challengesAwarded": true})
+        .after(doc! {"challengesAwarded": false}),
+    )
+    .await;
+
+    Ok(Json(revocation))
+}
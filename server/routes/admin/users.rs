@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use bson::oid::ObjectId;
+use futures_util::TryStreamExt;
+use http::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+
+use crate::{
+    audit::{self, AuditEntry},
+    database::{
+        exam_creator::{ExamCreatorAuditAction, ExamCreatorRole},
+        insert_user_with_roles, prisma,
+    },
+    errors::Error,
+    state::{ServerState, remove_user},
+};
+
+#[derive(Serialize)]
+pub struct AdminUser {
+    #[serde(flatten)]
+    pub user: prisma::ExamCreatorUser,
+    pub roles: Vec<ExamCreatorRole>,
+    pub disabled: bool,
+}
+
+/// Get all Exam Creator users, with their roles
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_users(
+    _: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+) -> Result<Json<Vec<AdminUser>>, Error> {
+    let users: Vec<prisma::ExamCreatorUser> = state
+        .production_database
+        .exam_creator_user
+        .find(doc! {})
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut access: HashMap<ObjectId, (Vec<ExamCreatorRole>, bool)> = state
+        .production_database
+        .exam_creator_user_access
+        .find(doc! {})
+        .projection(doc! {"_id": true, "roles": true, "disabled": true})
+        .await?
+        .map_ok(|a| (a.id, (a.roles, a.disabled)))
+        .try_collect()
+        .await?;
+
+    let users = users
+        .into_iter()
+        .map(|user| {
+            let (roles, disabled) = access.remove(&user.id).unwrap_or_default();
+            AdminUser {
+                user,
+                roles,
+                disabled,
+            }
+        })
+        .collect();
+
+    Ok(Json(users))
+}
+
+#[derive(Deserialize)]
+pub struct PostUserBody {
+    pub name: String,
+    pub email: String,
+    #[serde(default)]
+    pub roles: Vec<ExamCreatorRole>,
+    pub settings: Option<prisma::ExamCreatorUserSettings>,
+}
+
+/// Invite a user, by creating an `ExamCreatorUser` they can log in as with GitHub.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn post_user(
+    admin: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Json(body): Json<PostUserBody>,
+) -> Result<Json<AdminUser>, Error> {
+    let email = body.email.trim().to_string();
+    if email.is_empty() {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            "email must not be empty".to_string(),
+        ));
+    }
+
+    let existing_user = state
+        .production_database
+        .exam_creator_user
+        .find_one(doc! {"email": &email})
+        .await?;
+    if existing_user.is_some() {
+        return Err(Error::Server(
+            StatusCode::CONFLICT,
+            format!("user already exists: {email}"),
+        ));
+    }
+
+    let user = prisma::ExamCreatorUser {
+        id: ObjectId::new(),
+        name: body.name,
+        email,
+        github_id: None,
+        picture: None,
+        settings: body.settings.unwrap_or_default(),
+        version: 2,
+    };
+
+    insert_user_with_roles(&state, &user, &body.roles).await?;
+
+    info!(email = %user.email, "user invited");
+
+    audit::record(
+        &state,
+        &admin,
+        AuditEntry::new(ExamCreatorAuditAction::UserCreate, vec![user.id]).after(doc! {
+            "email": user.email.clone(),
+            "name": user.name.clone(),
+            "roles": bson::serialize_to_bson(&body.roles)?,
+        }),
+    )
+    .await;
+
+    Ok(Json(AdminUser {
+        user,
+        roles: body.roles,
+        disabled: false,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct PatchUserBody {
+    pub name: Option<String>,
+    pub roles: Option<Vec<ExamCreatorRole>>,
+    pub settings: Option<prisma::ExamCreatorUserSettings>,
+    /// Disabling a user also revokes all of their sessions
+    pub disabled: Option<bool>,
+}
+
+/// Update a user's name, roles, settings, or disabled state.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn patch_user(
+    admin: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(user_id): Path<ObjectId>,
+    Json(body): Json<PatchUserBody>,
+) -> Result<Json<AdminUser>, Error> {
+    if admin.id == user_id {
+        let removes_admin = body
+            .roles
+            .as_ref()
+            .is_some_and(|roles| !roles.contains(&ExamCreatorRole::Admin));
+        if removes_admin || body.disabled == Some(true) {
+            return Err(Error::Server(
+                StatusCode::BAD_REQUEST,
+                "admins cannot remove their own admin role, nor disable themselves".to_string(),
+            ));
+        }
+    }
+
+    let old_access = state
+        .production_database
+        .exam_creator_user_access
+        .find_one(doc! {"_id": user_id})
+        .projection(doc! {"_id": true, "roles": true, "disabled": true})
+        .await?;
+
+    let mut update = doc! {};
+    if let Some(name) = body.name {
+        update.insert("name", name);
+    }
+    if let Some(roles) = &body.roles {
+        update.insert("roles", bson::serialize_to_bson(roles)?);
+    }
+    if let Some(settings) = &body.settings {
+        update.insert("settings", bson::serialize_to_bson(settings)?);
+    }
+    if let Some(disabled) = body.disabled {
+        update.insert("disabled", disabled);
+    }
+
+    // Returns the pre-update document
+    let old_user = if update.is_empty() {
+        None
+    } else {
+        Some(
+            state
+                .production_database
+                .exam_creator_user
+                .find_one_and_update(doc! {"_id": user_id}, doc! {"$set": update.clone()})
+                .await?
+                .ok_or(Error::Server(
+                    StatusCode::NOT_FOUND,
+                    format!("user non-existent: {user_id}"),
+                ))?,
+        )
+    };
+
+    let user = state
+        .production_database
+        .exam_creator_user
+        .find_one(doc! {"_id": user_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::NOT_FOUND,
+            format!("user non-existent: {user_id}"),
+        ))?;
+
+    let access = state
+        .production_database
+        .exam_creator_user_access
+        .find_one(doc! {"_id": user_id})
+        .projection(doc! {"_id": true, "roles": true, "disabled": true})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not find user after update: {user_id}"),
+        ))?;
+
+    if let (Some(old_user), Some(old_access)) = (old_user, old_access) {
+        audit::record(
+            &state,
+            &admin,
+            AuditEntry::new(ExamCreatorAuditAction::UserUpdate, vec![user_id])
+                .before(doc! {
+                    "name": old_user.name,
+                    "roles": bson::serialize_to_bson(&old_access.roles)?,
+                    "settings": bson::serialize_to_bson(&old_user.settings)?,
+                    "disabled": old_access.disabled,
+                })
+                .after(update),
+        )
+        .await;
+    }
+
+    if access.disabled {
+        revoke_sessions(&state, &user).await?;
+    } else {
+        // Update state
+        let client_sync = &mut state.client_sync.lock().unwrap();
+        if let Some(online_user) = client_sync.users.iter_mut().find(|u| u.email == user.email) {
+            online_user.name = user.name.clone();
+            online_user.settings = user.settings.clone();
+        }
+    }
+
+    Ok(Json(AdminUser {
+        user,
+        roles: access.roles,
+        disabled: access.disabled,
+    }))
+}
+
+/// Remove a user, and all of their sessions.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn delete_user(
+    admin: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(user_id): Path<ObjectId>,
+) -> Result<(), Error> {
+    if admin.id == user_id {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            "admins cannot remove themselves".to_string(),
+        ));
+    }
+
+    let user = state
+        .production_database
+        .exam_creator_user
+        .find_one(doc! {"_id": user_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::NOT_FOUND,
+            format!("user non-existent: {user_id}"),
+        ))?;
+
+    revoke_sessions(&state, &user).await?;
+
+    state
+        .production_database
+        .exam_creator_user
+        .delete_one(doc! {"_id": user_id})
+        .await?;
+
+    info!(email = %user.email, "user removed");
+
+    audit::record(
+        &state,
+        &admin,
+        AuditEntry::new(ExamCreatorAuditAction::UserDelete, vec![user_id]).before(doc! {
+            "email": user.email.clone(),
+            "name": user.name.clone(),
+        }),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Deletes all sessions of the user, and removes them from the online users
+async fn revoke_sessions(state: &ServerState, user: &prisma::ExamCreatorUser) -> Result<(), Error> {
+    let delete_result = state
+        .production_database
+        .exam_creator_session
+        .delete_many(doc! {"user_id": user.id})
+        .await?;
+
+    info!(
+        email = %user.email,
+        sessions = delete_result.deleted_count,
+        "user sessions revoked"
+    );
+
+    let client_sync = &mut state.client_sync.lock().unwrap();
+    remove_user(client_sync, &user.email);
+
+    Ok(())
+}
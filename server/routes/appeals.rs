@@ -0,0 +1,173 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use futures_util::TryStreamExt;
+use mongodb::bson::{Bson, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+    appeal,
+    database::{
+        database_environment,
+        exam_creator::{ExamCreatorAppeal, ExamCreatorAppealStatus},
+        prisma,
+    },
+    errors::Error,
+    state::ServerState,
+};
+
+const MAX_APPEALS_LIMIT: i64 = 500;
+
+#[derive(Deserialize)]
+pub struct GetAppealsQuery {
+    /// Defaults to `Open`
+    pub status: Option<ExamCreatorAppealStatus>,
+    pub assignee_id: Option<ObjectId>,
+    /// Only appeals without an assignee
+    #[serde(default)]
+    pub unassigned: bool,
+    pub skip: Option<u64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetAppealsResponse {
+    pub appeals: Vec<ExamCreatorAppeal>,
+    /// Number of appeals matching the filters, across all pages
+    pub total: u64,
+}
+
+/// Get the appeals queue of the user's database environment, oldest first
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_appeals(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<GetAppealsQuery>,
+) -> Result<Json<GetAppealsResponse>, Error> {
+    let database = database_environment(&state, &exam_creator_user);
+
+    let mut filter = doc! {
+        "status": bson::serialize_to_bson(
+            &params.status.unwrap_or(ExamCreatorAppealStatus::Open),
+        )?,
+    };
+    if params.unassigned {
+        filter.insert("assigneeId", Bson::Null);
+    } else if let Some(assignee_id) = params.assignee_id {
+        filter.insert("assigneeId", assignee_id);
+    }
+
+    let total = database
+        .exam_creator_appeal
+        .count_documents(filter.clone())
+        .await?;
+    let appeals = database
+        .exam_creator_appeal
+        .find(filter)
+        .sort(doc! {"createdAt": 1})
+        .skip(params.skip.unwrap_or(0))
+        .limit(params.limit.unwrap_or(50).clamp(1, MAX_APPEALS_LIMIT))
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(GetAppealsResponse { appeals, total }))
+}
+
+/// Get all appeals of an attempt, newest first
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_appeals_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+) -> Result<Json<Vec<ExamCreatorAppeal>>, Error> {
+    let database = database_environment(&state, &exam_creator_user);
+    let appeals = database
+        .exam_creator_appeal
+        .find(doc! {"examAttemptId": attempt_id})
+        .sort(doc! {"createdAt": -1})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(appeals))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostAppealBody {
+    /// The student's reason for appealing
+    pub reason: String,
+    /// Defaults to the moderator with the fewest open appeals
+    pub assignee_id: Option<ObjectId>,
+}
+
+/// Open an appeal of an attempt's denial
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn post_appeal_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+    Json(body): Json<PostAppealBody>,
+) -> Result<Json<ExamCreatorAppeal>, Error> {
+    let appeal = appeal::open(
+        &state,
+        &exam_creator_user,
+        attempt_id,
+        body.reason,
+        body.assignee_id,
+    )
+    .await?;
+
+    Ok(Json(appeal))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PutAppealAssigneeBody {
+    pub assignee_id: ObjectId,
+}
+
+/// Assign an open appeal to another moderator
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn put_appeal_assignee(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(appeal_id): Path<ObjectId>,
+    Json(body): Json<PutAppealAssigneeBody>,
+) -> Result<Json<ExamCreatorAppeal>, Error> {
+    let appeal = appeal::assign(&state, &exam_creator_user, appeal_id, body.assignee_id).await?;
+
+    Ok(Json(appeal))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostAppealResolutionBody {
+    /// `Upheld` approves the attempt
+    pub outcome: ExamCreatorAppealStatus,
+    pub feedback: Option<String>,
+}
+
+/// Resolve an open appeal
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn post_appeal_resolution(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(appeal_id): Path<ObjectId>,
+    Json(body): Json<PostAppealResolutionBody>,
+) -> Result<Json<ExamCreatorAppeal>, Error> {
+    let appeal = appeal::resolve(
+        &state,
+        &exam_creator_user,
+        appeal_id,
+        body.outcome,
+        body.feedback,
+    )
+    .await?;
+
+    Ok(Json(appeal))
+}
@@ -2,21 +2,32 @@ use std::collections::HashMap;
 
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
 };
-use bson::DateTime;
 use futures_util::TryStreamExt;
 use http::StatusCode;
 use mongodb::bson::doc;
 use mongodb::bson::oid::ObjectId;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::{
     config,
-    database::{database_environment, prisma},
+    database::{
+        database_environment,
+        exam_creator::{
+            ExamCreatorArchivedAttempt, ExamCreatorAttemptDeletion, ExamCreatorAttemptSuspicion,
+        },
+        prisma,
+    },
+    deletion,
     errors::Error,
+    moderation,
+    routes::{date_range_filter, events::events_by_attempt_id},
+    scoring::ScoredAttempt,
     state::ServerState,
+    suspicion,
+    timeline::{self, AttemptTimeline},
 };
 
 /// Get all attempts
@@ -62,7 +73,7 @@ pub async fn get_attempt_by_id(
     exam_creator_user: prisma::ExamCreatorUser,
     State(server_state): State<ServerState>,
     Path(attempt_id): Path<ObjectId>,
-) -> Result<Json<config::Attempt>, Error> {
+) -> Result<Json<ScoredAttempt>, Error> {
     let database = database_environment(&server_state, &exam_creator_user);
     let exam_attempt = database
         .exam_attempt
@@ -98,7 +109,88 @@ pub async fn get_attempt_by_id(
 
     let attempt = config::construct_attempt(&exam, &generation, &exam_attempt);
 
-    Ok(Json(attempt))
+    Ok(Json(attempt.into()))
+}
+
+#[derive(Deserialize)]
+pub struct GetSuspicionByAttemptIdQuery {
+    /// Recompute the score, even if the attempt has already been scored
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// Get the suspicion score of an attempt, with the signals explaining it.
+///
+/// Attempts which have not been scored are scored, and the score is stored.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_suspicion_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(server_state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+    Query(params): Query<GetSuspicionByAttemptIdQuery>,
+) -> Result<Json<ExamCreatorAttemptSuspicion>, Error> {
+    let database = database_environment(&server_state, &exam_creator_user);
+
+    if !params.refresh {
+        let stored = database
+            .exam_creator_attempt_suspicion
+            .find_one(doc! {"_id": attempt_id})
+            .await?;
+        if let Some(stored) = stored {
+            return Ok(Json(stored));
+        }
+    }
+
+    let exam_attempt = database
+        .exam_attempt
+        .find_one(doc! {"_id": attempt_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("attempt non-existent: {attempt_id}"),
+        ))?;
+    let attempt = construct_attempts(database, &[exam_attempt])
+        .await?
+        .pop()
+        .ok_or(Error::Server(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unable to construct attempt: {attempt_id}"),
+        ))?;
+
+    let suspicion =
+        suspicion::score_and_store(database, server_state.event_store.as_ref(), &attempt).await?;
+
+    Ok(Json(suspicion))
+}
+
+/// Get the ordered timeline of an attempt's submissions and events, with time away from the exam
+/// window and time per question
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_timeline_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(server_state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+) -> Result<Json<AttemptTimeline>, Error> {
+    let database = database_environment(&server_state, &exam_creator_user);
+    let exam_attempt = database
+        .exam_attempt
+        .find_one(doc! {"_id": attempt_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("attempt non-existent: {attempt_id}"),
+        ))?;
+    let attempt = construct_attempts(database, &[exam_attempt])
+        .await?
+        .pop()
+        .ok_or(Error::Server(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unable to construct attempt: {attempt_id}"),
+        ))?;
+
+    let events = events_by_attempt_id(server_state.event_store.as_ref(), attempt_id).await?;
+
+    Ok(Json(timeline::build_timeline(&attempt, &events)))
 }
 
 #[derive(Deserialize)]
@@ -106,8 +198,19 @@ pub struct PatchModerationStatusByAttemptIdBody {
     #[serde(rename = "attemptId")]
     pub attempt_id: mongodb::bson::oid::ObjectId,
     pub status: prisma::ExamEnvironmentExamModerationStatus,
+    /// Optional feedback about the decision
+    pub feedback: Option<String>,
+    /// Codes of the reasons for a denial
+    #[serde(rename = "denialReasonCodes", default)]
+    pub denial_reason_codes: Vec<String>,
+    /// Admin's reason for changing the status after challenges were awarded
+    #[serde(rename = "overrideReason")]
+    pub override_reason: Option<String>,
 }
 
+/// Decide, or re-open (`Pending`), an attempt's moderation.
+///
+/// Changing the status after challenges were awarded requires an admin, with `overrideReason`.
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn patch_moderation_status_by_attempt_id(
     exam_creator_user: prisma::ExamCreatorUser,
@@ -125,51 +228,241 @@ pub async fn patch_moderation_status_by_attempt_id(
         ));
     }
 
+    moderation::moderate_attempt(
+        &server_state,
+        &exam_creator_user,
+        attempt_id,
+        &body.status,
+        body.feedback,
+        &body.denial_reason_codes,
+        body.override_reason,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Maximum number of attempts a single bulk moderation may apply to
+const MAX_BULK_MODERATIONS: usize = 500;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostBulkModerationBody {
+    /// Attempts to moderate. Mutually exclusive with `filter`.
+    pub attempt_ids: Option<Vec<ObjectId>>,
+    /// Moderate every attempt matching the filter. Mutually exclusive with `attemptIds`.
+    pub filter: Option<BulkModerationFilter>,
+    pub status: prisma::ExamEnvironmentExamModerationStatus,
+    pub feedback: Option<String>,
+    /// Codes of the reasons for a denial, applied to every attempt
+    #[serde(default)]
+    pub denial_reason_codes: Vec<String>,
+    /// Report what would be moderated, checking each moderation as a real run would, without
+    /// moderating
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkModerationFilter {
+    pub status: Option<prisma::ExamEnvironmentExamModerationStatus>,
+    pub exam_id: Option<ObjectId>,
+    /// RFC 3339 date, inclusive
+    pub submitted_from: Option<String>,
+    /// RFC 3339 date, exclusive
+    pub submitted_to: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostBulkModerationResponse {
+    pub dry_run: bool,
+    pub results: Vec<BulkModerationResult>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkModerationResult {
+    pub attempt_id: ObjectId,
+    pub previous_status: Option<prisma::ExamEnvironmentExamModerationStatus>,
+    pub outcome: BulkModerationOutcome,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BulkModerationOutcome {
+    /// Dry run: the moderation would be updated
+    WouldUpdate,
+    Updated,
+    NotFound,
+    Failed,
+}
+
+/// Apply a status, and optional feedback, to many attempts' moderations.
+///
+/// Each attempt is moderated as in [`patch_moderation_status_by_attempt_id`], with the same metrics
+/// and audit log entries. A failure for one attempt does not stop the others. Results are in the
+/// order of `attemptIds`, or of submission for a filter.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn post_bulk_moderation(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(server_state): State<ServerState>,
+    Json(body): Json<PostBulkModerationBody>,
+) -> Result<Json<PostBulkModerationResponse>, Error> {
     let database = database_environment(&server_state, &exam_creator_user);
 
-    let now = DateTime::now();
-    // Returns the pre-update document, providing the previous status and submission date
-    let old_moderation = database
+    let mut pipeline = match (&body.attempt_ids, &body.filter) {
+        (Some(attempt_ids), None) => {
+            vec![doc! {"$match": {"examAttemptId": {"$in": attempt_ids.clone()}}}]
+        }
+        (None, Some(filter)) => {
+            let mut moderation_filter = doc! {};
+            if let Some(status) = &filter.status {
+                moderation_filter.insert("status", bson::serialize_to_bson(status)?);
+            }
+            let submission_date = date_range_filter(
+                filter.submitted_from.as_deref(),
+                filter.submitted_to.as_deref(),
+            )?;
+            if !submission_date.is_empty() {
+                moderation_filter.insert("submissionDate", submission_date);
+            }
+            let mut pipeline = vec![doc! {"$match": moderation_filter}];
+            if let Some(exam_id) = filter.exam_id {
+                // "examId" does not exist on moderation
+                pipeline.extend([
+                    doc! {
+                        "$lookup": {
+                            "from": "ExamEnvironmentExamAttempt",
+                            "localField": "examAttemptId",
+                            "foreignField": "_id",
+                            "pipeline": [{"$project": {"examId": true}}],
+                            "as": "attempt",
+                        }
+                    },
+                    doc! {"$match": {"attempt.examId": exam_id}},
+                    doc! {"$unset": "attempt"},
+                ]);
+            }
+            pipeline.push(doc! {"$sort": {"submissionDate": 1, "_id": 1}});
+            pipeline
+        }
+        _ => {
+            return Err(Error::Server(
+                StatusCode::BAD_REQUEST,
+                "exactly one of attemptIds or filter must be provided".to_string(),
+            ));
+        }
+    };
+    pipeline.push(doc! {"$limit": MAX_BULK_MODERATIONS as i64 + 1});
+
+    let moderations: Vec<prisma::ExamEnvironmentExamModeration> = database
         .exam_environment_exam_moderation
-        .find_one_and_update(
-            doc! { "examAttemptId": body.attempt_id },
-            doc! { "$set": { "status": bson::serialize_to_bson(&body.status)?, "moderationDate": now } },
-        )
+        .aggregate(pipeline)
+        .with_type::<prisma::ExamEnvironmentExamModeration>()
         .await?
-        .ok_or(Error::Server(
+        .try_collect()
+        .await?;
+
+    if moderations.len() > MAX_BULK_MODERATIONS {
+        return Err(Error::Server(
             StatusCode::BAD_REQUEST,
-            format!("Moderation record non-existent for attempt: {}", attempt_id),
-        ))?;
+            format!("bulk moderation is limited to {MAX_BULK_MODERATIONS} attempts"),
+        ));
+    }
 
-    let database_environment = exam_creator_user.settings.database_environment.to_string();
-    sentry::metrics::counter("exam.moderation.decision", 1)
-        .attribute("status", body.status.to_string())
-        .attribute("previous_status", old_moderation.status.to_string())
-        .attribute("database_environment", database_environment.clone())
-        .capture();
+    // In request order, including requested attempts without a moderation record, or else in
+    // submission order
+    let attempt_ids: Vec<ObjectId> = match &body.attempt_ids {
+        Some(requested_attempt_ids) => {
+            let mut attempt_ids = Vec::with_capacity(requested_attempt_ids.len());
+            for attempt_id in requested_attempt_ids {
+                if !attempt_ids.contains(attempt_id) {
+                    attempt_ids.push(*attempt_id);
+                }
+            }
+            attempt_ids
+        }
+        None => moderations.iter().map(|m| m.exam_attempt_id).collect(),
+    };
+
+    let mut results = Vec::with_capacity(attempt_ids.len());
+    for attempt_id in attempt_ids {
+        let Some(moderation) = moderations.iter().find(|m| m.exam_attempt_id == attempt_id) else {
+            results.push(BulkModerationResult {
+                attempt_id,
+                previous_status: None,
+                outcome: BulkModerationOutcome::NotFound,
+                error: None,
+            });
+            continue;
+        };
 
-    let time_to_decision_s = (now.timestamp_millis()
-        - old_moderation.submission_date.timestamp_millis()) as f64
-        / 1000.0;
-    sentry::metrics::distribution("exam.moderation.time_to_decision", time_to_decision_s)
-        .unit(sentry::protocol::Unit::Second)
+        // A dry run makes the same checks, only skipping the write
+        let result = if body.dry_run {
+            moderation::check_moderation(
+                &server_state,
+                &exam_creator_user,
+                attempt_id,
+                &body.status,
+                &body.denial_reason_codes,
+                None,
+            )
+            .await
+            .map(|check| (check.moderation, BulkModerationOutcome::WouldUpdate))
+        } else {
+            moderation::moderate_attempt(
+                &server_state,
+                &exam_creator_user,
+                attempt_id,
+                &body.status,
+                body.feedback.clone(),
+                &body.denial_reason_codes,
+                None,
+            )
+            .await
+            .map(|old_moderation| (old_moderation, BulkModerationOutcome::Updated))
+        };
+        let result = match result {
+            Ok((old_moderation, outcome)) => BulkModerationResult {
+                attempt_id,
+                previous_status: Some(old_moderation.status),
+                outcome,
+                error: None,
+            },
+            Err(e) => {
+                tracing::error!(
+                    %attempt_id,
+                    dry_run = body.dry_run,
+                    error = %e,
+                    "bulk moderation failed"
+                );
+                BulkModerationResult {
+                    attempt_id,
+                    previous_status: Some(moderation.status.clone()),
+                    outcome: BulkModerationOutcome::Failed,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        results.push(result);
+    }
+
+    sentry::metrics::counter("exam.moderation.bulk", 1)
         .attribute("status", body.status.to_string())
-        .attribute("database_environment", database_environment.clone())
+        .attribute("dry_run", body.dry_run.to_string())
+        .attribute(
+            "database_environment",
+            exam_creator_user.settings.database_environment.to_string(),
+        )
         .capture();
 
-    if let Ok(mut lock) = server_state.attempt_page_views.lock() {
-        let view_started_at = lock.remove(&(exam_creator_user.id, attempt_id));
-        if let Some(view_started_at) = view_started_at {
-            let time_on_page_ms = now.timestamp_millis() - view_started_at.timestamp_millis();
-            sentry::metrics::distribution("exam.moderation.time_on_page", time_on_page_ms as f64)
-                .unit(sentry::protocol::Unit::Millisecond)
-                .attribute("moderator", exam_creator_user.name.clone())
-                .attribute("database_environment", database_environment)
-                .capture();
-        }
-    }
-
-    Ok(())
+    Ok(Json(PostBulkModerationResponse {
+        dry_run: body.dry_run,
+        results,
+    }))
 }
 
 /// Records that the moderator opened this attempt's moderation page, so the
@@ -180,81 +473,108 @@ pub async fn put_moderation_view_start(
     State(server_state): State<ServerState>,
     Path(attempt_id): Path<ObjectId>,
 ) -> Result<(), Error> {
-    if let Ok(mut lock) = server_state.attempt_page_views.lock() {
-        lock.insert((exam_creator_user.id, attempt_id), DateTime::now());
-    }
-
-    Ok(())
+    moderation::start_review_session(&server_state, &exam_creator_user, attempt_id).await
 }
 
-/// Grace period before a scheduled attempt deletion is executed, allowing an undo.
-const DELETE_GRACE_SECONDS: u64 = 10;
+/// Get the attempt deletions scheduled in the user's database environment, soonest first
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_pending_deletions(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(server_state): State<ServerState>,
+) -> Result<Json<Vec<ExamCreatorAttemptDeletion>>, Error> {
+    let database = database_environment(&server_state, &exam_creator_user);
 
-/// Monotonic tag distinguishing successive schedules for the same attempt id.
-static DELETE_GENERATION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let deletions = database
+        .exam_creator_attempt_deletion
+        .find(doc! {})
+        .sort(doc! {"graceUntil": 1})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(deletions))
+}
+
+#[derive(Deserialize)]
+pub struct PutPendingDeletionBody {
+    pub reason: Option<String>,
+}
 
 /// Schedule deletion of an attempt (and its moderation) after a grace period.
 ///
-/// Spawns a non-blocking task that waits `DELETE_GRACE_SECONDS` before deleting.
-/// The task is cancellable via [`delete_pending_deletion`]; unlike the previous
-/// client-side timer, it survives the client navigating away.
+/// The deletion is stored, and executed by the deletion executor once the grace period passes,
+/// so it survives restarts. It is cancellable via [`delete_pending_deletion`] until then, and the
+/// attempt can be restored from the archive afterwards.
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn put_pending_deletion(
     exam_creator_user: prisma::ExamCreatorUser,
     State(server_state): State<ServerState>,
     Path(attempt_id): Path<ObjectId>,
-) -> Result<(), Error> {
-    let database = database_environment(&server_state, &exam_creator_user).clone();
-
-    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-    let generation = DELETE_GENERATION.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-
-    // Replace any existing schedule for this attempt (dropping the old sender cancels it).
-    server_state
-        .pending_deletes
-        .lock()
-        .unwrap()
-        .insert(attempt_id, (generation, tx));
-
-    let database_environment = exam_creator_user.settings.database_environment.to_string();
-    sentry::metrics::counter("exam.attempt.deletion", 1)
-        .attribute("outcome", "scheduled")
-        .attribute("database_environment", database_environment.clone())
-        .capture();
+    body: Option<Json<PutPendingDeletionBody>>,
+) -> Result<Json<ExamCreatorAttemptDeletion>, Error> {
+    let reason = body.and_then(|Json(body)| body.reason);
+    let deletion =
+        deletion::schedule(&server_state, &exam_creator_user, attempt_id, reason).await?;
 
-    let pending_deletes = server_state.pending_deletes.clone();
-    tokio::spawn(async move {
-        tokio::select! {
-            _ = tokio::time::sleep(std::time::Duration::from_secs(DELETE_GRACE_SECONDS)) => {
-                let outcome = match perform_delete_attempt(&database, attempt_id).await {
-                    Ok(()) => "executed",
-                    Err(e) => {
-                        tracing::error!("scheduled delete failed for attempt {attempt_id}: {e}");
-                        "failed"
-                    }
-                };
-                sentry::metrics::counter("exam.attempt.deletion", 1)
-                    .attribute("outcome", outcome)
-                    .attribute("database_environment", database_environment.clone())
-                    .capture();
-                // Only drop our own entry, not a newer reschedule that replaced it.
-                let mut pending = pending_deletes.lock().unwrap();
-                if pending.get(&attempt_id).is_some_and(|(g, _)| *g == generation) {
-                    pending.remove(&attempt_id);
-                }
-            }
-            _ = rx => {
-                // Cancelled: the sender was dropped/removed by delete_pending_deletion.
-                tracing::info!(%attempt_id, "scheduled delete cancelled");
-                sentry::metrics::counter("exam.attempt.deletion", 1)
-                    .attribute("outcome", "cancelled")
-                    .attribute("database_environment", database_environment.clone())
-                    .capture();
-            }
-        }
-    });
+    Ok(Json(deletion))
+}
 
-    Ok(())
+const MAX_ARCHIVED_ATTEMPTS_LIMIT: i64 = 1_000;
+
+#[derive(Deserialize)]
+pub struct GetArchivedAttemptsQuery {
+    pub exam_id: Option<ObjectId>,
+    pub user_id: Option<ObjectId>,
+    pub skip: Option<u64>,
+    pub limit: Option<i64>,
+}
+
+/// Get deleted attempts which can still be restored, in the user's database environment, newest
+/// first
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_archived_attempts(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(server_state): State<ServerState>,
+    Query(params): Query<GetArchivedAttemptsQuery>,
+) -> Result<Json<Vec<ExamCreatorArchivedAttempt>>, Error> {
+    let database = database_environment(&server_state, &exam_creator_user);
+
+    let mut filter = doc! {};
+    if let Some(exam_id) = params.exam_id {
+        filter.insert("attempt.examId", exam_id);
+    }
+    if let Some(user_id) = params.user_id {
+        filter.insert("attempt.userId", user_id);
+    }
+
+    let archived_attempts = database
+        .exam_creator_archived_attempt
+        .find(filter)
+        .sort(doc! {"deletedAt": -1})
+        .skip(params.skip.unwrap_or(0))
+        .limit(
+            params
+                .limit
+                .unwrap_or(100)
+                .clamp(1, MAX_ARCHIVED_ATTEMPTS_LIMIT),
+        )
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(archived_attempts))
+}
+
+/// Restore a deleted attempt, and its moderation, from the archive
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn post_restore_attempt(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(server_state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+) -> Result<Json<ExamCreatorArchivedAttempt>, Error> {
+    let archived_attempt = deletion::restore(&server_state, &exam_creator_user, attempt_id).await?;
+
+    Ok(Json(archived_attempt))
 }
 
 /// Cancel a pending attempt deletion, restoring the attempt.
@@ -263,44 +583,11 @@ pub async fn put_pending_deletion(
 /// already elapsed) is a no-op, not an error.
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn delete_pending_deletion(
-    _exam_creator_user: prisma::ExamCreatorUser,
+    exam_creator_user: prisma::ExamCreatorUser,
     State(server_state): State<ServerState>,
     Path(attempt_id): Path<ObjectId>,
 ) -> Result<(), Error> {
-    // Removing the sender drops it, waking the task's `rx` branch and aborting the delete.
-    let removed = server_state
-        .pending_deletes
-        .lock()
-        .unwrap()
-        .remove(&attempt_id);
-
-    if removed.is_none() {
-        tracing::info!(%attempt_id, "no pending deletion to cancel");
-    }
-
-    Ok(())
-}
-
-/// Delete an attempt and its moderation (0-1 records). Already-deleted records
-/// are not an error: the desired end state is reached either way.
-async fn perform_delete_attempt(
-    database: &crate::database::Database,
-    attempt_id: ObjectId,
-) -> Result<(), Error> {
-    // Delete moderation first (0-1 records). Not-found is fine.
-    database
-        .exam_environment_exam_moderation
-        .delete_one(doc! { "examAttemptId": attempt_id })
-        .await?;
-
-    let delete_result = database
-        .exam_attempt
-        .delete_one(doc! { "_id": attempt_id })
-        .await?;
-
-    if delete_result.deleted_count == 0 {
-        tracing::warn!(%attempt_id, "attempt already deleted");
-    }
+    deletion::cancel(&server_state, &exam_creator_user, attempt_id).await?;
 
     Ok(())
 }
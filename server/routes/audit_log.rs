@@ -0,0 +1,79 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use bson::oid::ObjectId;
+use futures_util::TryStreamExt;
+use mongodb::bson::doc;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    database::{
+        exam_creator::{ExamCreatorAuditAction, ExamCreatorAuditLog},
+        prisma,
+    },
+    errors::Error,
+    routes::date_range_filter,
+    state::ServerState,
+};
+
+const MAX_AUDIT_LOG_LIMIT: i64 = 1_000;
+
+#[derive(Deserialize)]
+pub struct GetAuditLogQuery {
+    pub actor_id: Option<ObjectId>,
+    pub action: Option<ExamCreatorAuditAction>,
+    pub target_id: Option<ObjectId>,
+    pub database_environment: Option<prisma::ExamCreatorDatabaseEnvironment>,
+    /// RFC 3339 date, inclusive
+    pub from: Option<String>,
+    /// RFC 3339 date, exclusive
+    pub to: Option<String>,
+    pub skip: Option<u64>,
+    pub limit: Option<i64>,
+}
+
+/// Get audit log entries matching all given filters, newest first
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_audit_log(
+    _: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<GetAuditLogQuery>,
+) -> Result<Json<Vec<ExamCreatorAuditLog>>, Error> {
+    let mut filter = doc! {};
+    if let Some(actor_id) = params.actor_id {
+        filter.insert("actorId", actor_id);
+    }
+    if let Some(action) = params.action {
+        filter.insert("action", bson::serialize_to_bson(&action)?);
+    }
+    if let Some(target_id) = params.target_id {
+        filter.insert("targetIds", target_id);
+    }
+    if let Some(database_environment) = params.database_environment {
+        filter.insert(
+            "databaseEnvironment",
+            bson::serialize_to_bson(&database_environment)?,
+        );
+    }
+    let created_at = date_range_filter(params.from.as_deref(), params.to.as_deref())?;
+    if !created_at.is_empty() {
+        filter.insert("createdAt", created_at);
+    }
+
+    let limit = params.limit.unwrap_or(100).clamp(1, MAX_AUDIT_LOG_LIMIT);
+
+    let audit_log = state
+        .production_database
+        .exam_creator_audit_log
+        .find(filter)
+        .sort(doc! {"createdAt": -1})
+        .skip(params.skip.unwrap_or(0))
+        .limit(limit)
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(audit_log))
+}
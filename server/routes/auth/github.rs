@@ -21,7 +21,15 @@ use tower_sessions::Session;
 use tracing::{error, info, warn};
 use url::Url;
 
-use crate::{database::prisma, errors::Error, state::ServerState};
+use crate::{
+    audit::{self, AuditEntry},
+    database::{
+        exam_creator::{ExamCreatorAuditAction, ExamCreatorRole},
+        insert_user_with_roles, prisma, user_access,
+    },
+    errors::Error,
+    state::ServerState,
+};
 
 type GitHubClient =
     BasicClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet>;
@@ -129,14 +137,11 @@ pub async fn github_handler(
             settings: prisma::ExamCreatorUserSettings::default(),
             version: 1,
         };
-        let res = server_state
-            .production_database
-            .exam_creator_user
-            .insert_one(mock_user)
-            .await;
+        let res =
+            insert_user_with_roles(&server_state, &mock_user, &[ExamCreatorRole::Admin]).await;
 
         match res {
-            Ok(_insert_result) => {
+            Ok(()) => {
                 info!("Camperbot user inserted into database");
             }
             Err(e) => {
@@ -157,12 +162,41 @@ pub async fn github_handler(
         sentry::metrics::counter("auth.login", 1)
             .attribute("outcome", "unauthorized")
             .capture();
+        audit::insert(
+            &server_state.production_database.exam_creator_audit_log,
+            None,
+            &email,
+            AuditEntry::new(ExamCreatorAuditAction::Login, vec![])
+                .after(doc! {"outcome": "unauthorized"}),
+        )
+        .await;
         return Err(Error::Server(
             StatusCode::UNAUTHORIZED,
             format!("user non-existent: {email}"),
         ));
     };
 
+    if user_access(&server_state, user.id)
+        .await?
+        .is_some_and(|access| access.disabled)
+    {
+        warn!({ email }, "login attempt for disabled user");
+        sentry::metrics::counter("auth.login", 1)
+            .attribute("outcome", "disabled")
+            .capture();
+        audit::record(
+            &server_state,
+            &user,
+            AuditEntry::new(ExamCreatorAuditAction::Login, vec![user.id])
+                .after(doc! {"outcome": "disabled"}),
+        )
+        .await;
+        return Err(Error::Server(
+            StatusCode::FORBIDDEN,
+            format!("user disabled: {email}"),
+        ));
+    }
+
     // Update user picture
     server_state
         .production_database
@@ -197,6 +231,13 @@ pub async fn github_handler(
     sentry::metrics::counter("auth.login", 1)
         .attribute("outcome", "success")
         .capture();
+    audit::record(
+        &server_state,
+        &user,
+        AuditEntry::new(ExamCreatorAuditAction::Login, vec![user.id])
+            .after(doc! {"outcome": "success"}),
+    )
+    .await;
 
     let cookie = Cookie::build(("sid", session.session_id))
         // .domain("http://127.0.0.1:3001")
@@ -10,9 +10,17 @@ use oauth2::{
 };
 use serde::Deserialize;
 use tower_sessions::Session;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
-use crate::{database::prisma, errors::Error, state::ServerState};
+use crate::{
+    audit::{self, AuditEntry},
+    database::{
+        exam_creator::{ExamCreatorAuditAction, ExamCreatorRole},
+        insert_user_with_roles, prisma, user_access,
+    },
+    errors::Error,
+    state::ServerState,
+};
 
 pub mod github;
 
@@ -37,6 +45,13 @@ pub async fn delete_logout(
         .delete_many(doc! {"user_id": &user.id})
         .await?;
 
+    audit::record(
+        &server_state,
+        &user,
+        AuditEntry::new(ExamCreatorAuditAction::Logout, vec![user.id]),
+    )
+    .await;
+
     Ok(jar.remove(cookie))
 }
 
@@ -50,6 +65,8 @@ pub struct DevLoginBody {
 ///
 /// Takes a name and email as body parameters, creates a user if one does not exist,
 /// and creates a session for that user, setting the sid cookie in the response.
+///
+/// Created users are granted the `Admin` role.
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn post_dev_login(
     _session: Session,
@@ -75,15 +92,22 @@ pub async fn post_dev_login(
                 settings: Default::default(),
                 version: 2,
             };
-            let _ = server_state
-                .production_database
-                .exam_creator_user
-                .insert_one(&user)
-                .await?;
+            insert_user_with_roles(&server_state, &user, &[ExamCreatorRole::Admin]).await?;
             user
         }
     };
 
+    if user_access(&server_state, user.id)
+        .await?
+        .is_some_and(|access| access.disabled)
+    {
+        warn!(email = %user.email, "dev login attempt for disabled user");
+        return Err(Error::Server(
+            StatusCode::FORBIDDEN,
+            format!("user disabled: {}", user.email),
+        ));
+    }
+
     let access_token = user.email.clone();
     let token = StandardTokenResponse::new(
         AccessToken::new(access_token.clone()),
@@ -112,6 +136,14 @@ pub async fn post_dev_login(
         .insert_one(&session)
         .await?;
 
+    audit::record(
+        &server_state,
+        &user,
+        AuditEntry::new(ExamCreatorAuditAction::Login, vec![user.id])
+            .after(doc! {"outcome": "success", "dev": true}),
+    )
+    .await;
+
     let cookie = Cookie::build(("sid", session.session_id))
         // .domain("http://127.0.0.1:3001")
         .path("/")
@@ -1,44 +1,366 @@
+use std::collections::{BTreeMap, HashMap};
+
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
 };
-use bson::oid::ObjectId;
+use bson::{doc, oid::ObjectId};
+use futures_util::TryStreamExt;
 use http::StatusCode;
+use serde::{Deserialize, Serialize};
 use tracing::{instrument, warn};
 
-use crate::{config, database::prisma, errors::Error, state::ServerState};
+use crate::{
+    config::{self, EventError, EventKind},
+    database::{database_environment, prisma},
+    errors::Error,
+    event_store::{EventCount, EventCursor, EventFilter, EventStore},
+    routes::parse_date,
+    state::ServerState,
+};
+
+const DEFAULT_EVENTS_LIMIT: usize = 1_000;
+const MAX_EVENTS_LIMIT: usize = 5_000;
+/// Maximum number of attempts whose events are queried at once
+const MAX_EVENT_QUERY_ATTEMPTS: u64 = 200;
+
+/// Events of attempts, with the rows left out of `events`
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptEvents {
+    /// Oldest first
+    pub events: Vec<config::Event>,
+    /// Events of a kind this server does not know yet
+    pub unknown: Vec<UnknownEvent>,
+    /// Events which failed validation
+    pub dropped: Vec<DroppedEvent>,
+}
+
+impl AttemptEvents {
+    /// Number of rows left out of `events`
+    pub fn skipped(&self) -> usize {
+        self.unknown.len() + self.dropped.len()
+    }
+}
 
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnknownEvent {
+    pub id: String,
+    pub kind: String,
+    pub timestamp: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedEvent {
+    /// `None` if the row does not have an id
+    pub id: Option<String>,
+    pub kind: Option<String>,
+    pub error: String,
+}
+
+/// Get the events of an attempt, oldest first.
+///
+/// Unknown, and invalid, rows are left out, so the response stays an array of events. They are
+/// reported by `get_events`, with `attempt_id`.
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn get_events_by_attempt_id(
     _: prisma::ExamCreatorUser,
     State(server_state): State<ServerState>,
     Path(attempt_id): Path<ObjectId>,
 ) -> Result<Json<Vec<config::Event>>, Error> {
-    let supabase = server_state.supabase;
-    let events = supabase
-        .from("events")
-        .eq("attempt_id", &attempt_id.to_hex())
-        .execute()
-        .await
-        .map_err(|e| {
-            Error::Server(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("supabase http error: {e}"),
-            )
-        })?;
-
-    let mut events: Vec<config::Event> = events
-        .into_iter()
-        .filter_map(|event| match serde_json::from_value(event) {
-            Ok(event) => Some(event),
+    let events = events_by_attempt_id(server_state.event_store.as_ref(), attempt_id).await?;
+    if events.skipped() > 0 {
+        warn!(
+            %attempt_id,
+            unknown = events.unknown.len(),
+            dropped = events.dropped.len(),
+            "left out events of attempt"
+        );
+    }
+
+    Ok(Json(events.events))
+}
+
+#[derive(Deserialize)]
+pub struct GetEventsQuery {
+    pub attempt_id: Option<ObjectId>,
+    pub user_id: Option<ObjectId>,
+    pub exam_id: Option<ObjectId>,
+    /// Comma-separated kinds, e.g. `BLUR,FOCUS`. Defaults to all kinds.
+    pub kinds: Option<String>,
+    /// RFC 3339 date, inclusive
+    pub from: Option<String>,
+    /// RFC 3339 date, exclusive
+    pub to: Option<String>,
+    /// `nextCursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Get a page of the events of an attempt, or of all attempts of a user, or of an exam, oldest
+/// first.
+///
+/// `attempts` has the counts of each attempt, to compare attempts.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_events(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(server_state): State<ServerState>,
+    Query(params): Query<GetEventsQuery>,
+) -> Result<Json<EventPage>, Error> {
+    let database = database_environment(&server_state, &exam_creator_user);
+
+    let mut attempt_filter = doc! {};
+    if let Some(attempt_id) = params.attempt_id {
+        attempt_filter.insert("_id", attempt_id);
+    }
+    if let Some(user_id) = params.user_id {
+        attempt_filter.insert("userId", user_id);
+    }
+    if let Some(exam_id) = params.exam_id {
+        attempt_filter.insert("examId", exam_id);
+    }
+    if attempt_filter.is_empty() {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            "attempt_id, user_id, or exam_id required".to_string(),
+        ));
+    }
+
+    let number_of_attempts = database
+        .exam_attempt
+        .count_documents(attempt_filter.clone())
+        .await?;
+    if number_of_attempts > MAX_EVENT_QUERY_ATTEMPTS {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{number_of_attempts} attempts match, more than {MAX_EVENT_QUERY_ATTEMPTS}. Narrow the query with user_id, and exam_id."
+            ),
+        ));
+    }
+
+    let mut attempts = database
+        .exam_attempt
+        .clone_with_type::<bson::Document>()
+        .find(attempt_filter)
+        .projection(doc! {"_id": true})
+        .sort(doc! {"startTime": 1})
+        .await?;
+    let mut attempt_ids = vec![];
+    while let Some(attempt) = attempts.try_next().await? {
+        attempt_ids.push(attempt.get_object_id("_id")?);
+    }
+
+    let filter = EventFilter {
+        attempt_ids,
+        from: params.from.as_deref().map(parse_date).transpose()?,
+        to: params.to.as_deref().map(parse_date).transpose()?,
+        kinds: parse_kinds(params.kinds.as_deref())?,
+        after: params.cursor.as_deref().map(parse_cursor).transpose()?,
+        limit: None,
+    };
+    let page = EventPage::query(server_state.event_store.as_ref(), filter, params.limit).await?;
+
+    Ok(Json(page))
+}
+
+/// Page of events, with the counts of every page
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventPage {
+    /// Oldest first
+    pub events: Vec<config::Event>,
+    /// Number of events matching the kinds, across all pages
+    pub total: usize,
+    /// Pass as `cursor` to get the next page. `None` on the last page.
+    pub next_cursor: Option<String>,
+    /// Number of events of each kind in the time window, whatever the kinds requested.
+    ///
+    /// Counted by the event store, so events failing validation are counted.
+    pub counts: BTreeMap<EventKind, usize>,
+    /// `counts` of each attempt, in the order of the attempts. Attempts without events have no
+    /// counts.
+    pub attempts: Vec<AttemptEventCounts>,
+    /// Of this page
+    pub unknown: Vec<UnknownEvent>,
+    /// Of this page
+    pub dropped: Vec<DroppedEvent>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptEventCounts {
+    pub attempt_id: ObjectId,
+    pub counts: BTreeMap<EventKind, usize>,
+}
+
+impl EventPage {
+    /// Reads a page of at most `limit` rows matching `filter`, after `filter.after`.
+    ///
+    /// Unknown, and invalid, rows take up the page, but are reported in `unknown`, and `dropped`,
+    /// instead of `events`.
+    async fn query(
+        event_store: &dyn EventStore,
+        mut filter: EventFilter,
+        limit: Option<usize>,
+    ) -> Result<Self, Error> {
+        let limit = limit
+            .unwrap_or(DEFAULT_EVENTS_LIMIT)
+            .clamp(1, MAX_EVENTS_LIMIT);
+        // One more row than the page, to know whether there is a next page
+        filter.limit = Some(limit + 1);
+
+        let (mut rows, event_counts) =
+            tokio::try_join!(event_store.events(&filter), event_store.counts(&filter))?;
+
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last()
+                .and_then(EventCursor::of)
+                .map(|cursor| cursor.to_string())
+        } else {
+            None
+        };
+        let events = validate_rows(rows);
+
+        let mut counts = BTreeMap::new();
+        let mut attempt_counts: HashMap<ObjectId, BTreeMap<EventKind, usize>> = HashMap::new();
+        for EventCount {
+            attempt_id,
+            kind,
+            count,
+        } in event_counts
+        {
+            *counts.entry(kind).or_default() += count;
+            *attempt_counts
+                .entry(attempt_id)
+                .or_default()
+                .entry(kind)
+                .or_default() += count;
+        }
+        let attempts = filter
+            .attempt_ids
+            .iter()
+            .filter_map(|attempt_id| {
+                attempt_counts
+                    .remove(attempt_id)
+                    .map(|counts| AttemptEventCounts {
+                        attempt_id: *attempt_id,
+                        counts,
+                    })
+            })
+            .collect();
+        let total = counts
+            .iter()
+            .filter(|(kind, _)| filter.kinds.is_empty() || filter.kinds.contains(kind))
+            .map(|(_, count)| count)
+            .sum();
+
+        Ok(EventPage {
+            events: events.events,
+            total,
+            next_cursor,
+            counts,
+            attempts,
+            unknown: events.unknown,
+            dropped: events.dropped,
+        })
+    }
+}
+
+/// Parses a `nextCursor`
+fn parse_cursor(cursor: &str) -> Result<EventCursor, Error> {
+    EventCursor::parse(cursor).ok_or(Error::Server(
+        StatusCode::BAD_REQUEST,
+        format!("invalid cursor: {cursor}"),
+    ))
+}
+
+fn parse_kinds(kinds: Option<&str>) -> Result<Vec<EventKind>, Error> {
+    let Some(kinds) = kinds else {
+        return Ok(vec![]);
+    };
+    kinds
+        .split(',')
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
+        .map(|kind| {
+            EventKind::from_name(kind).ok_or(Error::Server(
+                StatusCode::BAD_REQUEST,
+                format!("unknown event kind: {kind}"),
+            ))
+        })
+        .collect()
+}
+
+/// Get all events of an attempt, oldest first.
+///
+/// See `query_events`.
+pub async fn events_by_attempt_id(
+    event_store: &dyn EventStore,
+    attempt_id: ObjectId,
+) -> Result<AttemptEvents, Error> {
+    query_events(event_store, &EventFilter::attempt(attempt_id)).await
+}
+
+/// Get the events matching `filter`, oldest first. Events at the same instant are ordered by id.
+///
+/// See `validate_rows`.
+pub async fn query_events(
+    event_store: &dyn EventStore,
+    filter: &EventFilter,
+) -> Result<AttemptEvents, Error> {
+    let rows = event_store.events(filter).await?;
+
+    Ok(validate_rows(rows))
+}
+
+/// Validates `rows`, keeping their order.
+///
+/// Rows which fail validation are logged, and reported in `dropped`. Rows of an unknown kind are
+/// reported in `unknown`.
+fn validate_rows(rows: Vec<serde_json::Value>) -> AttemptEvents {
+    let mut events = AttemptEvents::default();
+    for row in rows {
+        let id = row.get("id").and_then(|id| id.as_str()).map(String::from);
+        let kind = row
+            .get("kind")
+            .and_then(|kind| kind.as_str())
+            .map(String::from);
+
+        let raw: config::RawEvent = match serde_json::from_value(row) {
+            Ok(raw) => raw,
             Err(e) => {
-                warn!(error = ?e, "unable to deserialize event");
-                None
+                warn!(?id, error = ?e, "unable to deserialize event");
+                events.dropped.push(DroppedEvent {
+                    id,
+                    kind,
+                    error: e.to_string(),
+                });
+                continue;
             }
-        })
-        .collect();
+        };
+        let timestamp = raw.timestamp.clone();
 
-    events.sort_by(|a, b| (a.timestamp).cmp(&b.timestamp));
+        match config::Event::try_from(raw) {
+            Ok(event) => events.events.push(event),
+            Err(EventError::UnknownKind(kind)) => events.unknown.push(UnknownEvent {
+                id: id.unwrap_or_default(),
+                kind,
+                timestamp,
+            }),
+            Err(e) => {
+                warn!(?id, error = %e, "invalid event");
+                events.dropped.push(DroppedEvent {
+                    id,
+                    kind,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
 
-    Ok(Json(events))
+    events
 }
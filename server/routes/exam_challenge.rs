@@ -8,7 +8,12 @@ use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
-use crate::{database::prisma, errors::Error, state::ServerState};
+use crate::{
+    audit::{self, AuditEntry},
+    database::{exam_creator::ExamCreatorAuditAction, prisma},
+    errors::Error,
+    state::ServerState,
+};
 
 /// Get all exam-challenge mappings for the given exam id.
 #[instrument(skip_all, err(Debug), level = "debug")]
@@ -46,7 +51,7 @@ pub struct PutExamChallengeBody {
 /// TODO: Use `x_many` queries, and fewer ops
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn put_exam_challenges(
-    _: prisma::ExamCreatorUser,
+    user: prisma::ExamCreatorUser,
     State(state): State<ServerState>,
     Path(exam_id): Path<ObjectId>,
     Json(exam_environment_challenges): Json<Vec<PutExamChallengeBody>>,
@@ -107,5 +112,20 @@ pub async fn put_exam_challenges(
         .try_collect()
         .await?;
 
+    let challenge_ids = |exam_challenges: &[prisma::ExamEnvironmentChallenge]| {
+        exam_challenges
+            .iter()
+            .map(|ec| ec.challenge_id)
+            .collect::<Vec<_>>()
+    };
+    audit::record(
+        &state,
+        &user,
+        AuditEntry::new(ExamCreatorAuditAction::ExamChallengesUpdate, vec![exam_id])
+            .before(doc! {"challengeIds": challenge_ids(&existing_exam_challenges)})
+            .after(doc! {"challengeIds": challenge_ids(&updated_exam_challenges)}),
+    )
+    .await;
+
     Ok(Json(updated_exam_challenges))
 }
@@ -15,8 +15,9 @@ use tokio_stream::wrappers::ReceiverStream;
 use tracing::{info, instrument};
 
 use crate::{
+    audit::{self, AuditEntry, exam_summary},
     config,
-    database::{Database, prisma},
+    database::{Database, exam_creator::ExamCreatorAuditAction, prisma},
     errors::Error,
     generate,
     state::ServerState,
@@ -118,7 +119,7 @@ pub async fn get_exam_by_id(
 /// Create an exam
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn post_exam(
-    _: prisma::ExamCreatorUser,
+    user: prisma::ExamCreatorUser,
     State(state): State<ServerState>,
 ) -> Result<Json<prisma::ExamCreatorExam>, Error> {
     info!("post_exam");
@@ -130,13 +131,21 @@ pub async fn post_exam(
         .insert_one(&exam)
         .await?;
 
+    audit::record(
+        &state,
+        &user,
+        AuditEntry::new(ExamCreatorAuditAction::ExamCreate, vec![exam.id])
+            .after(exam_summary(&exam)),
+    )
+    .await;
+
     Ok(Json(exam))
 }
 
 /// Update an exam
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn put_exam(
-    _: prisma::ExamCreatorUser,
+    user: prisma::ExamCreatorUser,
     State(state): State<ServerState>,
     Path(exam_id): Path<ObjectId>,
     Json(exam): Json<prisma::ExamCreatorExam>,
@@ -151,12 +160,25 @@ pub async fn put_exam(
         )
         .into());
     }
-    state
+    // Returns the pre-replacement document
+    let old_exam = state
         .production_database
         .exam_creator_exam
-        .replace_one(doc! { "_id": exam_id }, &exam)
+        .find_one_and_replace(doc! { "_id": exam_id }, &exam)
         .await?;
 
+    // Nothing was replaced, so nothing is audited
+    if let Some(old_exam) = &old_exam {
+        audit::record(
+            &state,
+            &user,
+            AuditEntry::new(ExamCreatorAuditAction::ExamUpdate, vec![exam_id])
+                .before(exam_summary(old_exam))
+                .after(exam_summary(&exam)),
+        )
+        .await;
+    }
+
     Ok(Json(exam))
 }
 
@@ -166,7 +188,7 @@ pub async fn put_exam(
 /// NOTE: Staging has a special case where the `ExamEnvironmentChallenge` documents need to be copied over
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn put_exam_by_id_to_staging(
-    _auth_user: prisma::ExamCreatorUser,
+    auth_user: prisma::ExamCreatorUser,
     State(state): State<ServerState>,
     Path(exam_id): Path<ObjectId>,
 ) -> Result<(), Error> {
@@ -223,6 +245,15 @@ pub async fn put_exam_by_id_to_staging(
         .attribute("database_environment", "staging")
         .capture();
 
+    audit::record(
+        &state,
+        &auth_user,
+        AuditEntry::new(ExamCreatorAuditAction::ExamSeed, vec![exam_id])
+            .database_environment(prisma::ExamCreatorDatabaseEnvironment::Staging)
+            .after(exam_summary(&exam_creator_exam)),
+    )
+    .await;
+
     Ok(())
 }
 
@@ -230,7 +261,7 @@ pub async fn put_exam_by_id_to_staging(
 /// Upserts it into production database `ExamEnvironmentExam`
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn put_exam_by_id_to_production(
-    _auth_user: prisma::ExamCreatorUser,
+    auth_user: prisma::ExamCreatorUser,
     State(state): State<ServerState>,
     Path(exam_id): Path<ObjectId>,
 ) -> Result<(), Error> {
@@ -261,6 +292,15 @@ pub async fn put_exam_by_id_to_production(
         .attribute("database_environment", "production")
         .capture();
 
+    audit::record(
+        &state,
+        &auth_user,
+        AuditEntry::new(ExamCreatorAuditAction::ExamSeed, vec![exam_id])
+            .database_environment(prisma::ExamCreatorDatabaseEnvironment::Production)
+            .after(exam_summary(&exam_creator_exam)),
+    )
+    .await;
+
     Ok(())
 }
 
@@ -302,7 +342,7 @@ pub struct PutGenerateExamResponse {
 /// Generate an exam based on the exam configuration
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn put_generations_by_exam_id_with_database_environment(
-    _auth_user: prisma::ExamCreatorUser,
+    auth_user: prisma::ExamCreatorUser,
     State(state): State<ServerState>,
     Path((exam_id, database_environment)): Path<(ObjectId, prisma::ExamCreatorDatabaseEnvironment)>,
     Json(body): Json<PutGenerateExamBody>,
@@ -322,15 +362,40 @@ pub async fn put_generations_by_exam_id_with_database_environment(
             StatusCode::BAD_REQUEST,
             format!("exam non-existent: {exam_id}"),
         ))?;
-    put_generations_by_exam_id(body.count, database, exam_id, exam_creator_exam).await
+
+    put_generations_by_exam_id(
+        body.count,
+        database,
+        exam_id,
+        exam_creator_exam,
+        move |generated| async move {
+            audit::record(
+                &state,
+                &auth_user,
+                AuditEntry::new(ExamCreatorAuditAction::ExamGenerate, vec![exam_id])
+                    .database_environment(database_environment)
+                    .after(doc! {"count": generated as i32, "requested": body.count as i32}),
+            )
+            .await;
+        },
+    )
+    .await
 }
 
-async fn put_generations_by_exam_id(
+/// Generates `count` exams, streaming the progress.
+///
+/// `on_generated` is called with the number of exams generated, once generation ends, if any were.
+async fn put_generations_by_exam_id<F, Fut>(
     count: i16,
     database: Database,
     exam_id: ObjectId,
     exam_creator_exam: prisma::ExamCreatorExam,
-) -> Result<impl IntoResponse, Error> {
+    on_generated: F,
+) -> Result<impl IntoResponse, Error>
+where
+    F: FnOnce(i16) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
     // Convert to ExamInput for generation
     let exam_input = generate::ExamInput {
         id: exam_creator_exam.id,
@@ -347,12 +412,13 @@ async fn put_generations_by_exam_id(
         let generation_timeout = std::time::Duration::from_secs(10);
 
         let generation_future = async {
+            let mut generated = 0;
             for i in 0..count {
                 loop {
                     // Check timeout within the retry loop
                     if generation_start.elapsed() > generation_timeout {
                         tracing::warn!("Exam generation timed out after 10 seconds.");
-                        return;
+                        return generated;
                     }
 
                     match generate::generate_exam(exam_input.clone()) {
@@ -365,8 +431,9 @@ async fn put_generations_by_exam_id(
                                     e
                                 );
                                 // The sender `tx` is dropped here, closing the channel and ending the stream.
-                                return;
+                                return generated;
                             }
+                            generated += 1;
 
                             info!("Successfully generated exam: {}", generated_exam.id);
 
@@ -401,10 +468,15 @@ async fn put_generations_by_exam_id(
                     }
                 }
             }
+            generated
         };
 
-        generation_future.await;
+        let generated = generation_future.await;
         // The sender `tx` is dropped when the task finishes or times out, closing the stream.
+        drop(tx);
+        if generated > 0 {
+            on_generated(generated).await;
+        }
     });
 
     // 4. Create a stream from the receiver
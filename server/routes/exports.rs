@@ -0,0 +1,231 @@
+use axum::{
+    extract::{Query, State},
+    response::Response,
+};
+use futures_util::{TryStreamExt, stream};
+use http::StatusCode;
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    analysis::ItemAnalysisBuilder,
+    database::{database_environment, prisma},
+    errors::Error,
+    export::{
+        AttemptQuestionRow, EventRow, ExportFormat, ItemStatisticRow, ModerationRow,
+        export_response,
+    },
+    routes::{
+        attempts::construct_attempts, date_range_filter, events::events_by_attempt_id,
+        metrics::for_each_moderated_attempt,
+    },
+    state::ServerState,
+};
+
+/// Number of attempts constructed at once
+const ATTEMPT_BATCH_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    pub exam_id: Option<ObjectId>,
+    /// RFC 3339 date, inclusive
+    pub from: Option<String>,
+    /// RFC 3339 date, exclusive
+    pub to: Option<String>,
+}
+
+impl ExportQuery {
+    /// Filter of attempts started in the date range, of the exam
+    fn attempt_filter(&self) -> Result<bson::Document, Error> {
+        let mut filter = doc! {};
+        if let Some(exam_id) = self.exam_id {
+            filter.insert("examId", exam_id);
+        }
+        let start_time = date_range_filter(self.from.as_deref(), self.to.as_deref())?;
+        if !start_time.is_empty() {
+            filter.insert("startTime", start_time);
+        }
+        Ok(filter)
+    }
+}
+
+/// Export every presented question of every attempt started in the date range, one row each
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_attempts_export(
+    user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<ExportQuery>,
+) -> Result<Response, Error> {
+    let database = database_environment(&state, &user).clone();
+
+    let exam_attempts = database
+        .exam_attempt
+        .find(params.attempt_filter()?)
+        .sort(doc! {"startTime": 1})
+        .await?;
+
+    let rows = exam_attempts
+        .map_err(Error::from)
+        .try_chunks(ATTEMPT_BATCH_SIZE)
+        .map_err(|e| e.1)
+        .and_then(move |exam_attempts| {
+            let database = database.clone();
+            async move { construct_attempts(&database, &exam_attempts).await }
+        })
+        .map_ok(|attempts| {
+            let rows: Vec<_> = attempts
+                .iter()
+                .flat_map(AttemptQuestionRow::from_attempt)
+                .collect();
+            stream::iter(rows.into_iter().map(Ok))
+        })
+        .try_flatten();
+
+    Ok(export_response(params.format, "attempts", rows))
+}
+
+#[derive(Deserialize)]
+struct ModerationWithAttempt {
+    #[serde(flatten)]
+    moderation: prisma::ExamEnvironmentExamModeration,
+    attempt: AttemptIds,
+}
+
+#[derive(Deserialize)]
+struct AttemptIds {
+    #[serde(rename = "examId")]
+    exam_id: ObjectId,
+    #[serde(rename = "userId")]
+    user_id: ObjectId,
+}
+
+/// Export every moderation of attempts submitted in the date range
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_moderations_export(
+    user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<ExportQuery>,
+) -> Result<Response, Error> {
+    let database = database_environment(&state, &user);
+
+    let submission_date = date_range_filter(params.from.as_deref(), params.to.as_deref())?;
+    let mut pipeline = vec![];
+    if !submission_date.is_empty() {
+        pipeline.push(doc! {"$match": {"submissionDate": submission_date}});
+    }
+    pipeline.push(doc! {
+        "$lookup": {
+            "from": "ExamEnvironmentExamAttempt",
+            "localField": "examAttemptId",
+            "foreignField": "_id",
+            "pipeline": [{"$project": {"examId": true, "userId": true}}],
+            "as": "attempt",
+        }
+    });
+    // Moderations of deleted attempts are skipped
+    pipeline.push(doc! {"$unwind": "$attempt"});
+    if let Some(exam_id) = params.exam_id {
+        pipeline.push(doc! {"$match": {"attempt.examId": exam_id}});
+    }
+    pipeline.push(doc! {"$sort": {"submissionDate": 1}});
+
+    let moderations = database
+        .exam_environment_exam_moderation
+        .aggregate(pipeline)
+        .with_type::<ModerationWithAttempt>()
+        .await?;
+
+    let rows = moderations
+        .map_err(Error::from)
+        .map_ok(|m| ModerationRow::new(m.moderation, m.attempt.exam_id, m.attempt.user_id));
+
+    Ok(export_response(params.format, "moderations", rows))
+}
+
+/// Export the item statistics of every question of an exam, over moderated attempts started in
+/// the date range
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_item_statistics_export(
+    user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<ExportQuery>,
+) -> Result<Response, Error> {
+    let database = database_environment(&state, &user);
+
+    let exam_id = params.exam_id.ok_or(Error::Server(
+        StatusCode::BAD_REQUEST,
+        "exam_id is required for item statistics".to_string(),
+    ))?;
+    let exam = database
+        .exam
+        .find_one(doc! { "_id": exam_id })
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("exam non-existent: {exam_id}"),
+        ))?;
+
+    let start_time = date_range_filter(params.from.as_deref(), params.to.as_deref())?;
+    let from = start_time.get_datetime("$gte").ok().copied();
+    let to = start_time.get_datetime("$lt").ok().copied();
+
+    // There is one row per question, so the analysis is computed before the first row. It is
+    // computed in the body, so the response has started, and is not limited by the timeout.
+    let database = database.clone();
+    let rows = stream::once(async move {
+        let mut builder = ItemAnalysisBuilder::new(&exam);
+        for_each_moderated_attempt(&database, &exam, None, |_, attempt| {
+            let in_range = from.is_none_or(|from| attempt.start_time >= from)
+                && to.is_none_or(|to| attempt.start_time < to);
+            if in_range {
+                builder.add(attempt);
+            }
+        })
+        .await?;
+        let item_analysis = builder.build();
+
+        Ok::<_, Error>(stream::iter(
+            item_analysis
+                .questions
+                .into_iter()
+                .map(move |q| Ok(ItemStatisticRow::new(exam_id, q))),
+        ))
+    })
+    .try_flatten();
+
+    Ok(export_response(params.format, "item-statistics", rows))
+}
+
+/// Export every event of attempts started in the date range
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_events_export(
+    user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<ExportQuery>,
+) -> Result<Response, Error> {
+    let database = database_environment(&state, &user);
+
+    let attempt_ids = database
+        .exam_attempt
+        .clone_with_type::<bson::Document>()
+        .find(params.attempt_filter()?)
+        .projection(doc! {"_id": true})
+        .sort(doc! {"startTime": 1})
+        .await?;
+
+    let event_store = state.event_store.clone();
+    let rows = attempt_ids
+        .map_err(Error::from)
+        .and_then(|attempt| async move { Ok(attempt.get_object_id("_id")?) })
+        .and_then(move |attempt_id| {
+            let event_store = event_store.clone();
+            async move { events_by_attempt_id(event_store.as_ref(), attempt_id).await }
+        })
+        .map_ok(|events| stream::iter(events.events.into_iter().map(|e| Ok(EventRow::from(e)))))
+        .try_flatten();
+
+    Ok(export_response(params.format, "events", rows))
+}
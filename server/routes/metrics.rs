@@ -1,6 +1,8 @@
+use std::{collections::HashMap, time::Duration};
+
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
 };
 use futures_util::TryStreamExt;
 use http::StatusCode;
@@ -10,8 +12,12 @@ use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::{
-    database::{database_environment, prisma},
+    analysis::{ExamPsychometrics, ItemAnalysis, ItemAnalysisBuilder, PsychometricsBuilder},
+    cache::{CacheKey, CacheKind},
+    config,
+    database::{Database, database_environment, prisma},
     errors::Error,
+    routes::date_range_filter,
     state::ServerState,
 };
 
@@ -63,8 +69,6 @@ pub struct GetExamMetricsById {
     exam: prisma::ExamEnvironmentExam,
     attempts: Vec<prisma::ExamEnvironmentExamAttempt>,
     generations: Vec<prisma::ExamEnvironmentGeneratedExam>,
-    /// When the cache for this sampled data expires
-    expire_at: std::time::SystemTime,
 }
 
 #[instrument(skip_all, err(Debug))]
@@ -73,28 +77,27 @@ pub async fn get_exam_metrics_by_exam_id(
     State(state): State<ServerState>,
     Path(exam_id): Path<ObjectId>,
 ) -> Result<Json<GetExamMetricsById>, Error> {
-    {
-        let mut cache = state.exam_metrics_by_id_cache.lock().unwrap();
-        if let Some(cached_response) = cache.iter().find(|c| c.exam.id == exam_id) {
-            if cached_response.expire_at < std::time::SystemTime::now() {
-                // Remove expired cached response
-                cache.retain(|c| c.exam.id != exam_id);
-            } else {
-                sentry::metrics::counter("cache.access", 1)
-                    .attribute("cache", "exam_metrics_by_id")
-                    .attribute("outcome", "hit")
-                    .capture();
-                return Ok(Json(cached_response.clone()));
-            }
-        }
-    }
+    let database = database_environment(&state, &user).clone();
+    let key = CacheKey::new(
+        &user.settings.database_environment,
+        CacheKind::ExamMetrics,
+        exam_id.to_hex(),
+    );
 
-    sentry::metrics::counter("cache.access", 1)
-        .attribute("cache", "exam_metrics_by_id")
-        .attribute("outcome", "miss")
-        .capture();
+    let response = state
+        .metrics_cache
+        .get_or_compute(key, Duration::from_secs(2 * 60 * 60), move || {
+            exam_metrics_by_exam_id(database, exam_id)
+        })
+        .await?;
 
-    let database = database_environment(&state, &user);
+    Ok(Json(response))
+}
+
+async fn exam_metrics_by_exam_id(
+    database: Database,
+    exam_id: ObjectId,
+) -> Result<GetExamMetricsById, Error> {
     let exam = database
         .exam
         .find_one(doc! { "_id": exam_id })
@@ -149,19 +152,200 @@ pub async fn get_exam_metrics_by_exam_id(
         generations.push(generation);
     }
 
-    let response = GetExamMetricsById {
+    Ok(GetExamMetricsById {
         exam,
         attempts: attempts_sample,
         generations,
-        expire_at: std::time::SystemTime::now() + std::time::Duration::from_secs(2 * 60 * 60), // 2 hours
+    })
+}
+
+#[derive(Deserialize)]
+pub struct GetItemAnalysisQuery {
+    /// Only include attempts whose moderation has this status
+    pub status: Option<prisma::ExamEnvironmentExamModerationStatus>,
+}
+
+/// Get the item analysis of every question of an exam, over all moderated attempts
+#[instrument(skip_all, err(Debug))]
+pub async fn get_item_analysis_by_exam_id(
+    user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(exam_id): Path<ObjectId>,
+    Query(params): Query<GetItemAnalysisQuery>,
+) -> Result<Json<ItemAnalysis>, Error> {
+    let database = database_environment(&state, &user).clone();
+    let status = params.status;
+    let key = CacheKey::new(
+        &user.settings.database_environment,
+        CacheKind::ItemAnalysis,
+        cache_query(exam_id, status.as_ref()),
+    );
+
+    let item_analysis = state
+        .metrics_cache
+        .get_or_compute(key, ANALYSIS_TTL, move || async move {
+            let exam = database
+                .exam
+                .find_one(doc! { "_id": exam_id })
+                .await?
+                .ok_or(Error::Server(
+                    StatusCode::BAD_REQUEST,
+                    format!("exam non-existent: {exam_id}"),
+                ))?;
+
+            let mut builder = ItemAnalysisBuilder::new(&exam);
+            for_each_moderated_attempt(&database, &exam, status.as_ref(), |_, attempt| {
+                builder.add(attempt)
+            })
+            .await?;
+
+            Ok(builder.build())
+        })
+        .await?;
+
+    Ok(Json(item_analysis))
+}
+
+/// Attempts are only added to the analyses once moderated, so analyses can be cached for long
+const ANALYSIS_TTL: Duration = Duration::from_secs(60 * 60);
+
+fn cache_query(
+    exam_id: ObjectId,
+    status: Option<&prisma::ExamEnvironmentExamModerationStatus>,
+) -> String {
+    match status {
+        Some(status) => format!("{exam_id}?status={}", status.to_string()),
+        None => exam_id.to_hex(),
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetPsychometricsResponse {
+    /// `None` if the exam does not exist in the database environment
+    pub staging: Option<ExamPsychometrics>,
+    pub production: Option<ExamPsychometrics>,
+}
+
+/// Get score statistics and reliability of an exam, per generation, in both database environments,
+/// so exam versions can be compared.
+#[instrument(skip_all, err(Debug))]
+pub async fn get_psychometrics_by_exam_id(
+    _: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(exam_id): Path<ObjectId>,
+    Query(params): Query<GetItemAnalysisQuery>,
+) -> Result<Json<GetPsychometricsResponse>, Error> {
+    let mut by_environment = vec![];
+    for database_environment in [
+        prisma::ExamCreatorDatabaseEnvironment::Staging,
+        prisma::ExamCreatorDatabaseEnvironment::Production,
+    ] {
+        let database = match database_environment {
+            prisma::ExamCreatorDatabaseEnvironment::Staging => state.staging_database.clone(),
+            prisma::ExamCreatorDatabaseEnvironment::Production => state.production_database.clone(),
+        };
+        let status = params.status.clone();
+        let key = CacheKey::new(
+            &database_environment,
+            CacheKind::Psychometrics,
+            cache_query(exam_id, status.as_ref()),
+        );
+        let psychometrics = state
+            .metrics_cache
+            .get_or_compute(key, ANALYSIS_TTL, move || async move {
+                psychometrics(&database, exam_id, status.as_ref()).await
+            })
+            .await?;
+        by_environment.push(psychometrics);
+    }
+    let production = by_environment.pop().flatten();
+    let staging = by_environment.pop().flatten();
+
+    Ok(Json(GetPsychometricsResponse {
+        staging,
+        production,
+    }))
+}
+
+async fn psychometrics(
+    database: &Database,
+    exam_id: ObjectId,
+    status: Option<&prisma::ExamEnvironmentExamModerationStatus>,
+) -> Result<Option<ExamPsychometrics>, Error> {
+    let Some(exam) = database.exam.find_one(doc! { "_id": exam_id }).await? else {
+        return Ok(None);
     };
 
-    {
-        let mut cache = state.exam_metrics_by_id_cache.lock().unwrap();
-        cache.push(response.clone());
+    let mut builder = PsychometricsBuilder::new(&exam);
+    for_each_moderated_attempt(database, &exam, status, |exam_attempt, attempt| {
+        builder.add(exam_attempt.generated_exam_id, attempt)
+    })
+    .await?;
+
+    Ok(Some(builder.build()))
+}
+
+/// Streams all moderated attempts of an exam, constructing each as an `Attempt`, alongside the raw attempt.
+///
+/// Attempts are not collected, because there can be too many to hold in memory.
+pub async fn for_each_moderated_attempt(
+    database: &Database,
+    exam: &prisma::ExamEnvironmentExam,
+    status: Option<&prisma::ExamEnvironmentExamModerationStatus>,
+    mut f: impl FnMut(&prisma::ExamEnvironmentExamAttempt, &config::Attempt),
+) -> Result<(), Error> {
+    let mut pipeline = vec![doc! {
+        "$match": {
+            "examId": exam.id,
+            "$and": [
+                {"examModerationId": { "$exists": true }},
+                {"examModerationId": { "$ne": null}}
+            ]
+        }
+    }];
+    if let Some(status) = status {
+        pipeline.push(doc! {
+            "$lookup": {
+                "from": "ExamEnvironmentExamModeration",
+                "localField": "_id",
+                "foreignField": "examAttemptId",
+                "pipeline": [{"$project": {"status": true}}],
+                "as": "moderation",
+            }
+        });
+        pipeline.push(doc! {"$match": {"moderation.status": bson::serialize_to_bson(status)?}});
+        pipeline.push(doc! {"$unset": "moderation"});
     }
 
-    Ok(Json(response))
+    let mut exam_attempts = database
+        .exam_attempt
+        .aggregate(pipeline)
+        .with_type::<prisma::ExamEnvironmentExamAttempt>()
+        .await?;
+
+    let mut generations = HashMap::<ObjectId, prisma::ExamEnvironmentGeneratedExam>::new();
+    while let Some(exam_attempt) = exam_attempts.try_next().await? {
+        if !generations.contains_key(&exam_attempt.generated_exam_id) {
+            let generation = database
+                .generated_exam
+                .find_one(doc! { "_id": exam_attempt.generated_exam_id })
+                .await?
+                .ok_or(Error::Server(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!(
+                        "generated exam non-existent: {}",
+                        exam_attempt.generated_exam_id
+                    ),
+                ))?;
+            generations.insert(exam_attempt.generated_exam_id, generation);
+        }
+        let generation = &generations[&exam_attempt.generated_exam_id];
+
+        let attempt = config::construct_attempt(exam, generation, &exam_attempt);
+        f(&exam_attempt, &attempt);
+    }
+
+    Ok(())
 }
 
 #[serde_with::serde_as]
@@ -192,48 +376,255 @@ pub async fn get_attempts_metrics(
     user: prisma::ExamCreatorUser,
     State(state): State<ServerState>,
 ) -> Result<Json<Vec<GetAttemptsMetrics>>, Error> {
-    {
-        let mut cache = state.attempt_metrics_cache.lock().unwrap();
-        if cache.expire_at < std::time::SystemTime::now() {
-            // Remove expired cached response
-            cache.data.clear();
-        } else {
-            if !cache.data.is_empty() {
-                tracing::debug!(len = cache.data.len(), "using cache");
-                sentry::metrics::counter("cache.access", 1)
-                    .attribute("cache", "attempt_metrics")
-                    .attribute("outcome", "hit")
-                    .capture();
-                return Ok(Json(cache.data.clone()));
+    let database = database_environment(&state, &user).clone();
+    let key = CacheKey::new(
+        &user.settings.database_environment,
+        CacheKind::AttemptMetrics,
+        "",
+    );
+
+    let attempts_metrics = state
+        .metrics_cache
+        .get_or_compute(key, Duration::from_secs(24 * 60 * 60), move || async move {
+            let mut attempts = database
+                .exam_attempt
+                .clone_with_type::<mongodb::bson::Document>()
+                .find(doc! {})
+                .projection(doc! {"startTime": true, "examId": true})
+                .await?;
+
+            let mut attempts_metrics: Vec<GetAttemptsMetrics> = vec![];
+
+            while let Some(attempt) = attempts.try_next().await? {
+                let attempt = attempt.try_into()?;
+                attempts_metrics.push(attempt);
             }
+
+            Ok(attempts_metrics)
+        })
+        .await?;
+
+    Ok(Json(attempts_metrics))
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSeriesInterval {
+    Hour,
+    Day,
+    /// Weeks start on Monday
+    Week,
+}
+
+impl TimeSeriesInterval {
+    fn unit(&self) -> &'static str {
+        match self {
+            TimeSeriesInterval::Hour => "hour",
+            TimeSeriesInterval::Day => "day",
+            TimeSeriesInterval::Week => "week",
         }
     }
+}
 
-    sentry::metrics::counter("cache.access", 1)
-        .attribute("cache", "attempt_metrics")
-        .attribute("outcome", "miss")
-        .capture();
+#[derive(Deserialize)]
+pub struct GetAttemptsTimeSeriesQuery {
+    pub interval: TimeSeriesInterval,
+    /// RFC 3339 date, inclusive
+    pub from: String,
+    /// RFC 3339 date, exclusive
+    pub to: String,
+    /// Olson timezone (e.g. `Europe/London`), or `±HH:MM` UTC offset (e.g. `+02:00`), buckets are
+    /// aligned to.
+    ///
+    /// Defaults to UTC.
+    pub timezone: Option<String>,
+    pub exam_id: Option<ObjectId>,
+}
 
-    let database = database_environment(&state, &user);
+#[serde_with::serde_as]
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptsTimeSeriesBucket {
+    exam_id: ObjectId,
+    /// Start of the bucket
+    #[serde_as(as = "bson::serde_helpers::datetime::AsRfc3339String")]
+    start: mongodb::bson::DateTime,
+    /// Attempts started in the bucket
+    attempts: u64,
+    /// Attempts started in the bucket, which have been submitted
+    completed: u64,
+    pending: u64,
+    approved: u64,
+    denied: u64,
+}
 
-    let mut attempts = database
-        .exam_attempt
-        .clone_with_type::<mongodb::bson::Document>()
-        .find(doc! {})
-        .projection(doc! {"startTime": true, "examId": true})
+/// Get the number of attempts started per exam, per interval, with their completion and moderation outcomes.
+///
+/// Buckets without attempts are omitted.
+#[instrument(skip_all, err(Debug))]
+pub async fn get_attempts_time_series(
+    user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<GetAttemptsTimeSeriesQuery>,
+) -> Result<Json<Vec<AttemptsTimeSeriesBucket>>, Error> {
+    let database = database_environment(&state, &user).clone();
+
+    let mut filter = doc! {
+        "startTime": date_range_filter(Some(&params.from), Some(&params.to))?,
+    };
+    if let Some(exam_id) = params.exam_id {
+        filter.insert("examId", exam_id);
+    }
+
+    let timezone = match params.timezone {
+        Some(timezone) => parse_timezone(timezone)?,
+        None => "UTC".to_string(),
+    };
+    let key = CacheKey::new(
+        &user.settings.database_environment,
+        CacheKind::AttemptTimeSeries,
+        format!(
+            "interval={}&from={}&to={}&timezone={}&exam_id={}",
+            params.interval.unit(),
+            params.from,
+            params.to,
+            timezone,
+            params.exam_id.map(|id| id.to_hex()).unwrap_or_default()
+        ),
+    );
+
+    let status_count = |status: prisma::ExamEnvironmentExamModerationStatus| -> Result<_, Error> {
+        Ok(doc! {
+            "$sum": {"$cond": [{"$eq": ["$moderationStatus", bson::serialize_to_bson(&status)?]}, 1, 0]}
+        })
+    };
+
+    let pipeline = vec![
+        doc! {"$match": filter},
+        doc! {
+            "$lookup": {
+                "from": "ExamEnvironmentExamModeration",
+                "localField": "_id",
+                "foreignField": "examAttemptId",
+                "pipeline": [{"$project": {"status": true}}],
+                "as": "moderation",
+            }
+        },
+        doc! {"$set": {"moderationStatus": {"$first": "$moderation.status"}}},
+        doc! {
+            "$group": {
+                "_id": {
+                    "examId": "$examId",
+                    "start": {
+                        "$dateTrunc": {
+                            "date": "$startTime",
+                            "unit": params.interval.unit(),
+                            "timezone": timezone.clone(),
+                            "startOfWeek": "monday",
+                        }
+                    },
+                },
+                "attempts": {"$sum": 1},
+                // A moderation is created when an attempt is submitted
+                "completed": {"$sum": {"$cond": [{"$gt": [{"$size": "$moderation"}, 0]}, 1, 0]}},
+                "pending": status_count(prisma::ExamEnvironmentExamModerationStatus::Pending)?,
+                "approved": status_count(prisma::ExamEnvironmentExamModerationStatus::Approved)?,
+                "denied": status_count(prisma::ExamEnvironmentExamModerationStatus::Denied)?,
+            }
+        },
+        doc! {"$sort": {"_id.start": 1, "_id.examId": 1}},
+    ];
+
+    let buckets = state
+        .metrics_cache
+        .get_or_compute(key, Duration::from_secs(5 * 60), move || async move {
+            let mut cursor = database
+                .exam_attempt
+                .aggregate(pipeline)
+                .await
+                .map_err(|e| unknown_timezone(e, &timezone))?;
+
+            let mut buckets = vec![];
+            while let Some(bucket) = cursor.try_next().await? {
+                let id = bucket.get_document("_id")?;
+                buckets.push(AttemptsTimeSeriesBucket {
+                    exam_id: id.get_object_id("examId")?,
+                    start: *id.get_datetime("start")?,
+                    attempts: count(&bucket, "attempts"),
+                    completed: count(&bucket, "completed"),
+                    pending: count(&bucket, "pending"),
+                    approved: count(&bucket, "approved"),
+                    denied: count(&bucket, "denied"),
+                });
+            }
+
+            Ok(buckets)
+        })
         .await?;
 
-    let mut attempts_metrics: Vec<GetAttemptsMetrics> = vec![];
+    Ok(Json(buckets))
+}
 
-    while let Some(attempt) = attempts.try_next().await? {
-        let attempt = attempt.try_into()?;
-        attempts_metrics.push(attempt);
+/// Checks `timezone` is shaped like an Olson timezone (e.g. `Europe/London`), or a `±HH:MM` UTC
+/// offset, before it is passed to `$dateTrunc`.
+///
+/// Whether an Olson timezone exists is left to MongoDB. See `unknown_timezone`.
+fn parse_timezone(timezone: String) -> Result<String, Error> {
+    let is_offset = |tz: &str| {
+        let Some((hours, minutes)) = tz
+            .strip_prefix(['+', '-'])
+            .and_then(|offset| offset.split_once(':'))
+        else {
+            return false;
+        };
+        let is_number = |n: &str, max: u8| {
+            n.len() == 2
+                && n.bytes().all(|b| b.is_ascii_digit())
+                && n.parse::<u8>().is_ok_and(|n| n <= max)
+        };
+        is_number(hours, 23) && is_number(minutes, 59)
+    };
+    let is_olson = |tz: &str| {
+        tz.split('/').count() <= 3
+            && tz.split('/').all(|part| {
+                part.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+            })
+    };
+
+    if is_offset(&timezone) || is_olson(&timezone) {
+        Ok(timezone)
+    } else {
+        Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("invalid timezone {timezone}: expected an Olson timezone, or a ±HH:MM offset"),
+        ))
     }
+}
 
-    {
-        let mut cache = state.attempt_metrics_cache.lock().unwrap();
-        cache.data = attempts_metrics.clone();
+/// Maps MongoDB rejecting a well-formed, but unknown, timezone to a 400
+fn unknown_timezone(e: mongodb::error::Error, timezone: &str) -> Error {
+    const UNRECOGNIZED_TIMEZONE: i32 = 40485;
+    match e.kind.as_ref() {
+        mongodb::error::ErrorKind::Command(command_error)
+            if command_error.code == UNRECOGNIZED_TIMEZONE =>
+        {
+            Error::Server(
+                StatusCode::BAD_REQUEST,
+                format!("unknown timezone {timezone}"),
+            )
+        }
+        _ => e.into(),
     }
+}
 
-    Ok(Json(attempts_metrics))
+/// `$sum` results are `Int32`, unless they overflow
+pub fn count(document: &bson::Document, key: &str) -> u64 {
+    match document.get(key) {
+        Some(bson::Bson::Int32(c)) => *c as u64,
+        Some(bson::Bson::Int64(c)) => *c as u64,
+        _ => 0,
+    }
 }
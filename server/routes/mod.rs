@@ -5,25 +5,35 @@ use axum::{
     response::Response,
 };
 use http::StatusCode;
-use mongodb::bson::doc;
 use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{DateTime, Document, doc};
 use tracing::{info, instrument};
 
-use crate::{database::prisma, errors::Error, state::ServerState};
+use crate::{
+    audit::{self, AuditEntry, exam_summary},
+    database::{exam_creator::ExamCreatorAuditAction, prisma},
+    errors::Error,
+    state::ServerState,
+};
 
+pub mod admin;
+pub mod appeals;
 pub mod attempts;
+pub mod audit_log;
 pub mod auth;
 pub mod events;
 pub mod exam_challenge;
 pub mod exams;
+pub mod exports;
 pub mod metrics;
 pub mod moderations;
+pub mod reports;
 pub mod users;
 pub mod websocket;
 
 #[instrument(skip_all, err(Debug), level = "debug")]
 pub async fn discard_exam_state_by_id(
-    _: prisma::ExamCreatorUser,
+    user: prisma::ExamCreatorUser,
     State(state): State<ServerState>,
     Path(exam_id): Path<ObjectId>,
 ) -> Result<Json<prisma::ExamCreatorExam>, Error> {
@@ -39,13 +49,23 @@ pub async fn discard_exam_state_by_id(
             format!("No exam {exam_id} found"),
         ))?;
 
-    let client_sync = &mut state.client_sync.lock().unwrap();
-    if let Some(exam) = client_sync.exams.iter_mut().find(|e| e.id == exam_id) {
-        *exam = original_exam.clone();
-    } else {
-        info!("No exam in client sync state: {}", exam_id)
+    {
+        let client_sync = &mut state.client_sync.lock().unwrap();
+        if let Some(exam) = client_sync.exams.iter_mut().find(|e| e.id == exam_id) {
+            *exam = original_exam.clone();
+        } else {
+            info!("No exam in client sync state: {}", exam_id)
+        }
     }
 
+    audit::record(
+        &state,
+        &user,
+        AuditEntry::new(ExamCreatorAuditAction::ExamStateDiscard, vec![exam_id])
+            .after(exam_summary(&original_exam)),
+    )
+    .await;
+
     Ok(Json(original_exam))
 }
 
@@ -67,3 +87,26 @@ pub async fn get_status_ping() -> Response {
     );
     response
 }
+
+/// Builds a `{ "$gte": from, "$lt": to }` filter from RFC 3339 dates.
+///
+/// Returns an empty document if neither date is given.
+pub fn date_range_filter(from: Option<&str>, to: Option<&str>) -> Result<Document, Error> {
+    let mut range = doc! {};
+    if let Some(from) = from {
+        range.insert("$gte", parse_date(from)?);
+    }
+    if let Some(to) = to {
+        range.insert("$lt", parse_date(to)?);
+    }
+    Ok(range)
+}
+
+pub fn parse_date(date: &str) -> Result<DateTime, Error> {
+    DateTime::parse_rfc3339_str(date).map_err(|e| {
+        Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("invalid RFC 3339 date {date}: {e}"),
+        )
+    })
+}
@@ -5,13 +5,23 @@ use axum::{
 use bson::oid::ObjectId;
 use futures_util::TryStreamExt;
 use http::StatusCode;
-use mongodb::bson::doc;
+use mongodb::bson::{DateTime, Document, doc};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::{
-    database::{database_environment, prisma},
+    audit::{self, AuditEntry},
+    database::{
+        database_environment,
+        exam_creator::{
+            ExamCreatorAttemptSuspicion, ExamCreatorAuditAction, ExamCreatorDenialReason,
+            ExamCreatorModerationClaim, ExamCreatorModerationDecision, ExamCreatorModerationNote,
+        },
+        prisma,
+    },
     errors::Error,
+    moderation,
+    routes::date_range_filter,
     state::ServerState,
 };
 
@@ -150,3 +160,513 @@ pub async fn get_moderation_by_attempt_id(
 
     Ok(Json(moderation))
 }
+
+/// Get all notes on an attempt's moderation, oldest first
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_moderation_notes_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+) -> Result<Json<Vec<ExamCreatorModerationNote>>, Error> {
+    let database = database_environment(&state, &exam_creator_user);
+    let notes = database
+        .exam_creator_moderation_note
+        .find(doc! {"examAttemptId": attempt_id})
+        .sort(doc! {"createdAt": 1})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(notes))
+}
+
+/// Get all decisions of an attempt's moderation, newest first
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_moderation_decisions_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+) -> Result<Json<Vec<ExamCreatorModerationDecision>>, Error> {
+    let database = database_environment(&state, &exam_creator_user);
+    let decisions = database
+        .exam_creator_moderation_decision
+        .find(doc! {"examAttemptId": attempt_id})
+        .sort(doc! {"decidedAt": -1})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(decisions))
+}
+
+#[derive(Deserialize)]
+pub struct GetDenialReasonsQuery {
+    /// Include reasons which can no longer be picked
+    #[serde(default)]
+    pub include_disabled: bool,
+}
+
+/// Get the denial reasons moderators can pick from, by label
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_denial_reasons(
+    _: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<GetDenialReasonsQuery>,
+) -> Result<Json<Vec<ExamCreatorDenialReason>>, Error> {
+    let filter = if params.include_disabled {
+        doc! {}
+    } else {
+        doc! {"disabled": false}
+    };
+    let reasons = state
+        .production_database
+        .exam_creator_denial_reason
+        .find(filter)
+        .sort(doc! {"label": 1})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(Json(reasons))
+}
+
+#[derive(Deserialize)]
+pub struct PostModerationNoteBody {
+    /// Markdown
+    pub text: String,
+}
+
+/// Add a note to an attempt's moderation
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn post_moderation_note_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+    Json(body): Json<PostModerationNoteBody>,
+) -> Result<Json<ExamCreatorModerationNote>, Error> {
+    let text = body.text.trim().to_string();
+    if text.is_empty() {
+        return Err(Error::Server(
+            StatusCode::BAD_REQUEST,
+            "note text must not be empty".to_string(),
+        ));
+    }
+
+    let database = database_environment(&state, &exam_creator_user);
+    let moderation = database
+        .exam_environment_exam_moderation
+        .find_one(doc! {"examAttemptId": attempt_id})
+        .await?
+        .ok_or(Error::Server(
+            StatusCode::BAD_REQUEST,
+            format!("moderation non-existent for attempt id: {attempt_id}"),
+        ))?;
+
+    let note = ExamCreatorModerationNote {
+        id: ObjectId::new(),
+        exam_attempt_id: attempt_id,
+        author_id: exam_creator_user.id,
+        author_name: exam_creator_user.name.clone(),
+        text,
+        created_at: DateTime::now(),
+        version: 1,
+    };
+
+    database
+        .exam_creator_moderation_note
+        .insert_one(&note)
+        .await?;
+
+    audit::record(
+        &state,
+        &exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::ModerationNoteCreate,
+            vec![attempt_id, moderation.id, note.id],
+        )
+        .database_environment(exam_creator_user.settings.database_environment.clone()),
+    )
+    .await;
+
+    Ok(Json(note))
+}
+
+/// Get the unexpired claim of an attempt's moderation, if any
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_moderation_claim_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+) -> Result<Json<Option<ExamCreatorModerationClaim>>, Error> {
+    let database = database_environment(&state, &exam_creator_user);
+    let claim = moderation::active_claim(database, attempt_id).await?;
+
+    Ok(Json(claim))
+}
+
+/// Claim an attempt's moderation, or renew the user's claim.
+///
+/// Claims expire after `CLAIM_LEASE`, so clients renew them while the moderator is active.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn put_moderation_claim_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+) -> Result<Json<ExamCreatorModerationClaim>, Error> {
+    let claim = moderation::claim_attempt(&state, &exam_creator_user, attempt_id).await?;
+
+    Ok(Json(claim))
+}
+
+/// Release a claim of an attempt's moderation
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn delete_moderation_claim_by_attempt_id(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Path(attempt_id): Path<ObjectId>,
+) -> Result<(), Error> {
+    moderation::release_claim(&state, &exam_creator_user, attempt_id).await
+}
+
+const MAX_MODERATION_QUEUE_LIMIT: i64 = 500;
+
+/// Order of the moderation queue, after unclaimed items
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModerationQueueOrder {
+    /// By submission date, in the direction of `sort`
+    #[default]
+    Submission,
+    /// By suspicion score, highest first. Unscored attempts come last.
+    Risk,
+}
+
+#[derive(Deserialize)]
+pub struct GetModerationQueueQuery {
+    pub status: Option<prisma::ExamEnvironmentExamModerationStatus>,
+    pub exam_id: Option<ObjectId>,
+    pub user_id: Option<ObjectId>,
+    pub moderator_id: Option<ObjectId>,
+    pub challenges_awarded: Option<bool>,
+    /// Only moderations with (or without) an unexpired claim
+    pub claimed: Option<bool>,
+    /// Case-insensitive substring of the moderation feedback
+    pub feedback: Option<String>,
+    /// RFC 3339 date, inclusive
+    pub submitted_from: Option<String>,
+    /// RFC 3339 date, exclusive
+    pub submitted_to: Option<String>,
+    /// RFC 3339 date, inclusive
+    pub moderated_from: Option<String>,
+    /// RFC 3339 date, exclusive
+    pub moderated_to: Option<String>,
+    pub order: Option<ModerationQueueOrder>,
+    /// Sort by submission date: 1 (oldest first, default) or -1 (newest first)
+    pub sort: Option<i32>,
+    pub limit: Option<i64>,
+    /// `nextCursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetModerationQueueResponse {
+    pub items: Vec<ModerationQueueItem>,
+    /// Number of moderations matching the filters, across all pages
+    pub total: u64,
+    /// `None` if this is the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModerationQueueItem {
+    #[serde(flatten)]
+    pub moderation: prisma::ExamEnvironmentExamModeration,
+    pub exam_id: ObjectId,
+    pub user_id: ObjectId,
+    /// Unexpired claim, if any
+    pub claim: Option<ExamCreatorModerationClaim>,
+    /// `None` if the attempt has not been scored yet
+    pub suspicion: Option<ExamCreatorAttemptSuspicion>,
+}
+
+/// Get moderations joined with their attempts and claims, filtered, with cursor-based pagination.
+///
+/// Unclaimed items come first. Items are then sorted by `submissionDate` or suspicion score, then `_id`.
+#[instrument(skip_all, err(Debug))]
+pub async fn get_moderation_queue(
+    exam_creator_user: prisma::ExamCreatorUser,
+    State(server_state): State<ServerState>,
+    Query(params): Query<GetModerationQueueQuery>,
+) -> Result<Json<GetModerationQueueResponse>, Error> {
+    let database = database_environment(&server_state, &exam_creator_user);
+
+    let mut moderation_filter = doc! {};
+    if let Some(status) = &params.status {
+        moderation_filter.insert("status", bson::serialize_to_bson(status)?);
+    }
+    if let Some(moderator_id) = params.moderator_id {
+        moderation_filter.insert("moderatorId", moderator_id);
+    }
+    if let Some(challenges_awarded) = params.challenges_awarded {
+        moderation_filter.insert("challengesAwarded", challenges_awarded);
+    }
+    if let Some(feedback) = &params.feedback {
+        moderation_filter.insert(
+            "feedback",
+            doc! {"$regex": escape_regex(feedback), "$options": "i"},
+        );
+    }
+    let submission_date = date_range_filter(
+        params.submitted_from.as_deref(),
+        params.submitted_to.as_deref(),
+    )?;
+    if !submission_date.is_empty() {
+        moderation_filter.insert("submissionDate", submission_date);
+    }
+    let moderation_date = date_range_filter(
+        params.moderated_from.as_deref(),
+        params.moderated_to.as_deref(),
+    )?;
+    if !moderation_date.is_empty() {
+        moderation_filter.insert("moderationDate", moderation_date);
+    }
+
+    let mut attempt_filter = doc! {};
+    if let Some(exam_id) = params.exam_id {
+        attempt_filter.insert("attempt.examId", exam_id);
+    }
+    if let Some(user_id) = params.user_id {
+        attempt_filter.insert("attempt.userId", user_id);
+    }
+
+    let order = params.order.unwrap_or_default();
+    let (sort_field, sort) = match order {
+        ModerationQueueOrder::Submission => match params.sort {
+            Some(-1) => ("submissionDate", -1),
+            _ => ("submissionDate", 1),
+        },
+        ModerationQueueOrder::Risk => ("riskScore", -1),
+    };
+    let limit = params
+        .limit
+        .unwrap_or(50)
+        .clamp(1, MAX_MODERATION_QUEUE_LIMIT);
+
+    let mut claim_filter = doc! {};
+    if let Some(claimed) = params.claimed {
+        claim_filter.insert("claimed", claimed);
+    }
+
+    let mut page_pipeline = vec![];
+    if let Some(cursor) = &params.cursor {
+        let (claimed, key, id) = parse_queue_cursor(cursor, order)?;
+        let comparison = if sort == 1 { "$gt" } else { "$lt" };
+        let mut after_key = Document::new();
+        after_key.insert(comparison, key.clone());
+        let mut after_id = Document::new();
+        after_id.insert(comparison, id);
+        let mut same_key_after_id = doc! {"claimed": claimed};
+        same_key_after_id.insert(sort_field, key);
+        same_key_after_id.insert("_id", after_id);
+        let mut after_key_match = doc! {"claimed": claimed};
+        after_key_match.insert(sort_field, after_key);
+        page_pipeline.push(doc! {
+            "$match": {
+                "$or": [
+                    {"claimed": {"$gt": claimed}},
+                    after_key_match,
+                    same_key_after_id,
+                ]
+            }
+        });
+    }
+    let mut sort_stage = doc! {"claimed": 1};
+    sort_stage.insert(sort_field, sort);
+    sort_stage.insert("_id", sort);
+    page_pipeline.push(doc! {"$sort": sort_stage});
+    // Fetch one extra item to know if there is a next page
+    page_pipeline.push(doc! {"$limit": limit + 1});
+
+    let pipeline = vec![
+        doc! {"$match": moderation_filter},
+        doc! {
+            "$lookup": {
+                "from": "ExamEnvironmentExamAttempt",
+                "localField": "examAttemptId",
+                "foreignField": "_id",
+                "pipeline": [{"$project": {"examId": true, "userId": true}}],
+                "as": "attempt",
+            }
+        },
+        doc! {"$unwind": "$attempt"},
+        doc! {"$match": attempt_filter},
+        doc! {
+            "$lookup": {
+                "from": "ExamCreatorModerationClaim",
+                "localField": "examAttemptId",
+                "foreignField": "_id",
+                "pipeline": [{"$match": {"expiresAt": {"$gt": DateTime::now()}}}],
+                "as": "claim",
+            }
+        },
+        doc! {"$set": {"claim": {"$first": "$claim"}}},
+        doc! {"$set": {"claimed": {"$ne": [{"$type": "$claim"}, "missing"]}}},
+        doc! {"$match": claim_filter},
+        doc! {
+            "$lookup": {
+                "from": "ExamCreatorAttemptSuspicion",
+                "localField": "examAttemptId",
+                "foreignField": "_id",
+                "as": "suspicion",
+            }
+        },
+        doc! {"$set": {"suspicion": {"$first": "$suspicion"}}},
+        doc! {"$set": {"riskScore": {"$ifNull": ["$suspicion.score", -1.0]}}},
+        doc! {
+            "$facet": {
+                "items": page_pipeline,
+                "total": [{"$count": "count"}],
+            }
+        },
+    ];
+
+    let facet = database
+        .exam_environment_exam_moderation
+        .aggregate(pipeline)
+        .await?
+        .try_next()
+        .await?
+        .unwrap_or_default();
+
+    let total = facet
+        .get_array("total")
+        .ok()
+        .and_then(|total| total.first())
+        .and_then(|count| count.as_document())
+        .and_then(|count| match count.get("count") {
+            Some(bson::Bson::Int32(c)) => Some(*c as u64),
+            Some(bson::Bson::Int64(c)) => Some(*c as u64),
+            _ => None,
+        })
+        .unwrap_or(0);
+
+    let mut items = vec![];
+    for item in facet
+        .get_array("items")
+        .map(|i| i.to_vec())
+        .unwrap_or_default()
+    {
+        let Some(item) = item.as_document() else {
+            continue;
+        };
+        items.push(queue_item_from_document(item.clone())?);
+    }
+
+    let next_cursor = if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        items.last().map(|item| queue_cursor(item, order))
+    } else {
+        None
+    };
+
+    Ok(Json(GetModerationQueueResponse {
+        items,
+        total,
+        next_cursor,
+    }))
+}
+
+/// Splits the joined `attempt`, `claim`, and `suspicion` out of a queue aggregation document
+fn queue_item_from_document(mut document: Document) -> Result<ModerationQueueItem, Error> {
+    let attempt = document.get_document("attempt")?.clone();
+    document.remove("attempt");
+    document.remove("claimed");
+    document.remove("riskScore");
+    let claim = match document.remove("claim") {
+        Some(bson::Bson::Document(claim)) => Some(bson::deserialize_from_document(claim)?),
+        _ => None,
+    };
+    let suspicion = match document.remove("suspicion") {
+        Some(bson::Bson::Document(suspicion)) => Some(bson::deserialize_from_document(suspicion)?),
+        _ => None,
+    };
+    let moderation: prisma::ExamEnvironmentExamModeration =
+        bson::deserialize_from_document(document)?;
+
+    Ok(ModerationQueueItem {
+        moderation,
+        exam_id: attempt.get_object_id("examId")?,
+        user_id: attempt.get_object_id("userId")?,
+        claim,
+        suspicion,
+    })
+}
+
+/// Cursor pointing after the given item: `<claimed 0|1>-<sort key>-<_id hex>`
+///
+/// The sort key is the submission date in millis, or the suspicion score, depending on `order`.
+fn queue_cursor(item: &ModerationQueueItem, order: ModerationQueueOrder) -> String {
+    let key = match order {
+        ModerationQueueOrder::Submission => item
+            .moderation
+            .submission_date
+            .timestamp_millis()
+            .to_string(),
+        ModerationQueueOrder::Risk => item
+            .suspicion
+            .as_ref()
+            .map(|s| s.score)
+            .unwrap_or(-1.0)
+            .to_string(),
+    };
+    format!(
+        "{}-{}-{}",
+        item.claim.is_some() as u8,
+        key,
+        item.moderation.id.to_hex()
+    )
+}
+
+fn parse_queue_cursor(
+    cursor: &str,
+    order: ModerationQueueOrder,
+) -> Result<(bool, bson::Bson, ObjectId), Error> {
+    let invalid_cursor =
+        || Error::Server(StatusCode::BAD_REQUEST, format!("invalid cursor: {cursor}"));
+
+    let (claimed, rest) = cursor.split_once('-').ok_or_else(invalid_cursor)?;
+    let claimed = match claimed {
+        "0" => false,
+        "1" => true,
+        _ => return Err(invalid_cursor()),
+    };
+    // `rsplit_once`, because the sort key can be negative
+    let (key, id) = rest.rsplit_once('-').ok_or_else(invalid_cursor)?;
+    let key = match order {
+        ModerationQueueOrder::Submission => {
+            let millis: i64 = key.parse().map_err(|_| invalid_cursor())?;
+            bson::Bson::DateTime(DateTime::from_millis(millis))
+        }
+        ModerationQueueOrder::Risk => {
+            let score: f64 = key.parse().map_err(|_| invalid_cursor())?;
+            bson::Bson::Double(score)
+        }
+    };
+    let id = ObjectId::parse_str(id).map_err(|_| invalid_cursor())?;
+
+    Ok((claimed, key, id))
+}
+
+/// Escapes regex metacharacters, so user input is matched literally
+fn escape_regex(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use futures_util::TryStreamExt;
+use mongodb::bson::{DateTime, Document, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+    database::{database_environment, exam_creator::ExamCreatorAuditAction, prisma},
+    errors::Error,
+    routes::{date_range_filter, metrics::count},
+    state::ServerState,
+};
+
+#[derive(Deserialize)]
+pub struct GetModeratorReportQuery {
+    /// RFC 3339 date, inclusive
+    pub from: Option<String>,
+    /// RFC 3339 date, exclusive
+    pub to: Option<String>,
+    /// IANA timezone days are bucketed in. Defaults to UTC.
+    pub timezone: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModeratorReport {
+    pub moderators: Vec<ModeratorStatistics>,
+    /// Age of the pending queue, of both database environments
+    pub queues: Vec<QueueAge>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModeratorStatistics {
+    pub moderator_id: ObjectId,
+    /// `None` if the moderator is no longer an Exam Creator user
+    pub moderator_name: Option<String>,
+    pub decisions: u64,
+    pub approved: u64,
+    pub denied: u64,
+    /// `approved / decisions`, or `None` without decisions
+    pub approval_rate: Option<f64>,
+    /// Median time between opening an attempt's moderation page, and deciding
+    pub median_review_ms: Option<f64>,
+    /// Number of decisions with a recorded review time
+    pub timed_reviews: u64,
+    /// Decisions of the moderator, changed by another moderator
+    pub overturned: u64,
+    /// Oldest first
+    pub days: Vec<ModeratorDay>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModeratorDay {
+    /// `YYYY-MM-DD`, in the requested timezone
+    pub date: String,
+    pub decisions: u64,
+    pub approved: u64,
+    pub denied: u64,
+}
+
+#[serde_with::serde_as]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueAge {
+    pub database_environment: prisma::ExamCreatorDatabaseEnvironment,
+    pub pending: u64,
+    /// `None` if nothing is pending
+    #[serde_as(as = "Option<bson::serde_helpers::datetime::AsRfc3339String>")]
+    pub oldest_submission_date: Option<DateTime>,
+}
+
+#[derive(Default)]
+struct ModeratorTally {
+    days: BTreeMap<String, ModeratorDay>,
+    review_ms: Vec<i64>,
+    overturned: u64,
+}
+
+/// Get decisions per moderator per day, review times, and overturned decisions, in the user's
+/// database environment, as well as the age of the pending queues.
+///
+/// Decisions are counted from moderation records, so a decision which was later changed counts
+/// towards the moderator who changed it. Review times are counted from review sessions ended in
+/// the date range, and overturned decisions from the audit log.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_moderator_report(
+    user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<GetModeratorReportQuery>,
+) -> Result<Json<ModeratorReport>, Error> {
+    let database = database_environment(&state, &user);
+    let range = date_range_filter(params.from.as_deref(), params.to.as_deref())?;
+    let timezone = params.timezone.unwrap_or_else(|| "UTC".to_string());
+
+    let approved = bson::serialize_to_bson(&prisma::ExamEnvironmentExamModerationStatus::Approved)?;
+    let denied = bson::serialize_to_bson(&prisma::ExamEnvironmentExamModerationStatus::Denied)?;
+    let pending = bson::serialize_to_bson(&prisma::ExamEnvironmentExamModerationStatus::Pending)?;
+
+    let mut filter = doc! {
+        "moderatorId": {"$ne": null},
+        "status": {"$in": [approved.clone(), denied.clone()]},
+    };
+    if !range.is_empty() {
+        filter.insert("moderationDate", range.clone());
+    }
+    let mut days = database
+        .exam_environment_exam_moderation
+        .aggregate(vec![
+            doc! {"$match": filter},
+            doc! {
+                "$group": {
+                    "_id": {
+                        "moderatorId": "$moderatorId",
+                        "date": {
+                            "$dateToString": {
+                                "format": "%Y-%m-%d",
+                                "date": "$moderationDate",
+                                "timezone": timezone,
+                            }
+                        },
+                    },
+                    "decisions": {"$sum": 1},
+                    "approved": {"$sum": {"$cond": [{"$eq": ["$status", approved]}, 1, 0]}},
+                    "denied": {"$sum": {"$cond": [{"$eq": ["$status", denied]}, 1, 0]}},
+                }
+            },
+        ])
+        .await?;
+
+    let mut tallies: HashMap<ObjectId, ModeratorTally> = HashMap::new();
+    while let Some(day) = days.try_next().await? {
+        let id = day.get_document("_id")?;
+        let date = id.get_str("date")?.to_string();
+        tallies
+            .entry(id.get_object_id("moderatorId")?)
+            .or_default()
+            .days
+            .insert(
+                date.clone(),
+                ModeratorDay {
+                    date,
+                    decisions: count(&day, "decisions"),
+                    approved: count(&day, "approved"),
+                    denied: count(&day, "denied"),
+                },
+            );
+    }
+
+    let mut filter = doc! {
+        "action": bson::serialize_to_bson(&ExamCreatorAuditAction::ModerationDecision)?,
+        "databaseEnvironment": bson::serialize_to_bson(&user.settings.database_environment)?,
+    };
+    if !range.is_empty() {
+        filter.insert("createdAt", range.clone());
+    }
+    let mut decisions = state
+        .production_database
+        .exam_creator_audit_log
+        .find(filter)
+        .await?;
+    while let Some(decision) = decisions.try_next().await? {
+        let (Some(actor_id), Some(before), Some(after)) =
+            (decision.actor_id, decision.before, decision.after)
+        else {
+            continue;
+        };
+
+        // A decision is overturned, if another moderator changes it
+        let changed =
+            before.get("status") != Some(&pending) && before.get("status") != after.get("status");
+        let overturned_moderator_id = before
+            .get_object_id("moderatorId")
+            .ok()
+            .filter(|id| changed && *id != actor_id);
+        if let Some(overturned_moderator_id) = overturned_moderator_id {
+            tallies
+                .entry(overturned_moderator_id)
+                .or_default()
+                .overturned += 1;
+        }
+    }
+
+    let ended_at = if range.is_empty() {
+        doc! {"$ne": null}
+    } else {
+        range
+    };
+    let mut sessions = database
+        .exam_creator_review_session
+        .aggregate(vec![
+            doc! {"$match": {
+                "databaseEnvironment": bson::serialize_to_bson(&user.settings.database_environment)?,
+                "endedAt": ended_at,
+            }},
+            doc! {
+                "$group": {
+                    "_id": "$moderatorId",
+                    "reviewMs": {"$push": {"$subtract": ["$endedAt", "$startedAt"]}},
+                }
+            },
+        ])
+        .await?;
+    while let Some(session) = sessions.try_next().await? {
+        let review_ms = session
+            .get_array("reviewMs")?
+            .iter()
+            .filter_map(|ms| ms.as_i64())
+            .collect();
+        tallies
+            .entry(session.get_object_id("_id")?)
+            .or_default()
+            .review_ms = review_ms;
+    }
+
+    let moderator_ids: Vec<ObjectId> = tallies.keys().copied().collect();
+    let names: HashMap<ObjectId, String> = state
+        .production_database
+        .exam_creator_user
+        .find(doc! {"_id": {"$in": moderator_ids}})
+        .await?
+        .map_ok(|u| (u.id, u.name))
+        .try_collect()
+        .await?;
+
+    let mut moderators: Vec<ModeratorStatistics> = tallies
+        .into_iter()
+        .map(|(moderator_id, mut tally)| {
+            let days: Vec<ModeratorDay> = tally.days.into_values().collect();
+            let decisions = days.iter().map(|d| d.decisions).sum();
+            let approved = days.iter().map(|d| d.approved).sum();
+            let denied = days.iter().map(|d| d.denied).sum();
+            tally.review_ms.sort_unstable();
+
+            ModeratorStatistics {
+                moderator_id,
+                moderator_name: names.get(&moderator_id).cloned(),
+                decisions,
+                approved,
+                denied,
+                approval_rate: (decisions > 0).then(|| approved as f64 / decisions as f64),
+                median_review_ms: median(&tally.review_ms),
+                timed_reviews: tally.review_ms.len() as u64,
+                overturned: tally.overturned,
+                days,
+            }
+        })
+        .collect();
+    moderators.sort_by(|a, b| b.decisions.cmp(&a.decisions));
+
+    let mut queues = vec![];
+    for (database_environment, database) in [
+        (
+            prisma::ExamCreatorDatabaseEnvironment::Production,
+            &state.production_database,
+        ),
+        (
+            prisma::ExamCreatorDatabaseEnvironment::Staging,
+            &state.staging_database,
+        ),
+    ] {
+        let queue = database
+            .exam_environment_exam_moderation
+            .aggregate(vec![
+                doc! {"$match": {"status": pending.clone()}},
+                doc! {
+                    "$group": {
+                        "_id": null,
+                        "pending": {"$sum": 1},
+                        "oldestSubmissionDate": {"$min": "$submissionDate"},
+                    }
+                },
+            ])
+            .await?
+            .try_next()
+            .await?;
+
+        queues.push(QueueAge {
+            database_environment,
+            pending: queue.as_ref().map(|q| count(q, "pending")).unwrap_or(0),
+            oldest_submission_date: queue
+                .as_ref()
+                .and_then(|q| q.get_datetime("oldestSubmissionDate").ok().copied()),
+        });
+    }
+
+    Ok(Json(ModeratorReport { moderators, queues }))
+}
+
+#[derive(Deserialize)]
+pub struct GetDenialReasonReportQuery {
+    /// RFC 3339 date, inclusive
+    pub from: Option<String>,
+    /// RFC 3339 date, exclusive
+    pub to: Option<String>,
+    pub exam_id: Option<ObjectId>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DenialReasonReport {
+    /// Denied attempts
+    pub denied: u64,
+    /// Denied attempts without a reason
+    pub without_reason: u64,
+    /// Most frequent first
+    pub reasons: Vec<DenialReasonFrequency>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DenialReasonFrequency {
+    pub code: String,
+    /// `None` if the reason no longer exists
+    pub label: Option<String>,
+    /// Denied attempts with the reason. An attempt can have multiple reasons.
+    pub count: u64,
+    /// `count / denied`
+    pub rate: f64,
+}
+
+/// Get how often each denial reason is given, in the user's database environment.
+///
+/// Each attempt is counted by its latest decision in the date range, if it was a denial.
+#[instrument(skip_all, err(Debug), level = "debug")]
+pub async fn get_denial_reason_report(
+    user: prisma::ExamCreatorUser,
+    State(state): State<ServerState>,
+    Query(params): Query<GetDenialReasonReportQuery>,
+) -> Result<Json<DenialReasonReport>, Error> {
+    let database = database_environment(&state, &user);
+    let range = date_range_filter(params.from.as_deref(), params.to.as_deref())?;
+
+    let mut filter = doc! {};
+    if !range.is_empty() {
+        filter.insert("decidedAt", range);
+    }
+    if let Some(exam_id) = params.exam_id {
+        // "examId" does not exist on decisions
+        let attempt_ids: Vec<ObjectId> = database
+            .exam_attempt
+            .clone_with_type::<Document>()
+            .find(doc! {"examId": exam_id})
+            .projection(doc! {"_id": true})
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?
+            .iter()
+            .map(|attempt| attempt.get_object_id("_id"))
+            .collect::<Result<_, _>>()?;
+        filter.insert("examAttemptId", doc! {"$in": attempt_ids});
+    }
+
+    let report = database
+        .exam_creator_moderation_decision
+        .aggregate(vec![
+            doc! {"$match": filter},
+            doc! {"$sort": {"decidedAt": -1}},
+            doc! {
+                "$group": {
+                    "_id": "$examAttemptId",
+                    "status": {"$first": "$status"},
+                    "denialReasonCodes": {"$first": "$denialReasonCodes"},
+                }
+            },
+            doc! {"$match": {
+                "status": bson::serialize_to_bson(&prisma::ExamEnvironmentExamModerationStatus::Denied)?,
+            }},
+            doc! {
+                "$facet": {
+                    "totals": [{
+                        "$group": {
+                            "_id": null,
+                            "denied": {"$sum": 1},
+                            "withoutReason": {
+                                "$sum": {"$cond": [{"$eq": [{"$size": "$denialReasonCodes"}, 0]}, 1, 0]}
+                            },
+                        }
+                    }],
+                    "reasons": [
+                        {"$unwind": "$denialReasonCodes"},
+                        {"$group": {"_id": "$denialReasonCodes", "count": {"$sum": 1}}},
+                        {"$sort": {"count": -1, "_id": 1}},
+                    ],
+                }
+            },
+        ])
+        .await?
+        .try_next()
+        .await?
+        .unwrap_or_default();
+
+    let totals = report
+        .get_array("totals")
+        .ok()
+        .and_then(|totals| totals.first())
+        .and_then(|totals| totals.as_document());
+    let denied = totals.map(|t| count(t, "denied")).unwrap_or(0);
+    let without_reason = totals.map(|t| count(t, "withoutReason")).unwrap_or(0);
+
+    let labels: HashMap<String, String> = state
+        .production_database
+        .exam_creator_denial_reason
+        .find(doc! {})
+        .await?
+        .map_ok(|r| (r.code, r.label))
+        .try_collect()
+        .await?;
+
+    let mut reasons = vec![];
+    for reason in report.get_array("reasons").into_iter().flatten() {
+        let Some(reason) = reason.as_document() else {
+            continue;
+        };
+        let code = reason.get_str("_id")?.to_string();
+        let reason_count = count(reason, "count");
+        reasons.push(DenialReasonFrequency {
+            label: labels.get(&code).cloned(),
+            code,
+            count: reason_count,
+            rate: reason_count as f64 / denied.max(1) as f64,
+        });
+    }
+
+    Ok(Json(DenialReasonReport {
+        denied,
+        without_reason,
+        reasons,
+    }))
+}
+
+/// Median of sorted values
+fn median(sorted: &[i64]) -> Option<f64> {
+    let n = sorted.len();
+    if n == 0 {
+        None
+    } else if n % 2 == 0 {
+        Some((sorted[n / 2 - 1] + sorted[n / 2]) as f64 / 2.0)
+    } else {
+        Some(sorted[n / 2] as f64)
+    }
+}
@@ -13,10 +13,11 @@ use tower_sessions::Session;
 use tracing::instrument;
 
 use crate::{
-    config,
-    database::{database_environment, prisma},
+    audit::{self, AuditEntry},
+    database::{database_environment, exam_creator::ExamCreatorAuditAction, prisma, user_roles},
     errors::Error,
     routes::attempts::construct_attempts,
+    scoring::ScoredAttempt,
     state::{ServerState, SessionUser, User},
 };
 
@@ -51,6 +52,8 @@ pub async fn get_session_user(
 
     session.insert(&web_socket_token, &cookie).await?;
 
+    let roles = user_roles(&server_state, exam_creator_user.id).await?;
+
     let users = &server_state
         .client_sync
         .lock()
@@ -71,6 +74,7 @@ pub async fn get_session_user(
         activity,
         web_socket_token,
         settings,
+        roles,
     };
 
     Ok(Json(session_user))
@@ -103,6 +107,18 @@ pub async fn put_user_settings(
 
     let settings = updated_user.settings;
 
+    audit::record(
+        &server_state,
+        &exam_creator_user,
+        AuditEntry::new(
+            ExamCreatorAuditAction::UserSettingsUpdate,
+            vec![exam_creator_user.id],
+        )
+        .before(bson::serialize_to_document(&exam_creator_user.settings)?)
+        .after(bson::serialize_to_document(&settings)?),
+    )
+    .await;
+
     // Update state
     let client_sync = &mut server_state.client_sync.lock().unwrap();
     if let Some(user) = client_sync
@@ -128,7 +144,7 @@ pub struct GetUserSearchQuery {
 #[derive(Serialize)]
 pub struct GetUserSearchResponse {
     pub user: Document,
-    pub attempts: Vec<config::Attempt>,
+    pub attempts: Vec<ScoredAttempt>,
     pub moderations: Vec<prisma::ExamEnvironmentExamModeration>,
 }
 
@@ -235,7 +251,7 @@ pub async fn get_user_search(
 
     Ok(Json(GetUserSearchResponse {
         user,
-        attempts,
+        attempts: attempts.into_iter().map(ScoredAttempt::from).collect(),
         moderations,
     }))
 }
@@ -0,0 +1,215 @@
+//! Grading of exam attempts, so every consumer uses the same score and pass/fail decision.
+use std::collections::BTreeMap;
+
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+
+use crate::config::Attempt;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptScore {
+    pub questions: Vec<QuestionScore>,
+    pub question_sets: Vec<QuestionSetScore>,
+    /// Questions may count towards multiple tags
+    pub tags: Vec<TagScore>,
+    pub correct: usize,
+    /// Number of questions in the attempt's generation
+    pub total: usize,
+    /// 0 to 100
+    pub percent: f64,
+    pub passing_percent: f64,
+    pub passed: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuestionScore {
+    pub question_id: ObjectId,
+    pub question_set_id: ObjectId,
+    pub answered: bool,
+    pub correct: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuestionSetScore {
+    pub question_set_id: ObjectId,
+    pub correct: usize,
+    pub total: usize,
+    pub percent: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagScore {
+    pub tag: String,
+    pub correct: usize,
+    pub total: usize,
+    pub percent: f64,
+}
+
+/// An attempt, with its score
+#[derive(Clone, Debug, Serialize)]
+pub struct ScoredAttempt {
+    #[serde(flatten)]
+    pub attempt: Attempt,
+    pub score: AttemptScore,
+}
+
+impl From<Attempt> for ScoredAttempt {
+    fn from(attempt: Attempt) -> Self {
+        let score = score_attempt(&attempt);
+        Self { attempt, score }
+    }
+}
+
+/// Scores an attempt:
+/// - Questions not in the attempt's generation are skipped
+/// - A question is correct, if the selected answers are exactly the correct answers shown to the
+///   candidate
+/// - The attempt passes, if its percent is at least `config.passing_percent`
+pub fn score_attempt(attempt: &Attempt) -> AttemptScore {
+    let mut questions = vec![];
+    let mut question_sets = vec![];
+    // Sorted, so the response is stable
+    let mut tags: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+
+    for question_set in &attempt.question_sets {
+        let mut set_correct = 0;
+        let mut set_total = 0;
+
+        for question in &question_set.questions {
+            // If the generation has no answers for the question, the question was not in the attempt
+            if question.generated.is_empty() {
+                continue;
+            }
+
+            // The selected answers must be exactly the correct answers shown
+            let shown: Vec<_> = question
+                .answers
+                .iter()
+                .filter(|a| question.generated.contains(&a.id))
+                .collect();
+            let correct = !question.selected.is_empty()
+                && shown
+                    .iter()
+                    .all(|a| a.is_correct == question.selected.contains(&a.id))
+                && question
+                    .selected
+                    .iter()
+                    .all(|id| shown.iter().any(|a| a.id == *id));
+
+            set_total += 1;
+            if correct {
+                set_correct += 1;
+            }
+            for tag in &question.tags {
+                let (tag_correct, tag_total) = tags.entry(tag).or_default();
+                *tag_total += 1;
+                if correct {
+                    *tag_correct += 1;
+                }
+            }
+
+            questions.push(QuestionScore {
+                question_id: question.id,
+                question_set_id: question_set.id,
+                answered: question.submission_time.is_some(),
+                correct,
+            });
+        }
+
+        if set_total > 0 {
+            question_sets.push(QuestionSetScore {
+                question_set_id: question_set.id,
+                correct: set_correct,
+                total: set_total,
+                percent: percent(set_correct, set_total),
+            });
+        }
+    }
+
+    let tags = tags
+        .into_iter()
+        .map(|(tag, (correct, total))| TagScore {
+            tag: tag.to_string(),
+            correct,
+            total,
+            percent: percent(correct, total),
+        })
+        .collect();
+
+    let correct = questions.iter().filter(|q| q.correct).count();
+    let total = questions.len();
+    let percent = percent(correct, total);
+    let passing_percent = attempt.config.passing_percent;
+
+    AttemptScore {
+        questions,
+        question_sets,
+        tags,
+        correct,
+        total,
+        percent,
+        passing_percent,
+        passed: total > 0 && percent >= passing_percent,
+    }
+}
+
+fn percent(correct: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        correct as f64 / total as f64 * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::score_attempt;
+    use crate::config::{Attempt, fixtures};
+
+    /// Attempt with one question, of `answers` correct/incorrect answers, all shown
+    fn attempt(answers: &[bool], selected: &[usize]) -> Attempt {
+        let mut attempt = fixtures::attempt(&[answers.to_vec()]);
+
+        let question = &mut attempt.question_sets[0].questions[0];
+        question.generated = question.answers.iter().map(|a| a.id).collect();
+        question.selected = selected.iter().map(|i| question.answers[*i].id).collect();
+        attempt
+    }
+
+    #[test]
+    fn all_correct_answers_selected() {
+        let score = score_attempt(&attempt(&[true, true, false], &[0, 1]));
+        assert!(score.questions[0].correct);
+        assert_eq!(score.percent, 100.0);
+        assert!(score.passed);
+    }
+
+    #[test]
+    fn extra_incorrect_answer_selected() {
+        let score = score_attempt(&attempt(&[true, false, false], &[0, 1]));
+        assert!(!score.questions[0].correct);
+
+        // Selecting every answer is not correct
+        let score = score_attempt(&attempt(&[true, false, false], &[0, 1, 2]));
+        assert!(!score.questions[0].correct);
+        assert_eq!(score.percent, 0.0);
+    }
+
+    #[test]
+    fn some_correct_answers_selected() {
+        let score = score_attempt(&attempt(&[true, true, false], &[0]));
+        assert!(!score.questions[0].correct);
+    }
+
+    #[test]
+    fn unanswered() {
+        let score = score_attempt(&attempt(&[true, false], &[]));
+        assert!(!score.questions[0].correct);
+        assert!(!score.questions[0].answered);
+        assert!(!score.passed);
+    }
+}
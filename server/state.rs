@@ -1,45 +1,29 @@
-use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use axum::extract::FromRef;
 use axum_extra::extract::cookie::Key;
-use bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
-use supabase_rs::SupabaseClient;
-use tokio::sync::oneshot;
 use tracing::error;
 
 use crate::{
+    cache::MetricsCache,
     config::EnvVars,
-    database::{Database, prisma},
-    routes::metrics::{GetAttemptsMetrics, GetExamMetricsById},
+    database::{Database, exam_creator::ExamCreatorRole, prisma},
+    event_store::EventStore,
 };
 
 #[derive(Clone)]
 pub struct ServerState {
     pub production_database: Database,
     pub staging_database: Database,
-    pub supabase: SupabaseClient,
+    pub event_store: Arc<dyn EventStore>,
     pub client_sync: Arc<Mutex<ClientSync>>,
     pub key: Key,
     pub env_vars: EnvVars,
-    pub exam_metrics_by_id_cache: Arc<Mutex<Vec<GetExamMetricsById>>>,
-    pub attempt_metrics_cache: Arc<Mutex<Cache<Vec<GetAttemptsMetrics>>>>,
-    /// Attempts scheduled for deletion after a grace period. Sending on (or dropping)
-    /// the sender cancels the pending delete before it runs.
-    pub pending_deletes: PendingDeletes,
-    /// Time each attempt's moderation page was last opened, so a subsequent moderation
-    /// decision can compute time spent reviewing it.
-    pub attempt_page_views: AttemptPageViews,
+    /// Cached metrics queries of both database environments
+    pub metrics_cache: Arc<MetricsCache>,
 }
 
-/// Maps an attempt id to the cancellation channel for its pending deletion task, tagged with a
-/// generation so a completing task only clears its own entry (not a newer reschedule that replaced it).
-pub type PendingDeletes = Arc<Mutex<HashMap<ObjectId, (u64, oneshot::Sender<()>)>>>;
-
-/// Maps a moderator id and an attempt id to the time its moderation page was last opened.
-pub type AttemptPageViews = Arc<Mutex<HashMap<(ObjectId, ObjectId), bson::DateTime>>>;
-
 impl FromRef<ServerState> for Key {
     fn from_ref(state: &ServerState) -> Self {
         state.key.clone()
@@ -73,6 +57,7 @@ pub struct SessionUser {
     #[serde(rename = "webSocketToken")]
     pub web_socket_token: String,
     pub settings: prisma::ExamCreatorUserSettings,
+    pub roles: Vec<ExamCreatorRole>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,21 +69,6 @@ pub struct Activity {
     pub last_active: usize,
 }
 
-pub struct Cache<T: Default> {
-    pub data: T,
-    pub expire_at: std::time::SystemTime,
-}
-
-impl<T: Default> Cache<T> {
-    pub fn new() -> Self {
-        let data: T = Default::default();
-        Self {
-            data,
-            expire_at: std::time::SystemTime::now() + std::time::Duration::from_hours(24), // 1 day
-        }
-    }
-}
-
 pub async fn cleanup_online_users(
     client_sync: Arc<Mutex<ClientSync>>,
     // How often to check for inactive users
@@ -0,0 +1,469 @@
+//! Suspicion scoring of exam attempts, to help moderators prioritise their reviews.
+//!
+//! A score is the sum of the points of all raised signals, capped at 100.
+use std::collections::HashSet;
+
+use futures_util::TryStreamExt;
+use mongodb::bson::{DateTime, doc, oid::ObjectId};
+use tracing::{error, info};
+
+use crate::{
+    config::{Attempt, Event, EventKind},
+    database::{
+        Database,
+        exam_creator::{
+            ExamCreatorAttemptSuspicion, ExamCreatorSuspicionSignal, ExamCreatorSuspicionSignalKind,
+        },
+        prisma,
+    },
+    errors::Error,
+    event_store::EventStore,
+    routes::{attempts::construct_attempts, events::events_by_attempt_id},
+    state::ServerState,
+};
+
+/// Answers submitted quicker than this after the previous answer are considered fast
+const FAST_ANSWER_MS: i64 = 3_000;
+/// Fraction of fast answers above which `FastAnswers` is raised
+const FAST_ANSWER_FRACTION: f64 = 0.25;
+/// Average time per answer below which `FastSubmission` is raised
+const MIN_AVERAGE_ANSWER_MS: f64 = 10_000.0;
+/// Minimum number of answers for the answer time distribution to be meaningful
+const MIN_ANSWERS_FOR_DISTRIBUTION: usize = 10;
+/// Coefficient of variation of answer times below which `UniformAnswerTimes` is raised
+const UNIFORM_ANSWER_TIME_CV: f64 = 0.25;
+
+/// Number of unscored pending attempts scored per database, per run
+const PENDING_BATCH_SIZE: i64 = 50;
+
+/// Scores an attempt from its answers, and its events.
+///
+/// Answer times are measured between consecutive submission times, starting at the attempt's
+/// start time. Questions answered more than once only count their last submission.
+pub fn score_attempt(
+    attempt: &Attempt,
+    events: &[Event],
+) -> (f64, Vec<ExamCreatorSuspicionSignal>) {
+    let mut signals = vec![];
+
+    let mut submission_times: Vec<i64> = attempt
+        .question_sets
+        .iter()
+        .flat_map(|qs| qs.questions.iter())
+        .filter_map(|q| q.submission_time)
+        .map(|t| t.timestamp_millis())
+        .collect();
+    submission_times.sort_unstable();
+
+    let start_time = attempt.start_time.timestamp_millis();
+    let answer_times: Vec<i64> = std::iter::once(start_time)
+        .chain(submission_times.iter().copied())
+        .collect::<Vec<_>>()
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .collect();
+
+    if !answer_times.is_empty() {
+        let answered = answer_times.len() as f64;
+
+        let fast_answers = answer_times.iter().filter(|t| **t < FAST_ANSWER_MS).count();
+        let fast_fraction = fast_answers as f64 / answered;
+        if fast_fraction >= FAST_ANSWER_FRACTION {
+            signals.push(ExamCreatorSuspicionSignal {
+                kind: ExamCreatorSuspicionSignalKind::FastAnswers,
+                points: 40.0 * fast_fraction,
+                explanation: format!(
+                    "{fast_answers} of {} answers were submitted within {}s of the previous answer",
+                    answer_times.len(),
+                    FAST_ANSWER_MS / 1000
+                ),
+            });
+        }
+
+        let total_ms = answer_times.iter().sum::<i64>() as f64;
+        let average_ms = total_ms / answered;
+        if average_ms < MIN_AVERAGE_ANSWER_MS {
+            signals.push(ExamCreatorSuspicionSignal {
+                kind: ExamCreatorSuspicionSignalKind::FastSubmission,
+                points: 30.0 * (1.0 - average_ms / MIN_AVERAGE_ANSWER_MS),
+                explanation: format!(
+                    "{} answers were submitted in {:.0}s, averaging {:.1}s per answer",
+                    answer_times.len(),
+                    total_ms / 1000.0,
+                    average_ms / 1000.0
+                ),
+            });
+        }
+
+        if answer_times.len() >= MIN_ANSWERS_FOR_DISTRIBUTION && average_ms > 0.0 {
+            let variance = answer_times
+                .iter()
+                .map(|t| (*t as f64 - average_ms).powi(2))
+                .sum::<f64>()
+                / answered;
+            let cv = variance.sqrt() / average_ms;
+            if cv < UNIFORM_ANSWER_TIME_CV {
+                signals.push(ExamCreatorSuspicionSignal {
+                    kind: ExamCreatorSuspicionSignalKind::UniformAnswerTimes,
+                    points: 15.0 * (1.0 - cv / UNIFORM_ANSWER_TIME_CV),
+                    explanation: format!(
+                        "time between answers barely varies (coefficient of variation {cv:.2})"
+                    ),
+                });
+            }
+        }
+    }
+
+    let end_time = submission_times.last().copied();
+
+    // Pair each blur with the next focus, to find how long the exam was out of focus
+    let mut blurs = 0;
+    let mut blurred_ms = 0;
+    let mut blurred_at = None;
+    for event in events {
+        let timestamp = event.timestamp.timestamp_millis();
+        match event.kind {
+            EventKind::Blur => {
+                blurs += 1;
+                blurred_at.get_or_insert(timestamp);
+            }
+            EventKind::Focus => {
+                if let Some(blurred_at) = blurred_at.take() {
+                    blurred_ms += (timestamp - blurred_at).max(0);
+                }
+            }
+            _ => {}
+        }
+    }
+    if let (Some(blurred_at), Some(end_time)) = (blurred_at, end_time) {
+        blurred_ms += (end_time - blurred_at).max(0);
+    }
+    if blurs > 0 {
+        let blurred_s = blurred_ms as f64 / 1000.0;
+        signals.push(ExamCreatorSuspicionSignal {
+            kind: ExamCreatorSuspicionSignalKind::Blur,
+            points: (2.0 * blurs as f64 + blurred_s / 30.0).min(20.0),
+            explanation: format!("exam lost focus {blurs} time(s), for {blurred_s:.0}s in total"),
+        });
+    }
+
+    let exits = events
+        .iter()
+        .filter(|e| matches!(e.kind, EventKind::ExamExit))
+        .count();
+    if exits > 0 {
+        signals.push(ExamCreatorSuspicionSignal {
+            kind: ExamCreatorSuspicionSignalKind::ExamExit,
+            points: (10.0 * exits as f64).min(20.0),
+            explanation: format!("exam was exited {exits} time(s)"),
+        });
+    }
+
+    let answered_at: Vec<(ObjectId, i64)> = attempt
+        .question_sets
+        .iter()
+        .flat_map(|qs| qs.questions.iter())
+        .filter_map(|q| q.submission_time.map(|t| (q.id, t.timestamp_millis())))
+        .collect();
+    let mut revisited = HashSet::new();
+    let mut revisits = 0;
+    for event in events {
+        if !matches!(event.kind, EventKind::QuestionVisit) {
+            continue;
+        }
+        let Some(question_id) = event.question_id() else {
+            continue;
+        };
+        let timestamp = event.timestamp.timestamp_millis();
+        let visited_after_answer = answered_at
+            .iter()
+            .any(|(id, answered_at)| *id == question_id && timestamp > *answered_at);
+        if visited_after_answer {
+            revisits += 1;
+            revisited.insert(question_id);
+        }
+    }
+    // Reviewing answers is normal, so only more revisits than answered questions is raised
+    if !answered_at.is_empty() && revisits > answered_at.len() {
+        let ratio = revisits as f64 / answered_at.len() as f64;
+        signals.push(ExamCreatorSuspicionSignal {
+            kind: ExamCreatorSuspicionSignalKind::Revisits,
+            points: (5.0 * (ratio - 1.0)).min(10.0),
+            explanation: format!(
+                "{revisits} visits to {} already answered question(s)",
+                revisited.len()
+            ),
+        });
+    }
+
+    let score = signals.iter().map(|s| s.points).sum::<f64>().min(100.0);
+
+    (score, signals)
+}
+
+/// Scores an attempt, and stores the score, replacing any previous score.
+pub async fn score_and_store(
+    database: &Database,
+    event_store: &dyn EventStore,
+    attempt: &Attempt,
+) -> Result<ExamCreatorAttemptSuspicion, Error> {
+    let events = events_by_attempt_id(event_store, attempt.id).await?;
+    let (score, signals) = score_attempt(attempt, &events.events);
+
+    let suspicion = ExamCreatorAttemptSuspicion {
+        exam_attempt_id: attempt.id,
+        score,
+        signals,
+        computed_at: DateTime::now(),
+        version: 1,
+    };
+
+    database
+        .exam_creator_attempt_suspicion
+        .replace_one(doc! {"_id": attempt.id}, &suspicion)
+        .upsert(true)
+        .await?;
+
+    Ok(suspicion)
+}
+
+/// Periodically scores pending attempts which have not been scored, so the moderation queue
+/// can be sorted by risk.
+pub async fn score_pending_attempts(state: ServerState, interval: std::time::Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        for (database_environment, database) in [
+            ("production", &state.production_database),
+            ("staging", &state.staging_database),
+        ] {
+            match score_unscored_pending_attempts(database, state.event_store.as_ref()).await {
+                Ok(0) => {}
+                Ok(scored) => info!(database_environment, scored, "scored pending attempts"),
+                Err(e) => {
+                    error!(error = ?e, database_environment, "unable to score pending attempts")
+                }
+            }
+        }
+    }
+}
+
+async fn score_unscored_pending_attempts(
+    database: &Database,
+    event_store: &dyn EventStore,
+) -> Result<usize, Error> {
+    let attempt_ids: Vec<ObjectId> = database
+        .exam_environment_exam_moderation
+        .aggregate(vec![
+            doc! {"$match": {
+                "status": bson::serialize_to_bson(&prisma::ExamEnvironmentExamModerationStatus::Pending)?
+            }},
+            doc! {
+                "$lookup": {
+                    "from": "ExamCreatorAttemptSuspicion",
+                    "localField": "examAttemptId",
+                    "foreignField": "_id",
+                    "pipeline": [{"$project": {"_id": true}}],
+                    "as": "suspicion",
+                }
+            },
+            doc! {"$match": {"suspicion": {"$size": 0}}},
+            doc! {"$sort": {"submissionDate": 1}},
+            doc! {"$limit": PENDING_BATCH_SIZE},
+            doc! {"$project": {"examAttemptId": true}},
+        ])
+        .await?
+        .try_filter_map(|d| async move { Ok(d.get_object_id("examAttemptId").ok()) })
+        .try_collect()
+        .await?;
+
+    if attempt_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let exam_attempts: Vec<prisma::ExamEnvironmentExamAttempt> = database
+        .exam_attempt
+        .find(doc! {"_id": {"$in": attempt_ids}})
+        .await?
+        .try_collect()
+        .await?;
+    let attempts = construct_attempts(database, &exam_attempts).await?;
+
+    let mut scored = 0;
+    for attempt in &attempts {
+        match score_and_store(database, event_store, attempt).await {
+            Ok(_) => scored += 1,
+            Err(e) => error!(error = ?e, attempt_id = %attempt.id, "unable to score attempt"),
+        }
+    }
+
+    Ok(scored)
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{DateTime, oid::ObjectId};
+
+    use super::score_attempt;
+    use crate::{
+        config::{Attempt, Event, EventKind, EventMeta, QuestionEventMeta, fixtures},
+        database::exam_creator::{ExamCreatorSuspicionSignal, ExamCreatorSuspicionSignalKind},
+    };
+
+    /// 2026-01-01T00:00:00Z, the start time of every attempt
+    const START_MS: i64 = 1_767_225_600_000;
+
+    /// Attempt with one question per submission, submitted `submitted_after_s` after the start
+    fn attempt(submitted_after_s: &[i64]) -> Attempt {
+        let mut attempt = fixtures::attempt(&vec![vec![]; submitted_after_s.len()]);
+
+        for (question, after_s) in attempt.question_sets[0]
+            .questions
+            .iter_mut()
+            .zip(submitted_after_s)
+        {
+            question.submission_time = Some(DateTime::from_millis(START_MS + after_s * 1000));
+        }
+        attempt
+    }
+
+    fn question_ids(attempt: &Attempt) -> Vec<ObjectId> {
+        attempt.question_sets[0]
+            .questions
+            .iter()
+            .map(|q| q.id)
+            .collect()
+    }
+
+    /// Event of `kind`, `after_s` after the start of `attempt`
+    fn event(
+        attempt: &Attempt,
+        kind: EventKind,
+        after_s: i64,
+        question: Option<ObjectId>,
+    ) -> Event {
+        Event {
+            id: ObjectId::new().to_hex(),
+            timestamp: DateTime::from_millis(START_MS + after_s * 1000),
+            kind,
+            meta: EventMeta::Question(QuestionEventMeta {
+                question,
+                ..Default::default()
+            }),
+            attempt_id: attempt.id,
+        }
+    }
+
+    fn signal(
+        signals: &[ExamCreatorSuspicionSignal],
+        kind: ExamCreatorSuspicionSignalKind,
+    ) -> Option<&ExamCreatorSuspicionSignal> {
+        signals.iter().find(|s| s.kind == kind)
+    }
+
+    #[test]
+    fn blur_time_until_focus_or_last_answer() {
+        let attempt = attempt(&[60, 120]);
+        let events = [
+            event(&attempt, EventKind::Blur, 10, None),
+            event(&attempt, EventKind::Focus, 40, None),
+            // Focus without a blur is ignored
+            event(&attempt, EventKind::Focus, 45, None),
+            // Never refocused, so blurred until the last answer
+            event(&attempt, EventKind::Blur, 70, None),
+        ];
+
+        let (score, signals) = score_attempt(&attempt, &events);
+
+        assert_eq!(signals.len(), 1);
+        let blur = signal(&signals, ExamCreatorSuspicionSignalKind::Blur).unwrap();
+        // 2 blurs, for 30s + 50s
+        assert!((blur.points - (2.0 * 2.0 + 80.0 / 30.0)).abs() < 1e-12);
+        assert_eq!(
+            blur.explanation,
+            "exam lost focus 2 time(s), for 80s in total"
+        );
+        assert_eq!(score, blur.points);
+    }
+
+    #[test]
+    fn blur_points_are_capped() {
+        let attempt = attempt(&[3600]);
+        let events = [event(&attempt, EventKind::Blur, 0, None)];
+
+        let (_, signals) = score_attempt(&attempt, &events);
+
+        let blur = signal(&signals, ExamCreatorSuspicionSignalKind::Blur).unwrap();
+        assert_eq!(blur.points, 20.0);
+    }
+
+    #[test]
+    fn fast_answers() {
+        // Answer times of 1s, 1s, 1s, and 60s
+        let attempt = attempt(&[1, 2, 3, 63]);
+
+        let (score, signals) = score_attempt(&attempt, &[]);
+
+        assert_eq!(signals.len(), 1);
+        let fast = signal(&signals, ExamCreatorSuspicionSignalKind::FastAnswers).unwrap();
+        assert!((fast.points - 40.0 * 0.75).abs() < 1e-12);
+        assert_eq!(
+            fast.explanation,
+            "3 of 4 answers were submitted within 3s of the previous answer"
+        );
+        assert_eq!(score, fast.points);
+    }
+
+    #[test]
+    fn fast_answers_below_threshold() {
+        // Answer times of 1s, 60s, 60s, 60s, and 60s
+        let attempt = attempt(&[1, 61, 121, 181, 241]);
+
+        let (score, signals) = score_attempt(&attempt, &[]);
+
+        assert!(signals.is_empty());
+        assert_eq!(score, 0.0);
+    }
+
+    #[test]
+    fn revisits_after_answering() {
+        let attempt = attempt(&[60, 120]);
+        let ids = question_ids(&attempt);
+        let (first, second) = (ids[0], ids[1]);
+        let events = [
+            // Before the question is answered
+            event(&attempt, EventKind::QuestionVisit, 30, Some(first)),
+            event(&attempt, EventKind::QuestionVisit, 130, Some(first)),
+            event(&attempt, EventKind::QuestionVisit, 140, Some(second)),
+            event(&attempt, EventKind::QuestionVisit, 150, Some(first)),
+            // Without a question
+            event(&attempt, EventKind::QuestionVisit, 160, None),
+        ];
+
+        let (_, signals) = score_attempt(&attempt, &events);
+
+        assert_eq!(signals.len(), 1);
+        let revisits = signal(&signals, ExamCreatorSuspicionSignalKind::Revisits).unwrap();
+        assert!((revisits.points - 5.0 * 0.5).abs() < 1e-12);
+        assert_eq!(
+            revisits.explanation,
+            "3 visits to 2 already answered question(s)"
+        );
+    }
+
+    #[test]
+    fn reviewing_each_answer_once_is_not_a_revisit_signal() {
+        let attempt = attempt(&[60, 120]);
+        let ids = question_ids(&attempt);
+        let (first, second) = (ids[0], ids[1]);
+        let events = [
+            event(&attempt, EventKind::QuestionVisit, 130, Some(first)),
+            event(&attempt, EventKind::QuestionVisit, 140, Some(second)),
+        ];
+
+        let (score, signals) = score_attempt(&attempt, &events);
+
+        assert!(signals.is_empty());
+        assert_eq!(score, 0.0);
+    }
+}
@@ -0,0 +1,243 @@
+//! Single ordered timeline of an attempt, merging question submissions with Supabase events.
+use std::collections::HashMap;
+
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::Serialize;
+
+use crate::{
+    config::{Attempt, EventKind},
+    routes::events::AttemptEvents,
+};
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptTimeline {
+    pub attempt_id: ObjectId,
+    #[serde_as(as = "bson::serde_helpers::datetime::AsRfc3339String")]
+    pub start_time: DateTime,
+    /// Last submission or event, whichever is later
+    #[serde_as(as = "bson::serde_helpers::datetime::AsRfc3339String")]
+    pub end_time: DateTime,
+    /// Oldest first
+    pub entries: Vec<TimelineEntry>,
+    /// Time out of the exam window, from each blur to the next focus
+    pub away: Vec<TimelineInterval>,
+    pub total_away_ms: i64,
+    /// In exam order
+    pub questions: Vec<QuestionTimeline>,
+    /// Events of an unknown kind, or failing validation, which are left out
+    pub skipped_events: usize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TimelineEntryKind {
+    Start,
+    QuestionVisit,
+    /// Latest answer of a question. Attempts only keep the last submission of each question.
+    Submission,
+    Focus,
+    Blur,
+    CaptionsOpened,
+    ExamExit,
+    CopyPaste,
+    FullscreenExit,
+    NetworkLoss,
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineEntry {
+    /// UTC
+    #[serde_as(as = "bson::serde_helpers::datetime::AsRfc3339String")]
+    pub timestamp: DateTime,
+    /// Milliseconds since the start of the attempt
+    pub offset_ms: i64,
+    pub kind: TimelineEntryKind,
+    pub question_id: Option<ObjectId>,
+    /// Id of the Supabase event, `None` for the start and submissions
+    pub event_id: Option<String>,
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineInterval {
+    #[serde_as(as = "bson::serde_helpers::datetime::AsRfc3339String")]
+    pub start: DateTime,
+    /// The end of the attempt, if the window never regained focus
+    #[serde_as(as = "bson::serde_helpers::datetime::AsRfc3339String")]
+    pub end: DateTime,
+    pub duration_ms: i64,
+    /// Question being viewed when the interval started
+    pub question_id: Option<ObjectId>,
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuestionTimeline {
+    pub question_id: ObjectId,
+    pub question_set_id: ObjectId,
+    pub visits: usize,
+    /// Time the question was viewed with the exam window in focus, from each visit to the next
+    /// visit, exit, or the end of the attempt
+    pub time_on_question_ms: i64,
+    #[serde_as(as = "Option<bson::serde_helpers::datetime::AsRfc3339String>")]
+    pub submission_time: Option<DateTime>,
+    /// Time between the previous submission (or the start of the attempt), and the question's
+    /// submission
+    pub time_to_answer_ms: Option<i64>,
+}
+
+/// Builds the timeline of an attempt from its submissions and events.
+///
+/// Entries at the same instant keep a stable order: the start, then submissions, then events.
+pub fn build_timeline(attempt: &Attempt, events: &AttemptEvents) -> AttemptTimeline {
+    let start_time = attempt.start_time.timestamp_millis();
+
+    let mut entries = vec![TimelineEntry {
+        timestamp: attempt.start_time,
+        offset_ms: 0,
+        kind: TimelineEntryKind::Start,
+        question_id: None,
+        event_id: None,
+    }];
+
+    let questions = attempt
+        .question_sets
+        .iter()
+        .flat_map(|qs| qs.questions.iter().map(move |q| (qs.id, q)));
+    for (_, question) in questions.clone() {
+        if let Some(submission_time) = question.submission_time {
+            entries.push(TimelineEntry {
+                timestamp: submission_time,
+                offset_ms: submission_time.timestamp_millis() - start_time,
+                kind: TimelineEntryKind::Submission,
+                question_id: Some(question.id),
+                event_id: None,
+            });
+        }
+    }
+
+    for event in &events.events {
+        entries.push(TimelineEntry {
+            timestamp: event.timestamp,
+            offset_ms: event.timestamp.timestamp_millis() - start_time,
+            kind: match event.kind {
+                EventKind::CaptionsOpened => TimelineEntryKind::CaptionsOpened,
+                EventKind::QuestionVisit => TimelineEntryKind::QuestionVisit,
+                EventKind::Focus => TimelineEntryKind::Focus,
+                EventKind::Blur => TimelineEntryKind::Blur,
+                EventKind::ExamExit => TimelineEntryKind::ExamExit,
+                EventKind::CopyPaste => TimelineEntryKind::CopyPaste,
+                EventKind::FullscreenExit => TimelineEntryKind::FullscreenExit,
+                EventKind::NetworkLoss => TimelineEntryKind::NetworkLoss,
+            },
+            question_id: event.question_id(),
+            event_id: Some(event.id.clone()),
+        });
+    }
+
+    // Stable, so entries at the same instant keep the order they were added in
+    entries.sort_by_key(|entry| entry.timestamp);
+    let end_time = entries
+        .last()
+        .map(|entry| entry.timestamp)
+        .unwrap_or(attempt.start_time);
+
+    // Walk the timeline, attributing in-focus time to the question being viewed
+    let mut away = vec![];
+    let mut time_on_question: HashMap<ObjectId, i64> = HashMap::new();
+    let mut visits: HashMap<ObjectId, usize> = HashMap::new();
+    let mut viewing: Option<ObjectId> = None;
+    let mut blurred_at: Option<(DateTime, Option<ObjectId>)> = None;
+    let mut previous = attempt.start_time;
+    for entry in &entries {
+        if let (Some(question_id), None) = (viewing, blurred_at) {
+            *time_on_question.entry(question_id).or_default() +=
+                entry.timestamp.timestamp_millis() - previous.timestamp_millis();
+        }
+        previous = entry.timestamp;
+
+        match entry.kind {
+            TimelineEntryKind::QuestionVisit => {
+                if let Some(question_id) = entry.question_id {
+                    *visits.entry(question_id).or_default() += 1;
+                    viewing = Some(question_id);
+                }
+            }
+            TimelineEntryKind::Blur => {
+                blurred_at.get_or_insert((entry.timestamp, viewing));
+            }
+            TimelineEntryKind::Focus => {
+                if let Some((start, question_id)) = blurred_at.take() {
+                    away.push(interval(start, entry.timestamp, question_id));
+                }
+            }
+            TimelineEntryKind::ExamExit => viewing = None,
+            TimelineEntryKind::Start
+            | TimelineEntryKind::Submission
+            | TimelineEntryKind::CaptionsOpened
+            | TimelineEntryKind::CopyPaste
+            | TimelineEntryKind::FullscreenExit
+            | TimelineEntryKind::NetworkLoss => {}
+        }
+    }
+    if let Some((start, question_id)) = blurred_at {
+        away.push(interval(start, end_time, question_id));
+    }
+
+    let mut submission_times: Vec<i64> = questions
+        .clone()
+        .filter_map(|(_, q)| q.submission_time)
+        .map(|t| t.timestamp_millis())
+        .collect();
+    submission_times.sort_unstable();
+
+    let questions = questions
+        .map(|(question_set_id, question)| {
+            let time_to_answer_ms = question.submission_time.map(|t| {
+                let t = t.timestamp_millis();
+                let previous = submission_times
+                    .iter()
+                    .rev()
+                    .find(|s| **s < t)
+                    .copied()
+                    .unwrap_or(start_time);
+                t - previous
+            });
+
+            QuestionTimeline {
+                question_id: question.id,
+                question_set_id,
+                visits: visits.get(&question.id).copied().unwrap_or(0),
+                time_on_question_ms: time_on_question.get(&question.id).copied().unwrap_or(0),
+                submission_time: question.submission_time,
+                time_to_answer_ms,
+            }
+        })
+        .collect();
+
+    AttemptTimeline {
+        attempt_id: attempt.id,
+        start_time: attempt.start_time,
+        end_time,
+        total_away_ms: away.iter().map(|a| a.duration_ms).sum(),
+        entries,
+        away,
+        questions,
+        skipped_events: events.skipped(),
+    }
+}
+
+fn interval(start: DateTime, end: DateTime, question_id: Option<ObjectId>) -> TimelineInterval {
+    TimelineInterval {
+        start,
+        end,
+        duration_ms: (end.timestamp_millis() - start.timestamp_millis()).max(0),
+        question_id,
+    }
+}